@@ -0,0 +1,1531 @@
+// src/app.rs
+//
+// The session: tabs of panes, the popup, the mode myux is in, and what
+// input does to them. `App::handle_event` only changes that state; what
+// has to happen outside of it (input for children, myux commands, a new
+// console size, a redraw, quitting) comes back as `Action`s for main.rs
+// to carry out.
+
+use crate::chooser::{Chooser, ChooserAction, Entry, Target};
+use crate::clients::{ClientId, Clients};
+use crate::command::{self, Key};
+use crate::history::{History, HistoryLimits};
+use crate::hooks::{Hook, Hooks};
+use crate::layout::{Direction, Layout, PaneId, Rect, Split};
+use crate::palette::{ColorDepth, Palette};
+use crate::pipe::{PanePipe, PipeSpec};
+use crate::prompt::{self, Prompt, PromptAction};
+use crate::pty::{NotStarted, Output, Pending, Program, Pty, SpawnOptions, Spawner};
+use crate::renderer::AmbiguousWidth;
+use crate::{ipc, rpc};
+use crate::script::Scripts;
+use crate::session::{LayoutSpec, PaneSpec, Session, TabSpec};
+use crate::terminal::VirtualTerminal;
+use crate::wake;
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What the per-pane threads tell the main loop.
+pub enum PaneEvent {
+    /// The pane has output waiting in its `Pending`.
+    Output(PaneId),
+    /// The pane's process is gone, with its exit code.
+    Exited(PaneId, u32),
+}
+
+/// A child process in its own ConPTY plus the virtual terminal it draws into.
+pub struct Pane {
+    pub id: PaneId,
+    pub pty: Box<dyn Pty>,
+    pub term: VirtualTerminal,
+    /// Takes part in synchronized input when the tab has it switched on.
+    pub sync: bool,
+    /// Command line the pane was started with.
+    pub command: String,
+    /// Directory the pane was started in (None = myux's own).
+    pub cwd: Option<String>,
+    /// pipe-pane output copy, shared with the reader thread.
+    pub pipe: Arc<Mutex<Option<PanePipe>>>,
+    /// Output the reader thread has read and the main loop not yet taken.
+    pub pending: Arc<Pending>,
+    /// Has a palette of its own instead of the session's.
+    pub own_palette: bool,
+    /// Printed something since it was last on screen.
+    pub activity: bool,
+}
+
+impl Drop for Pane {
+    fn drop(&mut self) {
+        // A reader waiting for room would wait forever.
+        self.pending.close();
+    }
+}
+
+impl Pane {
+    /// Resize the VT model and the ConPTY together.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        if self.term.size() != (cols, rows) {
+            self.term.resize(cols, rows);
+            self.pty.resize(cols, rows);
+        }
+    }
+
+    /// Start piping output to `spec`, or stop if `spec` is None (or if
+    /// `toggle` is set and a pipe is already open).
+    pub fn set_pipe(&self, spec: Option<PipeSpec>, toggle: bool) -> Result<String, String> {
+        // Opening and closing start and stop programs, so the reader thread
+        // isn't kept waiting for the lock meanwhile.
+        let piped = self.pipe.lock().unwrap().is_some();
+        if piped && (toggle || spec.is_none()) {
+            let closed = self.pipe.lock().unwrap().take();
+            drop(closed);
+            return Ok("pipe closed".into());
+        }
+        let Some(spec) = spec else {
+            return Ok("pane is not piped".into());
+        };
+        let opened = PanePipe::open(&spec, &*self.pty).map_err(|e| e.to_string())?;
+        let msg = format!("piping to {}", opened.label());
+        let replaced = self.pipe.lock().unwrap().replace(opened);
+        drop(replaced);
+        Ok(msg)
+    }
+
+    /// Label of the open pipe, for the status bar.
+    pub fn pipe_label(&self) -> Option<String> {
+        let pipe = self.pipe.lock().unwrap();
+        pipe.as_ref().map(|p| p.label().to_string())
+    }
+
+    /// Where the shell is now if it tells us, else where it started.
+    pub fn current_dir(&self) -> Option<String> {
+        self.term
+            .cwd()
+            .map(str::to_string)
+            .or_else(|| self.cwd.clone())
+    }
+}
+
+/// Tabs are numbered by position, but also get an id that doesn't change,
+/// for control mode.
+pub type TabId = usize;
+
+pub struct Tab {
+    pub id: TabId,
+    pub name: String,
+    pub panes: Vec<Pane>,
+    pub layout: Layout,
+    pub focus: PaneId,
+    /// The focused pane temporarily fills the whole tab. The others keep
+    /// running at their layout size but aren't drawn.
+    pub zoomed: bool,
+    /// Keystrokes go to every pane with `Pane::sync` set, not just the
+    /// focused one.
+    pub sync: bool,
+}
+
+impl Tab {
+    pub fn pane(&self, id: PaneId) -> Option<&Pane> {
+        self.panes.iter().find(|p| p.id == id)
+    }
+
+    pub fn pane_mut(&mut self, id: PaneId) -> Option<&mut Pane> {
+        self.panes.iter_mut().find(|p| p.id == id)
+    }
+
+    pub fn focused(&self) -> &Pane {
+        self.pane(self.focus).expect("focused pane exists")
+    }
+
+    pub fn focused_mut(&mut self) -> &mut Pane {
+        let id = self.focus;
+        self.pane_mut(id).expect("focused pane exists")
+    }
+
+    /// Panes that are currently on screen, with their rectangles.
+    pub fn visible(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        if self.zoomed {
+            vec![(self.focus, area)]
+        } else {
+            self.layout.rects(area)
+        }
+    }
+
+    /// Resize every pane to its place in `area`; when zoomed the focused
+    /// pane gets all of it.
+    pub fn relayout(&mut self, area: Rect) {
+        for (id, rect) in self.layout.rects(area) {
+            let rect = if self.zoomed && id == self.focus { area } else { rect };
+            if let Some(pane) = self.pane_mut(id) {
+                pane.resize(rect.cols, rect.rows);
+            }
+        }
+    }
+
+    pub fn toggle_zoom(&mut self, area: Rect) {
+        self.zoomed = !self.zoomed && self.panes.len() > 1;
+        self.relayout(area);
+    }
+
+    pub fn unzoom(&mut self, area: Rect) {
+        if self.zoomed {
+            self.zoomed = false;
+            self.relayout(area);
+        }
+    }
+
+    /// Panes keystrokes go to: the focused one, plus the other
+    /// synchronized ones while sync is on.
+    pub fn input_targets(&self) -> Vec<PaneId> {
+        self.panes
+            .iter()
+            .filter(|p| p.id == self.focus || (self.sync && p.sync))
+            .map(|p| p.id)
+            .collect()
+    }
+
+    /// Number of panes that receive input while sync is on.
+    pub fn synced_count(&self) -> usize {
+        self.panes
+            .iter()
+            .filter(|p| p.id == self.focus || p.sync)
+            .count()
+    }
+
+    /// Move focus to `id`. Zoom is dropped first so the layout comes back.
+    pub fn focus(&mut self, id: PaneId, area: Rect) {
+        if id != self.focus && self.pane(id).is_some() {
+            self.unzoom(area);
+            self.focus = id;
+        }
+    }
+}
+
+/// A floating pane over the current tab, outside of its layout.
+pub struct Popup {
+    pub pane: Pane,
+    /// Size in percent of the pane area, border included.
+    pub width: u16,
+    pub height: u16,
+    /// Stay open after the command exits, until a key is pressed.
+    pub keep: bool,
+    /// Exit code, once the command is gone.
+    pub exited: Option<u32>,
+}
+
+impl Popup {
+    /// Where the pane goes: `width` x `height` percent of `area`, centered,
+    /// less the border. At least 2 rows, since vt100 can't do with one.
+    pub fn place(area: Rect, width: u16, height: u16) -> Rect {
+        let size = |total: u16, percent: u16| {
+            ((total as u32 * percent as u32 / 100) as u16).min(total).saturating_sub(2)
+        };
+        let cols = size(area.cols, width).max(1);
+        let rows = size(area.rows, height).max(2);
+        Rect::new(
+            area.x + (area.cols.saturating_sub(cols) / 2),
+            area.y + (area.rows.saturating_sub(rows) / 2),
+            cols,
+            rows,
+        )
+    }
+
+    pub fn inner(&self, area: Rect) -> Rect {
+        Popup::place(area, self.width, self.height)
+    }
+
+    pub fn title(&self) -> String {
+        match self.exited {
+            Some(status) => format!("{} [exited {status}, press a key]", self.pane.command),
+            None => self.pane.command.clone(),
+        }
+    }
+}
+
+pub const DEFAULT_FRAME_RATE: u32 = 60;
+
+pub const SCROLL_STEP: u16 = 5;
+pub const SHELL: &str = "cmd.exe";
+
+pub enum Mode {
+    Normal,
+    /// Ctrl+B was pressed; the next key is a myux command.
+    Prefix,
+    Scrollback,
+    /// Typing a query to search back through the focused pane's history.
+    Search(String),
+    /// Typing a myux command at the `:` prompt.
+    Command(Prompt),
+    /// The choose-tree overlay is open.
+    Choose(Chooser),
+}
+
+pub struct App {
+    pub tabs: Vec<Tab>,
+    pub active: usize,
+    pub mode: Mode,
+    /// Query of the last scrollback search, repeated by `n`.
+    pub last_search: Option<String>,
+    /// Commands run by the key after the prefix.
+    pub bindings: HashMap<Key, Vec<String>>,
+    /// Lines entered at the command prompt, oldest first.
+    pub command_history: Vec<String>,
+    /// Result or error of the last command, shown in the status bar.
+    pub message: Option<String>,
+    pub hooks: Hooks,
+    /// Context of the event whose hooks are running, for `run-shell`.
+    pub hook_env: Vec<(String, String)>,
+    pub scripts: Scripts,
+    /// JSON-RPC clients waiting for events.
+    pub rpc: rpc::Subscribers,
+    /// Open popup; it gets the keyboard while it is there.
+    pub popup: Option<Popup>,
+    /// Applied to every pane's history file (`set scrollback`).
+    pub history_limits: HistoryLimits,
+    pub ambiguous: AmbiguousWidth,
+    /// Colors of the panes without a palette of their own.
+    pub palette: Palette,
+    /// Colors the host can show (`set color-depth`).
+    pub color_depth: ColorDepth,
+    /// Most frames drawn per second; output arriving faster than that is
+    /// drawn together (`set frame-rate`).
+    pub frame_rate: u32,
+    /// Variables set, or with None removed, in new panes' environment
+    /// (`set-environment`).
+    pub environment: Vec<(String, Option<String>)>,
+    /// Start new panes' shells as login shells (`set login-shell`).
+    pub login_shell: bool,
+    /// Console size in cells, status bar included. With clients of
+    /// different sizes attached, the one the size policy picked.
+    pub size: (u16, u16),
+    pub clients: Clients,
+    pub next_pane_id: PaneId,
+    pub next_tab_id: TabId,
+    /// Starts the processes of new panes, for `execute`.
+    pub spawner: Box<dyn Spawner>,
+    /// Handed to every new pane for its output.
+    tx: wake::Sender<PaneEvent>,
+    /// Processes to start and end (`Action::Spawn`, `Action::Kill`), left
+    /// to `execute`.
+    queued: Vec<Action>,
+    /// Panes let go of whose process is yet to be ended.
+    closing: Vec<Pane>,
+}
+
+impl App {
+    /// No tabs yet; the first one is up to the caller.
+    pub fn new(size: (u16, u16), spawner: Box<dyn Spawner>, tx: wake::Sender<PaneEvent>) -> Self {
+        App {
+            tabs: Vec::new(),
+            active: 0,
+            mode: Mode::Normal,
+            last_search: None,
+            bindings: command::default_bindings(),
+            command_history: Vec::new(),
+            message: None,
+            hooks: Hooks::default(),
+            hook_env: Vec::new(),
+            scripts: Scripts::new(),
+            rpc: rpc::Subscribers::default(),
+            popup: None,
+            history_limits: HistoryLimits::from_env(),
+            ambiguous: AmbiguousWidth::from_env(),
+            palette: Palette::default(),
+            color_depth: ColorDepth::from_env(),
+            frame_rate: DEFAULT_FRAME_RATE,
+            environment: Vec::new(),
+            login_shell: false,
+            size,
+            clients: Clients::new(),
+            next_pane_id: 0,
+            next_tab_id: 0,
+            spawner,
+            tx,
+            queued: Vec::new(),
+            closing: Vec::new(),
+        }
+    }
+
+    pub fn active_tab(&self) -> &Tab {
+        &self.tabs[self.active]
+    }
+
+    pub fn active_tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active]
+    }
+
+    /// The part of the console that belongs to the panes (all but the
+    /// status bar).
+    pub fn area(&self) -> Rect {
+        let (cols, rows) = self.size;
+        Rect::new(0, 0, cols, rows.saturating_sub(1).max(1))
+    }
+
+    pub fn pane(&self, id: PaneId) -> Option<&Pane> {
+        if let Some(popup) = self.popup.as_ref().filter(|p| p.pane.id == id) {
+            return Some(&popup.pane);
+        }
+        self.tabs.iter().find_map(|t| t.pane(id))
+    }
+
+    pub fn pane_mut(&mut self, id: PaneId) -> Option<&mut Pane> {
+        if let Some(popup) = self.popup.as_mut().filter(|p| p.pane.id == id) {
+            return Some(&mut popup.pane);
+        }
+        self.tabs.iter_mut().find_map(|t| t.pane_mut(id))
+    }
+
+    /// Feed pane `id` everything it has waiting, in one go. Returns what
+    /// it was.
+    pub fn pane_output(&mut self, id: PaneId) -> Vec<u8> {
+        let Some(pane) = self.pane_mut(id) else {
+            return Vec::new();
+        };
+        let bytes = pane.pending.take();
+        pane.term.feed_bytes(&bytes);
+        // ConPTY asks where the cursor is when it starts, and programs
+        // wait for answers to what they ask: they go straight back.
+        let replies = pane.term.take_replies();
+        if !replies.is_empty() {
+            pane.pty.write(&replies);
+        }
+        pane.activity = true;
+        let bell = pane.term.take_bell();
+        let title = pane.term.take_title_change().then(|| pane.term.title().to_string());
+        let env = pane_env(pane).to_vec();
+
+        self.rpc.output(id, &bytes);
+        if let Some(title) = title
+            && self.rpc.wants(rpc::Event::Title)
+        {
+            self.rpc.publish(rpc::Event::Title, serde_json::json!({ "pane": id, "title": title }));
+        }
+        if bell {
+            self.rpc.publish(rpc::Event::Bell, serde_json::json!({ "pane": id }));
+            self.fire(Hook::Bell, env);
+        }
+        bytes
+    }
+
+    /// The console is now `cols` x `rows`: re-fit every tab's panes (VT +
+    /// ConPTY). Resizing reflows and returns each pane to its live view.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.size = (cols, rows);
+        self.fire(
+            Hook::Resized,
+            vec![
+                ("MYUX_COLS".into(), cols.to_string()),
+                ("MYUX_ROWS".into(), rows.to_string()),
+            ],
+        );
+        let area = self.area();
+        for tab in &mut self.tabs {
+            tab.relayout(area);
+        }
+        if let Some(popup) = self.popup.as_mut() {
+            let rect = popup.inner(area);
+            popup.pane.resize(rect.cols, rect.rows);
+        }
+    }
+
+    /// Re-fit the session if its clients call for another size.
+    pub fn apply_size_policy(&mut self) {
+        if let Some(size) = self.clients.session_size()
+            && size != self.size
+        {
+            self.resize(size.0, size.1);
+        }
+    }
+
+    /// `MYUX_CLIENT` and the active tab's variables.
+    pub fn client_env(&self, id: ClientId) -> Vec<(String, String)> {
+        let mut env = vec![("MYUX_CLIENT".into(), id.to_string())];
+        env.extend(self.tab_env(self.active));
+        env
+    }
+
+    /// Run the detach hooks and queue the end of every child process,
+    /// before exiting.
+    pub fn shut_down(&mut self) {
+        self.fire(Hook::ClientDetached, Vec::new());
+        self.run_hooks();
+        self.close_popup();
+        for pane in self.tabs.iter().flat_map(|t| &t.panes) {
+            self.queued.push(Action::Kill(pane.id));
+        }
+    }
+
+    /// The processes to start and end queued since last time.
+    pub fn take_queued(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.queued)
+    }
+
+    /// Pane `id` if it was let go of and its process is yet to be ended.
+    pub fn take_closing(&mut self, id: PaneId) -> Option<Pane> {
+        let pos = self.closing.iter().position(|p| p.id == id)?;
+        Some(self.closing.remove(pos))
+    }
+
+    /// Let go of `pane` and queue the end of its process.
+    fn close(&mut self, pane: Pane) {
+        self.queued.push(Action::Kill(pane.id));
+        self.closing.push(pane);
+    }
+
+    /// A pane's process ended. A popup closes unless it was asked to stay;
+    /// panes in tabs keep showing their last output.
+    pub fn pane_exited(&mut self, id: PaneId, status: u32) {
+        let mut env = match self.tabs.iter().position(|t| t.pane(id).is_some()) {
+            Some(tab) => self.tab_env(tab),
+            None => Vec::new(),
+        };
+        // The tab's focused pane isn't necessarily the one that exited,
+        // and a closed tab's panes are gone already.
+        env.retain(|(k, _)| !k.starts_with("MYUX_PANE"));
+        let pane = self.popup.as_ref().map(|p| &p.pane).filter(|p| p.id == id);
+        match pane.or_else(|| self.tabs.iter().find_map(|t| t.pane(id))) {
+            Some(pane) => env.extend(pane_env(pane)),
+            None => env.push(("MYUX_PANE".into(), id.to_string())),
+        }
+        env.push(("MYUX_EXIT_STATUS".into(), status.to_string()));
+        self.fire(Hook::PaneExited, env);
+        self.rpc.pane_gone(id);
+        self.rpc.publish(rpc::Event::Exit, serde_json::json!({ "pane": id, "status": status }));
+
+        if let Some(popup) = self.popup.as_mut().filter(|p| p.pane.id == id) {
+            if popup.keep {
+                popup.exited = Some(status);
+            } else {
+                self.popup = None;
+            }
+        }
+    }
+
+    /// Close tab `index`, ending its processes. The last tab stays.
+    pub fn kill_tab(&mut self, index: usize) -> Result<(), String> {
+        if self.tabs.len() == 1 {
+            return Err("can't close the last tab (F10 quits)".into());
+        }
+        if index >= self.tabs.len() {
+            return Err(format!("no tab {}", index + 1));
+        }
+        let tab = self.remove_tab(index);
+        for pane in tab.panes {
+            self.close(pane);
+        }
+        Ok(())
+    }
+
+    /// Take tab `index` out of the session.
+    fn remove_tab(&mut self, index: usize) -> Tab {
+        let env = self.tab_env(index);
+        let tab = self.tabs.remove(index);
+        if self.active > index || (self.active == self.tabs.len() && self.active > 0) {
+            self.active -= 1;
+        }
+        self.fire(Hook::TabClosed, env);
+        tab
+    }
+
+    /// `MYUX_TAB*` and `MYUX_PANE*` for tab `index` and its focused pane.
+    pub fn tab_env(&self, index: usize) -> Vec<(String, String)> {
+        let tab = &self.tabs[index];
+        let mut env = vec![
+            ("MYUX_TAB".into(), (index + 1).to_string()),
+            ("MYUX_TAB_NAME".into(), tab.name.clone()),
+        ];
+        env.extend(pane_env(tab.focused()));
+        env
+    }
+
+    pub fn fire(&mut self, hook: Hook, mut env: Vec<(String, String)>) {
+        env.insert(0, ("MYUX_EVENT".into(), hook.name().into()));
+        self.hooks.fire(hook, env);
+    }
+
+    /// Run the hooks of every queued event. Problems end up in the status
+    /// bar.
+    pub fn run_hooks(&mut self) {
+        for fired in self.hooks.take() {
+            self.hook_env = fired.env;
+            for words in self.hooks.commands(fired.hook).to_vec() {
+                if let Err(err) = command::run(self, &words, None) {
+                    self.message = Some(format!("{} hook: {err}", fired.hook.name()));
+                }
+            }
+        }
+        self.hook_env.clear();
+        // Events caused by the hooks themselves are dropped, so a hook
+        // can't set itself off forever.
+        self.hooks.take();
+    }
+
+    /// Run `cmdline` in a popup of `width` x `height` percent, replacing
+    /// any popup already open.
+    pub fn open_popup(
+        &mut self,
+        cmdline: &str,
+        cwd: Option<String>,
+        width: u16,
+        height: u16,
+        keep: bool,
+    ) {
+        self.close_popup();
+        let cwd = cwd.or_else(|| self.active_tab().focused().current_dir());
+        let id = self.next_pane_id;
+        let rect = Popup::place(self.area(), width, height);
+        let pane = self.new_pane(id, cmdline, cwd.as_deref(), rect.cols, rect.rows);
+        self.next_pane_id += 1;
+        self.popup = Some(Popup {
+            pane,
+            width,
+            height,
+            keep,
+            exited: None,
+        });
+        self.queued.push(Action::Spawn { panes: vec![id] });
+    }
+
+    pub fn close_popup(&mut self) {
+        if let Some(popup) = self.popup.take()
+            && popup.exited.is_none()
+        {
+            self.close(popup.pane);
+        }
+    }
+
+    /// Open a tab running `cmdline` and switch to it.
+    pub fn new_tab(&mut self, cmdline: &str, cwd: Option<&str>) {
+        let area = self.area();
+        let id = self.next_pane_id;
+        let pane = self.new_pane(id, cmdline, cwd, area.cols, area.rows);
+
+        self.next_pane_id += 1;
+        self.next_tab_id += 1;
+        self.tabs.push(Tab {
+            id: self.next_tab_id - 1,
+            name: default_tab_name(cmdline),
+            panes: vec![pane],
+            layout: Layout::Pane(id),
+            focus: id,
+            zoomed: false,
+            sync: false,
+        });
+        self.active = self.tabs.len() - 1;
+        self.fire(Hook::TabCreated, self.tab_env(self.active));
+        self.queued.push(Action::Spawn { panes: vec![id] });
+    }
+
+    /// Split the focused pane and run `cmdline` in the new half, by default
+    /// in the focused pane's directory.
+    pub fn split_focused(&mut self, dir: Split, cmdline: &str, cwd: Option<String>) -> Result<(), String> {
+        let target = self.active_tab().focus;
+        self.split_pane(self.active, target, dir, cmdline, cwd).map(|_| ())
+    }
+
+    /// Split pane `target` of tab `index` and focus the new pane, which is
+    /// returned.
+    pub fn split_pane(
+        &mut self,
+        index: usize,
+        target: PaneId,
+        dir: Split,
+        cmdline: &str,
+        cwd: Option<String>,
+    ) -> Result<PaneId, String> {
+        let area = self.area();
+        let id = self.next_pane_id;
+        self.tabs[index].unzoom(area);
+
+        let mut layout = self.tabs[index].layout.clone();
+        if !layout.split(area, target, dir, id) {
+            return Err("no room to split this pane".into());
+        }
+        let Some((_, rect)) = layout.rects(area).into_iter().find(|(p, _)| *p == id) else {
+            return Err("no room to split this pane".into());
+        };
+        let cwd = cwd.or_else(|| self.tabs[index].pane(target).and_then(Pane::current_dir));
+        let pane = self.new_pane(id, cmdline, cwd.as_deref(), rect.cols, rect.rows);
+
+        self.next_pane_id += 1;
+        let tab = &mut self.tabs[index];
+        tab.layout = layout;
+        tab.panes.push(pane);
+        tab.focus = id;
+        tab.relayout(area);
+        self.queued.push(Action::Spawn { panes: vec![id] });
+        Ok(id)
+    }
+
+    /// A pane `id` of `cols` x `rows` for `cmdline`, whose process is
+    /// still to be started.
+    fn new_pane(&self, id: PaneId, cmdline: &str, cwd: Option<&str>, cols: u16, rows: u16) -> Pane {
+        let mut term = VirtualTerminal::new(
+            cols,
+            rows,
+            History::new(&format!("pane-{id}"), self.history_limits),
+        );
+        term.set_palette(self.palette.clone());
+        Pane {
+            id,
+            pty: Box::new(NotStarted::default()),
+            term,
+            sync: true,
+            command: cmdline.to_string(),
+            cwd: cwd.map(str::to_string),
+            pipe: Arc::default(),
+            pending: Arc::default(),
+            own_palette: false,
+            activity: false,
+        }
+    }
+
+    /// What starts `pane`'s process.
+    pub fn spawn_options(&self, pane: &Pane) -> SpawnOptions {
+        SpawnOptions {
+            program: Program::Cmdline(pane.command.clone()),
+            cwd: pane.cwd.clone(),
+            env: self.child_env(pane.id),
+            login: self.login_shell,
+            ..SpawnOptions::default()
+        }
+    }
+
+    /// Where `pane`'s process sends its output and exit.
+    pub fn output(&self, pane: &Pane) -> Output {
+        let (pipe, pending) = (Arc::clone(&pane.pipe), Arc::clone(&pane.pending));
+        Output::new(pane.id, self.tx.clone(), pipe, pending)
+    }
+
+    /// What a new pane's process finds in its environment besides ours:
+    /// where its myux listens, which pane it is and what terminal it talks
+    /// to, then the `set-environment` changes.
+    fn child_env(&self, id: PaneId) -> Vec<(String, Option<String>)> {
+        let truecolor = self.color_depth == ColorDepth::TrueColor;
+        let mut env = vec![
+            ("MYUX".into(), Some(format!("{},{}", ipc::pipe_name(), std::process::id()))),
+            ("MYUX_PANE".into(), Some(id.to_string())),
+            ("TERM".into(), Some("xterm-256color".into())),
+            ("COLORTERM".into(), truecolor.then(|| "truecolor".into())),
+        ];
+        env.extend(self.environment.iter().cloned());
+        env
+    }
+
+    /// Set `name` in new panes' environment, or remove it with None.
+    pub fn set_environment(&mut self, name: &str, value: Option<String>) {
+        self.environment.retain(|(k, _)| k != name);
+        self.environment.push((name.to_string(), value));
+    }
+
+    /// Switch the session's palette, for every pane that doesn't have its
+    /// own.
+    pub fn set_palette(&mut self, palette: Palette) {
+        let popup = self.popup.iter_mut().map(|p| &mut p.pane);
+        for pane in self.tabs.iter_mut().flat_map(|t| t.panes.iter_mut()).chain(popup) {
+            if !pane.own_palette {
+                pane.term.set_palette(palette.clone());
+            }
+        }
+        self.palette = palette;
+    }
+
+    /// Give pane `id` a palette of its own, or with None the session's
+    /// again.
+    pub fn set_pane_palette(&mut self, id: PaneId, palette: Option<Palette>) {
+        let session = self.palette.clone();
+        if let Some(pane) = self.pane_mut(id) {
+            pane.own_palette = palette.is_some();
+            pane.term.set_palette(palette.unwrap_or(session));
+        }
+    }
+
+    /// Close pane `id`, ending its process. Closing a tab's only pane
+    /// closes the tab.
+    pub fn kill_pane(&mut self, id: PaneId) -> Result<(), String> {
+        let index = self
+            .tabs
+            .iter()
+            .position(|t| t.pane(id).is_some())
+            .ok_or_else(|| format!("no pane {id}"))?;
+        if self.tabs[index].panes.len() == 1 {
+            return self.kill_tab(index);
+        }
+        let pane = self.remove_pane(index, id);
+        self.close(pane);
+        Ok(())
+    }
+
+    /// Take pane `id` out of tab `index`, which has others.
+    fn remove_pane(&mut self, index: usize, id: PaneId) -> Pane {
+        let area = self.area();
+        let tab = &mut self.tabs[index];
+        tab.unzoom(area);
+        tab.layout.remove(id);
+        let pos = tab.panes.iter().position(|p| p.id == id).unwrap();
+        let pane = tab.panes.remove(pos);
+        if tab.focus == id {
+            tab.focus = tab.layout.panes()[0];
+        }
+        tab.relayout(area);
+        pane
+    }
+
+    /// Take away panes whose processes couldn't all be started, and the
+    /// tabs that leaves empty. The processes that did start have been
+    /// ended already.
+    pub fn discard(&mut self, panes: &[PaneId]) {
+        let popup = self.popup.as_ref().map(|p| p.pane.id);
+        if popup.is_some_and(|id| panes.contains(&id)) {
+            self.popup = None;
+        }
+        for &id in panes {
+            let Some(index) = self.tabs.iter().position(|t| t.pane(id).is_some()) else {
+                continue;
+            };
+            if self.tabs[index].panes.len() == 1 {
+                self.remove_tab(index);
+            } else {
+                self.remove_pane(index, id);
+            }
+        }
+    }
+
+    pub fn select_tab(&mut self, index: usize) {
+        if index < self.tabs.len() {
+            self.active = index;
+        }
+    }
+
+    pub fn set_history_limits(&mut self, limits: HistoryLimits) {
+        self.history_limits = limits;
+        for pane in self.tabs.iter_mut().flat_map(|t| &mut t.panes) {
+            pane.term.set_history_limits(limits);
+        }
+    }
+
+    /// Run a command for a key binding; whatever it has to say ends up in
+    /// the status bar. Relative paths are taken from the focused pane's
+    /// directory.
+    pub fn run_command(&mut self, words: &[String]) {
+        let cwd = self.active_tab().focused().current_dir().map(PathBuf::from);
+        let result = command::run(self, words, cwd.as_deref());
+        self.show_result(result);
+    }
+
+    /// `run_command` for a line typed at the prompt.
+    pub fn run_command_line(&mut self, line: &str) {
+        let cwd = self.active_tab().focused().current_dir().map(PathBuf::from);
+        let result = command::run_line(self, line, cwd.as_deref());
+        self.show_result(result);
+    }
+
+    fn show_result(&mut self, result: Result<String, String>) {
+        self.message = match result {
+            Ok(msg) if msg.is_empty() => None,
+            Ok(msg) => Some(msg),
+            Err(err) => Some(err),
+        };
+    }
+
+    pub fn remember_command(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.command_history.last().map(String::as_str) != Some(line) {
+            self.command_history.push(line.to_string());
+        }
+        if self.command_history.len() > prompt::HISTORY_LEN {
+            self.command_history.remove(0);
+        }
+    }
+
+    /// The tree shown by choose-tree: this session, its tabs, their panes.
+    pub fn chooser_entries(&self) -> Vec<Entry> {
+        let mut entries = vec![Entry {
+            target: Target::Session,
+            depth: 0,
+            label: format!("myux: {} tab(s)", self.tabs.len()),
+        }];
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let mut flags = String::new();
+            if i == self.active {
+                flags.push('*');
+            }
+            if tab.panes.iter().any(|p| p.activity) {
+                flags.push('#');
+            }
+            if tab.zoomed {
+                flags.push('Z');
+            }
+            if tab.sync {
+                flags.push('S');
+            }
+            entries.push(Entry {
+                target: Target::Tab(i),
+                depth: 1,
+                label: format!("{}: {} ({} pane(s)) {flags}", i + 1, tab.name, tab.panes.len()),
+            });
+
+            for id in tab.layout.panes() {
+                let Some(pane) = tab.pane(id) else {
+                    continue;
+                };
+                let (cols, rows) = pane.term.size();
+                let mut flags = String::new();
+                if id == tab.focus {
+                    flags.push('*');
+                }
+                if pane.activity {
+                    flags.push('#');
+                }
+                entries.push(Entry {
+                    target: Target::Pane(i, id),
+                    depth: 2,
+                    label: format!("%{id}: {} [{cols}x{rows}] {flags}", pane.command),
+                });
+            }
+        }
+        entries
+    }
+
+    /// Pane shown in the chooser's preview for `target`; a tab or the
+    /// session show their focused pane.
+    pub fn preview_pane(&self, target: Target) -> Option<&Pane> {
+        match target {
+            Target::Session => Some(self.active_tab().focused()),
+            Target::Tab(i) => self.tabs.get(i).map(Tab::focused),
+            Target::Pane(i, id) => self.tabs.get(i)?.pane(id),
+        }
+    }
+
+    /// Switch to what was picked in the chooser.
+    pub fn choose(&mut self, target: Target) {
+        let area = self.area();
+        match target {
+            Target::Session => {}
+            Target::Tab(i) => self.select_tab(i),
+            Target::Pane(i, id) => {
+                self.select_tab(i);
+                if let Some(tab) = self.tabs.get_mut(i) {
+                    tab.focus(id, area);
+                }
+            }
+        }
+    }
+
+    /// Focus the next pane of the active tab in layout order.
+    pub fn focus_next(&mut self) {
+        let area = self.area();
+        let tab = self.active_tab_mut();
+        let order = tab.layout.panes();
+        let pos = order.iter().position(|&id| id == tab.focus).unwrap_or(0);
+        let next = order[(pos + 1) % order.len()];
+        tab.focus(next, area);
+    }
+
+    pub fn focus_direction(&mut self, dir: Direction) {
+        let area = self.area();
+        let tab = self.active_tab_mut();
+        if let Some(id) = tab.layout.neighbour(area, tab.focus, dir) {
+            tab.focus(id, area);
+        }
+    }
+
+    /// Describe every tab for `save-session`. With `scrollback`, each pane's
+    /// history goes along (which also returns it to the live view).
+    pub fn session(&mut self, scrollback: bool) -> Session {
+        let tabs = self
+            .tabs
+            .iter_mut()
+            .map(|tab| {
+                let order = tab.layout.panes();
+                let panes = &mut tab.panes;
+                let layout = LayoutSpec::from_layout(&tab.layout, &mut |id| {
+                    let pane = panes
+                        .iter_mut()
+                        .find(|p| p.id == id)
+                        .expect("pane in layout exists");
+                    let history = scrollback.then(|| pane.term.history_formatted());
+                    PaneSpec {
+                        command: pane.command.clone(),
+                        cwd: pane.current_dir(),
+                        scrollback: history.map(|h| String::from_utf8_lossy(&h).into_owned()),
+                    }
+                });
+                TabSpec {
+                    name: tab.name.clone(),
+                    focus: order.iter().position(|&id| id == tab.focus).unwrap_or(0),
+                    layout,
+                }
+            })
+            .collect();
+
+        if scrollback {
+            self.mode = Mode::Normal;
+        }
+        Session {
+            active: self.active,
+            tabs,
+        }
+    }
+
+    /// Recreate the tabs of a saved session after the existing ones and
+    /// switch to the one that was active. Their processes start together:
+    /// if a pane can't be started, none of the session is kept.
+    pub fn open_session(&mut self, session: &Session) {
+        let area = self.area();
+        let mut tabs: Vec<Tab> = Vec::new();
+
+        for spec in &session.tabs {
+            let mut specs = Vec::new();
+            let layout = spec.layout.to_layout(&mut self.next_pane_id, &mut specs);
+
+            // `rects` and `to_layout` both go in layout order.
+            let mut panes = Vec::new();
+            for ((id, rect), (_, pane_spec)) in layout.rects(area).into_iter().zip(&specs) {
+                let command = &pane_spec.command;
+                let cwd = pane_spec.cwd.as_deref();
+                let mut pane = self.new_pane(id, command, cwd, rect.cols, rect.rows);
+                if let Some(history) = &pane_spec.scrollback {
+                    pane.term.replay_history(history.as_bytes());
+                }
+                panes.push(pane);
+            }
+
+            let order = layout.panes();
+            tabs.push(Tab {
+                id: self.next_tab_id + tabs.len(),
+                name: spec.name.clone(),
+                panes,
+                focus: order.get(spec.focus).copied().unwrap_or(order[0]),
+                layout,
+                zoomed: false,
+                sync: false,
+            });
+        }
+
+        let first = self.tabs.len();
+        let panes = tabs.iter().flat_map(|t| &t.panes).map(|p| p.id).collect();
+        self.queued.push(Action::Spawn { panes });
+        self.next_tab_id += tabs.len();
+        self.tabs.extend(tabs);
+        for index in first..self.tabs.len() {
+            self.fire(Hook::TabCreated, self.tab_env(index));
+        }
+        if self.tabs.len() > first {
+            self.active = (first + session.active).min(self.tabs.len() - 1);
+        }
+    }
+}
+
+/// Something `App::handle_event` wants done outside of the app. Commands,
+/// from a key binding or anywhere else, leave the processes they start and
+/// end as actions too (`App::take_queued`).
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Input for a pane's child.
+    Write(PaneId, Vec<u8>),
+    /// Start the processes of panes already laid out. If one can't be
+    /// started, none of them are kept.
+    Spawn {
+        panes: Vec<PaneId>,
+    },
+    /// End a pane's process: one the app let go of, or any when myux
+    /// quits.
+    Kill(PaneId),
+    /// A myux command, from a key binding.
+    Command(Vec<String>),
+    /// A command line typed at the prompt.
+    CommandLine(String),
+    /// The client's console is now this size.
+    Resize(u16, u16),
+    Redraw,
+    Quit,
+}
+
+impl App {
+    /// What an event from a client's console does: the mode, scrolling,
+    /// focus and so on change right here, everything else is returned.
+    pub fn handle_event(&mut self, event: Event) -> Vec<Action> {
+        let mut actions = Vec::new();
+        match event {
+            // Global: F10 quits.
+            // Releases (where the console reports them) do nothing.
+            Event::Key(key) if key.kind == KeyEventKind::Release => return actions,
+            Event::Key(key) if key.code == KeyCode::F(10) => return vec![Action::Quit],
+            Event::Key(key) => self.key(key, &mut actions),
+            Event::Mouse(mouse) => self.mouse(mouse),
+            Event::Paste(text) => self.paste(&text, &mut actions),
+            Event::Resize(cols, rows) => {
+                self.mode = Mode::Normal;
+                actions.push(Action::Resize(cols, rows));
+            }
+            _ => return actions,
+        }
+        actions.push(Action::Redraw);
+        actions
+    }
+
+    /// A key: myux's own keys by mode, else input for the focused pane (or
+    /// the popup).
+    fn key(&mut self, key: KeyEvent, actions: &mut Vec<Action>) {
+        let KeyEvent { code, modifiers, .. } = key;
+
+        self.message = None;
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+
+        // A popup whose command has exited goes away on any key.
+        if self.popup.as_ref().is_some_and(|p| p.exited.is_some()) {
+            self.popup = None;
+            return;
+        }
+
+        // -------- Scrollback / prefix mode handling --------
+        match self.mode {
+            Mode::Normal => {
+                match code {
+                    // Enter scrollback mode on PageUp
+                    KeyCode::PageUp if self.popup.is_none() => {
+                        self.mode = Mode::Scrollback;
+                        self.active_tab_mut().focused_mut().term.scroll_up(5);
+                        return; // don't send PageUp to the child
+                    }
+                    // Ctrl+B: next key is a myux command
+                    KeyCode::Char('b') if ctrl => {
+                        self.mode = Mode::Prefix;
+                        return;
+                    }
+                    _ => { /* fall through to normal key handling */ }
+                }
+            }
+            Mode::Prefix => {
+                self.mode = Mode::Normal;
+                // Unbound keys just leave prefix mode.
+                if let Some(words) = self.bindings.get(&Key { code, ctrl }).cloned() {
+                    actions.push(Action::Command(words));
+                }
+                return;
+            }
+            Mode::Scrollback => {
+                match code {
+                    KeyCode::PageUp => {
+                        self.active_tab_mut().focused_mut().term.scroll_up(5);
+                        return;
+                    }
+                    KeyCode::PageDown => {
+                        self.active_tab_mut().focused_mut().term.scroll_down(5);
+                        if self.active_tab().focused().term.is_at_bottom() {
+                            self.mode = Mode::Normal;
+                        }
+                        return;
+                    }
+                    KeyCode::Esc => {
+                        self.active_tab_mut().focused_mut().term.reset_scrollback();
+                        self.mode = Mode::Normal;
+                        return;
+                    }
+                    // /: search back, n: repeat the last search
+                    KeyCode::Char('/') => {
+                        self.mode = Mode::Search(String::new());
+                        return;
+                    }
+                    KeyCode::Char('n') => {
+                        if let Some(query) = self.last_search.clone() {
+                            self.active_tab_mut().focused_mut().term.search_back(&query);
+                        }
+                        return;
+                    }
+                    _ => {
+                        // while in scrollback, ignore all other keys
+                        return;
+                    }
+                }
+            }
+            Mode::Command(ref mut prompt) => {
+                match prompt.key(code, &self.command_history) {
+                    PromptAction::None => {}
+                    PromptAction::Cancel => self.mode = Mode::Normal,
+                    PromptAction::Submit(line) => {
+                        self.mode = Mode::Normal;
+                        self.remember_command(&line);
+                        actions.push(Action::CommandLine(line));
+                    }
+                }
+                return;
+            }
+            Mode::Choose(_) => {
+                let entries = self.chooser_entries();
+                let Mode::Choose(chooser) = &mut self.mode else {
+                    unreachable!();
+                };
+                match chooser.key(code, &entries) {
+                    ChooserAction::None => {}
+                    ChooserAction::Cancel => self.mode = Mode::Normal,
+                    ChooserAction::Choose(target) => {
+                        self.mode = Mode::Normal;
+                        self.choose(target);
+                    }
+                }
+                return;
+            }
+            Mode::Search(_) => {
+                match code {
+                    KeyCode::Enter => {
+                        if let Mode::Search(query) =
+                            std::mem::replace(&mut self.mode, Mode::Scrollback)
+                        {
+                            self.active_tab_mut().focused_mut().term.search_back(&query);
+                            self.last_search = Some(query);
+                        }
+                    }
+                    KeyCode::Esc => self.mode = Mode::Scrollback,
+                    KeyCode::Backspace => {
+                        if let Mode::Search(query) = &mut self.mode {
+                            query.pop();
+                        }
+                    }
+                    KeyCode::Char(c) => {
+                        if let Mode::Search(query) = &mut self.mode {
+                            query.push(c);
+                        }
+                    }
+                    _ => {}
+                }
+                return;
+            }
+        }
+
+        // -------- Normal key → ConPTY --------
+        // (every synchronized pane gets the same bytes, unless
+        // a popup has the keyboard)
+        let targets = match &self.popup {
+            Some(popup) => vec![popup.pane.id],
+            None => self.active_tab().input_targets(),
+        };
+        let bytes = key_bytes(code);
+        if !bytes.is_empty() {
+            for id in targets {
+                actions.push(Action::Write(id, bytes.clone()));
+            }
+        }
+    }
+
+    /// Pasted text goes where typed keys would, bracketed for each pane
+    /// that asked for it. Outside normal mode it's dropped.
+    fn paste(&mut self, text: &str, actions: &mut Vec<Action>) {
+        let exited = self.popup.as_ref().is_some_and(|p| p.exited.is_some());
+        if !matches!(self.mode, Mode::Normal) || exited {
+            return;
+        }
+        self.message = None;
+        let targets = match &self.popup {
+            Some(popup) => vec![popup.pane.id],
+            None => self.active_tab().input_targets(),
+        };
+        for id in targets {
+            let Some(pane) = self.pane(id) else { continue };
+            let bytes = if pane.term.bracketed_paste() {
+                format!("\x1b[200~{text}\x1b[201~").into_bytes()
+            } else {
+                text.as_bytes().to_vec()
+            };
+            actions.push(Action::Write(id, bytes));
+        }
+    }
+
+    fn mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => {
+                match self.mode {
+                    Mode::Command(_) | Mode::Choose(_) => {}
+                    Mode::Normal | Mode::Prefix => {
+                        // Same as first PageUp: enter scrollback mode.
+                        self.mode = Mode::Scrollback;
+                        self.active_tab_mut().focused_mut().term.scroll_up(SCROLL_STEP);
+                    }
+                    Mode::Scrollback | Mode::Search(_) => {
+                        self.active_tab_mut().focused_mut().term.scroll_up(SCROLL_STEP);
+                    }
+                }
+            }
+            MouseEventKind::ScrollDown => {
+                match self.mode {
+                    Mode::Normal | Mode::Prefix | Mode::Command(_) | Mode::Choose(_) => {
+                        // In normal mode at bottom: you could choose to ignore,
+                        // or later, pass wheel to child. For now: ignore.
+                    }
+                    Mode::Scrollback | Mode::Search(_) => {
+                        self.active_tab_mut().focused_mut().term.scroll_down(SCROLL_STEP);
+                        if self.active_tab().focused().term.is_at_bottom() {
+                            self.mode = Mode::Normal;
+                        }
+                    }
+                }
+            }
+            _ => {
+                // Ignore other mouse events for now (clicks, moves).
+            }
+        }
+    }
+}
+
+pub fn pane_env(pane: &Pane) -> [(String, String); 2] {
+    [
+        ("MYUX_PANE".into(), pane.id.to_string()),
+        ("MYUX_PANE_COMMAND".into(), pane.command.clone()),
+    ]
+}
+
+/// Tab name for a command line: the program without path or extension.
+fn default_tab_name(cmdline: &str) -> String {
+    let program = cmdline.split_whitespace().next().unwrap_or(cmdline);
+    let program = program.trim_matches('"');
+    Path::new(program)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| program.to_string())
+}
+
+/// What a key sends to the child.
+pub fn key_bytes(code: KeyCode) -> Vec<u8> {
+    match code {
+        KeyCode::Enter => b"\r".to_vec(),
+        KeyCode::Backspace => vec![0x08],
+        KeyCode::Tab => b"\t".to_vec(),
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Esc => b"\x1b".to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+
+    fn press(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn ctrl(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    fn wheel(kind: MouseEventKind) -> Event {
+        Event::Mouse(MouseEvent {
+            kind,
+            column: 0,
+            row: 0,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    /// A session whose only pane has more history than fits on screen.
+    fn with_history() -> Harness {
+        let mut h = Harness::new(80, 24);
+        for i in 0..100 {
+            h.print(0, &format!("line {i}\r\n"));
+        }
+        h
+    }
+
+    fn offset(app: &App) -> usize {
+        app.active_tab().focused().term.scroll_offset()
+    }
+
+    #[test]
+    fn keys_become_input_for_the_focused_pane() {
+        let mut h = Harness::new(80, 24);
+        let app = &mut h.app;
+        assert_eq!(
+            app.handle_event(press(KeyCode::Char('a'))),
+            [Action::Write(0, b"a".to_vec()), Action::Redraw]
+        );
+        assert_eq!(
+            app.handle_event(press(KeyCode::Up)),
+            [Action::Write(0, b"\x1b[A".to_vec()), Action::Redraw]
+        );
+        assert_eq!(
+            app.handle_event(press(KeyCode::Enter)),
+            [Action::Write(0, b"\r".to_vec()), Action::Redraw]
+        );
+    }
+
+    #[test]
+    fn key_releases_are_not_input() {
+        let mut h = Harness::new(80, 24);
+        for code in [KeyCode::Char('a'), KeyCode::F(10)] {
+            let mut release = KeyEvent::new(code, KeyModifiers::NONE);
+            release.kind = KeyEventKind::Release;
+            assert_eq!(h.app.handle_event(Event::Key(release)), []);
+        }
+    }
+
+    #[test]
+    fn keys_without_bytes_send_nothing() {
+        let mut h = Harness::new(80, 24);
+        assert_eq!(h.app.handle_event(press(KeyCode::F(5))), [Action::Redraw]);
+    }
+
+    #[test]
+    fn f10_quits() {
+        let mut h = Harness::new(80, 24);
+        assert_eq!(h.app.handle_event(press(KeyCode::F(10))), [Action::Quit]);
+    }
+
+    #[test]
+    fn prefix_runs_the_bound_command() {
+        let mut h = Harness::new(80, 24);
+        let app = &mut h.app;
+        assert_eq!(app.handle_event(ctrl('b')), [Action::Redraw]);
+        assert!(matches!(app.mode, Mode::Prefix));
+
+        let split = vec!["split".to_string(), "-h".to_string()];
+        assert_eq!(
+            app.handle_event(press(KeyCode::Char('%'))),
+            [Action::Command(split), Action::Redraw]
+        );
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn bound_commands_leave_processes_to_the_caller() {
+        let mut h = Harness::new(80, 24);
+        let app = &mut h.app;
+        app.run_command(&["split".to_string()]);
+        assert_eq!(app.take_queued(), [Action::Spawn { panes: vec![1] }]);
+        assert_eq!(app.active_tab().focus, 1);
+
+        app.run_command(&["kill-pane".to_string()]);
+        assert_eq!(app.take_queued(), [Action::Kill(1)]);
+        assert_eq!(app.active_tab().panes.len(), 1);
+        assert!(app.take_closing(1).is_some());
+    }
+
+    #[test]
+    fn unbound_prefix_keys_do_nothing() {
+        let mut h = Harness::new(80, 24);
+        h.app.handle_event(ctrl('b'));
+        assert_eq!(h.app.handle_event(press(KeyCode::F(5))), [Action::Redraw]);
+        assert!(matches!(h.app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn synchronized_panes_all_get_the_key() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.app.active_tab_mut().sync = true;
+        assert_eq!(
+            h.app.handle_event(press(KeyCode::Char('x'))),
+            [
+                Action::Write(0, b"x".to_vec()),
+                Action::Write(1, b"x".to_vec()),
+                Action::Redraw,
+            ]
+        );
+    }
+
+    #[test]
+    fn page_up_enters_scrollback_without_input() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        assert_eq!(app.handle_event(press(KeyCode::PageUp)), [Action::Redraw]);
+        assert!(matches!(app.mode, Mode::Scrollback));
+        assert_eq!(offset(app), 5);
+    }
+
+    #[test]
+    fn scrollback_swallows_other_keys() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        app.handle_event(press(KeyCode::PageUp));
+        assert_eq!(app.handle_event(press(KeyCode::Char('q'))), [Action::Redraw]);
+        assert!(matches!(app.mode, Mode::Scrollback));
+    }
+
+    #[test]
+    fn page_down_to_the_bottom_leaves_scrollback() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        app.handle_event(press(KeyCode::PageUp));
+        app.handle_event(press(KeyCode::PageUp));
+        app.handle_event(press(KeyCode::PageDown));
+        assert!(matches!(app.mode, Mode::Scrollback));
+        assert_eq!(offset(app), 5);
+
+        app.handle_event(press(KeyCode::PageDown));
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(offset(app), 0);
+    }
+
+    #[test]
+    fn esc_leaves_scrollback_at_once() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        app.handle_event(press(KeyCode::PageUp));
+        app.handle_event(press(KeyCode::PageUp));
+        app.handle_event(press(KeyCode::Esc));
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(offset(app), 0);
+    }
+
+    #[test]
+    fn search_scrolls_to_the_match_and_n_repeats_it() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        app.handle_event(press(KeyCode::PageUp));
+        app.handle_event(press(KeyCode::Char('/')));
+        for c in "line 5".chars() {
+            app.handle_event(press(KeyCode::Char(c)));
+        }
+        assert!(matches!(&app.mode, Mode::Search(q) if q == "line 5"));
+        app.handle_event(press(KeyCode::Enter));
+        assert!(matches!(app.mode, Mode::Scrollback));
+        assert_eq!(app.last_search.as_deref(), Some("line 5"));
+
+        // "line 59" is the closest match above the view.
+        let first = offset(app);
+        assert!(first > 5);
+        app.handle_event(press(KeyCode::Char('n')));
+        assert!(offset(app) > first);
+    }
+
+    #[test]
+    fn wheel_scrolls_and_leaves_scrollback_at_the_bottom() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        assert_eq!(app.handle_event(wheel(MouseEventKind::ScrollUp)), [Action::Redraw]);
+        assert!(matches!(app.mode, Mode::Scrollback));
+        assert_eq!(offset(app), SCROLL_STEP as usize);
+
+        app.handle_event(wheel(MouseEventKind::ScrollDown));
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(offset(app), 0);
+    }
+
+    #[test]
+    fn wheel_down_in_normal_mode_does_nothing() {
+        let mut h = with_history();
+        h.app.handle_event(wheel(MouseEventKind::ScrollDown));
+        assert!(matches!(h.app.mode, Mode::Normal));
+        assert_eq!(offset(&h.app), 0);
+    }
+
+    #[test]
+    fn resize_is_passed_on_and_ends_scrollback() {
+        let mut h = with_history();
+        let app = &mut h.app;
+        app.handle_event(press(KeyCode::PageUp));
+        assert_eq!(
+            app.handle_event(Event::Resize(100, 30)),
+            [Action::Resize(100, 30), Action::Redraw]
+        );
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+}
@@ -0,0 +1,200 @@
+// src/attach.rs
+//
+// `myux attach [-r|--read-only]`: the running session on another console.
+// The client introduces itself with a `Hello` line, then sends its input as
+// crossterm events, one JSON line each; the server sends back frames, as
+// terminal output to write to the console as is. F10 detaches.
+
+use crate::clients::{ClientId, LOCAL};
+use crate::ipc::{self, Pipe};
+use crate::wake;
+use crossterm::event::{
+    self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+    Event, KeyCode,
+};
+use crossterm::terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode};
+use crossterm::{cursor, execute};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+pub fn pipe_name() -> String {
+    format!("{}-attach", ipc::pipe_name())
+}
+
+/// First line from a client.
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    pub cols: u16,
+    pub rows: u16,
+    /// Asks to watch only. The server may make a client read-only that
+    /// didn't ask, never the other way round.
+    pub read_only: bool,
+}
+
+/// What the connection threads hand to the main loop.
+pub enum Message {
+    /// A client said hello; its frames go to `out`.
+    Attached {
+        id: ClientId,
+        hello: Hello,
+        out: Sender<Vec<u8>>,
+    },
+    Input(ClientId, Event),
+    Detached(ClientId),
+}
+
+/// Serve the attach pipe on a background thread. Fails when another myux
+/// already owns it.
+pub fn serve(tx: wake::Sender<Message>) -> windows::core::Result<()> {
+    let mut next_id = LOCAL;
+    ipc::listen(&pipe_name(), move |pipe| {
+        next_id += 1;
+        serve_client(Arc::new(pipe), next_id, &tx);
+    })
+}
+
+fn serve_client(pipe: Arc<Pipe>, id: ClientId, tx: &wake::Sender<Message>) {
+    let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
+
+    let writer = Arc::clone(&pipe);
+    thread::spawn(move || {
+        while let Ok(mut frame) = out_rx.recv() {
+            // Every frame redraws the whole console, so a client that
+            // can't keep up only needs the newest.
+            while let Ok(newer) = out_rx.try_recv() {
+                frame = newer;
+            }
+            if writer.write_all(&frame).is_err() {
+                return;
+            }
+        }
+    });
+
+    let tx = tx.clone();
+    thread::spawn(move || {
+        let mut attached = false;
+        let mut buf = vec![0u8; 4096];
+        let mut pending = Vec::new();
+        'read: while let Ok(read) = pipe.read(&mut buf) {
+            pending.extend_from_slice(&buf[..read]);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let message = if attached {
+                    match serde_json::from_slice(&line) {
+                        Ok(event) => Message::Input(id, event),
+                        Err(_) => break 'read,
+                    }
+                } else {
+                    match serde_json::from_slice(&line) {
+                        Ok(hello) => {
+                            attached = true;
+                            Message::Attached {
+                                id,
+                                hello,
+                                out: out_tx.clone(),
+                            }
+                        }
+                        Err(_) => break 'read,
+                    }
+                };
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        }
+        if attached {
+            let _ = tx.send(Message::Detached(id));
+        }
+    });
+}
+
+/// `myux attach ...`; returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let read_only = match args {
+        [] => false,
+        [flag] if flag == "-r" || flag == "--read-only" => true,
+        _ => {
+            eprintln!("usage: myux attach [-r|--read-only]");
+            return 1;
+        }
+    };
+    let pipe = match Pipe::open(&pipe_name()) {
+        Ok(pipe) => Arc::new(pipe),
+        Err(e) => {
+            eprintln!("myux: no running myux to attach to ({e})");
+            return 1;
+        }
+    };
+
+    crate::enable_vt_mode();
+    crate::clamp_console_buffer_to_window();
+    let (cols, rows) = crate::console_size();
+    enable_raw_mode().ok();
+    execute!(
+        io::stdout(),
+        Clear(ClearType::All),
+        EnableMouseCapture,
+        EnableBracketedPaste,
+    )
+    .ok();
+
+    // Frames go straight to the console.
+    let (gone_tx, gone_rx) = mpsc::channel::<()>();
+    let reader = Arc::clone(&pipe);
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut stdout = io::stdout();
+        while let Ok(read) = reader.read(&mut buf) {
+            let _ = stdout.write_all(&buf[..read]);
+            let _ = stdout.flush();
+        }
+        let _ = gone_tx.send(());
+    });
+
+    let hello = Hello {
+        cols,
+        rows,
+        read_only,
+    };
+    let mut sent = send(&pipe, &hello);
+    let ended = loop {
+        if sent.is_err() || gone_rx.try_recv().is_ok() {
+            break true;
+        }
+        if !event::poll(Duration::from_millis(50)).unwrap_or(false) {
+            continue;
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.code == KeyCode::F(10) => break false,
+            Ok(event) => sent = send(&pipe, &event),
+            Err(_) => break false,
+        }
+    };
+
+    disable_raw_mode().ok();
+    // The host's own cursor color and shape again.
+    let _ = io::stdout().write_all(b"\x1b]112\x1b\\");
+    execute!(
+        io::stdout(),
+        DisableMouseCapture,
+        DisableBracketedPaste,
+        cursor::SetCursorStyle::DefaultUserShape,
+        cursor::Show,
+        Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+    )
+    .ok();
+    if ended {
+        eprintln!("myux: the session has ended");
+    }
+    0
+}
+
+fn send(pipe: &Pipe, message: &impl Serialize) -> io::Result<()> {
+    let line = serde_json::to_string(message).map_err(io::Error::other)?;
+    pipe.write_all(format!("{line}\n").as_bytes())
+}
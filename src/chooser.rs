@@ -0,0 +1,127 @@
+// src/chooser.rs
+//
+// choose-tree: an overlay listing the session, its tabs and their panes as
+// a tree. Typing filters it, Up/Down move the selection and Enter switches
+// to what is selected. The list itself is rebuilt from the App every time,
+// so it stays live; only the filter and the selection live here.
+
+use crate::layout::PaneId;
+use crossterm::event::KeyCode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Session,
+    Tab(usize),
+    /// Tab index and pane.
+    Pane(usize, PaneId),
+}
+
+/// One line of the tree.
+pub struct Entry {
+    pub target: Target,
+    pub depth: usize,
+    pub label: String,
+}
+
+pub struct Chooser {
+    pub filter: String,
+    pub selected: Target,
+}
+
+pub enum ChooserAction {
+    None,
+    Cancel,
+    Choose(Target),
+}
+
+impl Chooser {
+    pub fn new(selected: Target) -> Self {
+        Chooser {
+            filter: String::new(),
+            selected,
+        }
+    }
+
+    /// Entries that pass the filter: the ones matching it plus everything
+    /// above them, so the tree still reads as a tree.
+    pub fn visible<'a>(&self, entries: &'a [Entry]) -> Vec<&'a Entry> {
+        let mut shown = vec![false; entries.len()];
+        for (i, entry) in entries.iter().enumerate() {
+            if !self.matches(entry) {
+                continue;
+            }
+            shown[i] = true;
+            let mut depth = entry.depth;
+            for j in (0..i).rev() {
+                if entries[j].depth < depth {
+                    shown[j] = true;
+                    depth = entries[j].depth;
+                }
+            }
+        }
+        entries
+            .iter()
+            .zip(shown)
+            .filter_map(|(e, shown)| shown.then_some(e))
+            .collect()
+    }
+
+    /// Position of the selection among `visible`, if it is there.
+    pub fn selected_index(&self, visible: &[&Entry]) -> Option<usize> {
+        visible.iter().position(|e| e.target == self.selected)
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        entry
+            .label
+            .to_lowercase()
+            .contains(&self.filter.to_lowercase())
+    }
+
+    /// Handle one key against the current tree.
+    pub fn key(&mut self, code: KeyCode, entries: &[Entry]) -> ChooserAction {
+        match code {
+            KeyCode::Esc => return ChooserAction::Cancel,
+            KeyCode::Enter => {
+                let visible = self.visible(entries);
+                return match self.selected_index(&visible) {
+                    Some(_) => ChooserAction::Choose(self.selected),
+                    None => ChooserAction::None,
+                };
+            }
+            KeyCode::Up | KeyCode::Down => {
+                let visible = self.visible(entries);
+                let next = match (self.selected_index(&visible), code) {
+                    (Some(i), KeyCode::Up) => i.saturating_sub(1),
+                    (Some(i), _) => (i + 1).min(visible.len().saturating_sub(1)),
+                    (None, _) => 0,
+                };
+                if let Some(entry) = visible.get(next) {
+                    self.selected = entry.target;
+                }
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.reselect(entries);
+            }
+            KeyCode::Char(c) => {
+                self.filter.push(c);
+                self.reselect(entries);
+            }
+            _ => {}
+        }
+        ChooserAction::None
+    }
+
+    /// After the filter changed: keep the selection if it still matches,
+    /// else move it to the first entry that does.
+    fn reselect(&mut self, entries: &[Entry]) {
+        let current = entries.iter().find(|e| e.target == self.selected);
+        if current.is_some_and(|e| self.matches(e)) {
+            return;
+        }
+        if let Some(entry) = entries.iter().find(|e| self.matches(e)) {
+            self.selected = entry.target;
+        }
+    }
+}
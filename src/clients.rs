@@ -0,0 +1,209 @@
+// src/clients.rs
+//
+// Clients attached to the session: the console myux was started in, plus
+// any number of `myux attach` from other consoles. Each has its own size,
+// but there is only one set of ConPTYs, so the session is laid out for the
+// size the size policy picks; clients of another size see it letterboxed
+// or cropped (see `renderer::fit_frame`).
+//
+// Read-only clients watch: they can't type into panes or run commands, but
+// they can scroll and search back through the focused pane on their own
+// (their `Viewport`) without disturbing anyone. Nor does their size: the
+// size policy only looks at clients with full access.
+
+use crate::layout::PaneId;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+pub type ClientId = usize;
+
+/// The console myux runs in.
+pub const LOCAL: ClientId = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizePolicy {
+    /// As small as the smallest client in both directions, so everyone
+    /// sees everything.
+    Smallest,
+    Largest,
+    /// The size of whoever typed last.
+    Latest,
+    Fixed(u16, u16),
+}
+
+impl SizePolicy {
+    /// `smallest`, `largest`, `latest` or `<cols>x<rows>`.
+    pub fn parse(value: &str) -> Option<SizePolicy> {
+        match value {
+            "smallest" => Some(SizePolicy::Smallest),
+            "largest" => Some(SizePolicy::Largest),
+            "latest" => Some(SizePolicy::Latest),
+            _ => {
+                let (cols, rows) = value.split_once('x')?;
+                match (cols.parse(), rows.parse()) {
+                    (Ok(cols @ 2..), Ok(rows @ 3..)) => Some(SizePolicy::Fixed(cols, rows)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            SizePolicy::Smallest => "smallest".into(),
+            SizePolicy::Largest => "largest".into(),
+            SizePolicy::Latest => "latest".into(),
+            SizePolicy::Fixed(cols, rows) => format!("{cols}x{rows}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Full,
+    ReadOnly,
+}
+
+impl Access {
+    pub fn parse(value: &str) -> Option<Access> {
+        match value {
+            "full" => Some(Access::Full),
+            "read-only" => Some(Access::ReadOnly),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Access::Full => "full",
+            Access::ReadOnly => "read-only",
+        }
+    }
+}
+
+/// A read-only client's own look back through a pane's history.
+#[derive(Clone)]
+pub struct Viewport {
+    pub pane: PaneId,
+    /// Rows above the live view.
+    pub offset: usize,
+    /// Query being typed, while searching.
+    pub search: Option<String>,
+}
+
+pub struct Client {
+    pub id: ClientId,
+    pub access: Access,
+    /// Console size, status bar included.
+    pub size: (u16, u16),
+    /// Last time it sent input (or attached).
+    pub active: Instant,
+    /// Where its frames go; None for the local console, which is drawn
+    /// directly.
+    pub out: Option<Sender<Vec<u8>>>,
+    /// Read-only clients only: where they are scrolled to, the status
+    /// message meant for them alone, whether they pressed the prefix and
+    /// what they searched for last.
+    pub view: Option<Viewport>,
+    pub message: Option<String>,
+    pub prefix: bool,
+    pub last_search: Option<String>,
+}
+
+impl Client {
+    /// Whether this client sees something of its own rather than the
+    /// frame everybody gets.
+    pub fn has_own_view(&self) -> bool {
+        self.view.is_some() || self.message.is_some()
+    }
+}
+
+pub struct Clients {
+    list: Vec<Client>,
+    pub policy: SizePolicy,
+    /// Most that a client attaching with `myux attach` gets.
+    pub attach_access: Access,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Clients {
+            list: Vec::new(),
+            policy: SizePolicy::Latest,
+            attach_access: Access::Full,
+        }
+    }
+
+    pub fn attach(
+        &mut self,
+        id: ClientId,
+        size: (u16, u16),
+        access: Access,
+        out: Option<Sender<Vec<u8>>>,
+    ) {
+        self.list.push(Client {
+            id,
+            access,
+            size,
+            active: Instant::now(),
+            out,
+            view: None,
+            message: None,
+            prefix: false,
+            last_search: None,
+        });
+    }
+
+    pub fn get(&self, id: ClientId) -> Option<&Client> {
+        self.list.iter().find(|c| c.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.list.iter_mut().find(|c| c.id == id)
+    }
+
+    pub fn set_access(&mut self, id: ClientId, access: Access) -> bool {
+        let Some(client) = self.get_mut(id) else {
+            return false;
+        };
+        client.access = access;
+        client.view = None;
+        client.message = None;
+        client.prefix = false;
+        true
+    }
+
+    pub fn detach(&mut self, id: ClientId) {
+        self.list.retain(|c| c.id != id);
+    }
+
+    pub fn resize(&mut self, id: ClientId, size: (u16, u16)) {
+        if let Some(client) = self.get_mut(id) {
+            client.size = size;
+        }
+    }
+
+    /// `id` sent input.
+    pub fn touch(&mut self, id: ClientId) {
+        if let Some(client) = self.get_mut(id) {
+            client.active = Instant::now();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.list.iter()
+    }
+
+    /// The size the session should have under the policy; None while
+    /// nobody with full access is attached (and the policy doesn't fix it).
+    pub fn session_size(&self) -> Option<(u16, u16)> {
+        let full = self.list.iter().filter(|c| c.access == Access::Full);
+        let sizes = full.clone().map(|c| c.size);
+        match self.policy {
+            SizePolicy::Fixed(cols, rows) => Some((cols, rows)),
+            SizePolicy::Smallest => sizes.reduce(|a, b| (a.0.min(b.0), a.1.min(b.1))),
+            SizePolicy::Largest => sizes.reduce(|a, b| (a.0.max(b.0), a.1.max(b.1))),
+            SizePolicy::Latest => full.max_by_key(|c| c.active).map(|c| c.size),
+        }
+    }
+}
@@ -0,0 +1,1211 @@
+// src/command.rs
+//
+// The myux command language. Key bindings, the `:` prompt, the config file
+// and `myux <command>` all come down to a line such as `split -h -c C:\src`,
+// which is split into words and looked up in one table.
+
+use crate::app::{self, App, Mode, SHELL};
+use crate::chooser::{Chooser, Target};
+use crate::clients::{Access, LOCAL, SizePolicy};
+use crate::history::HistoryLimits;
+use crate::hooks::Hook;
+use crate::layout::{Direction, PaneId, Split};
+use crate::palette::{self, ColorDepth, Rgb};
+use crate::pipe::PipeCommand;
+use crate::pty::Program;
+use crate::renderer::AmbiguousWidth;
+use crate::session::Session;
+use crate::terminal::SCROLLBACK_LEN;
+use crossterm::event::KeyCode;
+use std::collections::HashMap;
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+pub struct Command {
+    pub name: &'static str,
+    /// Flags in getopt style: a letter, followed by `:` if it takes a value.
+    pub flags: &'static str,
+    pub usage: &'static str,
+    run: fn(&mut App, &Args) -> Result<String, String>,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "bind-key",
+        flags: "",
+        usage: "bind-key <key> <command...>",
+        run: bind_key,
+    },
+    Command {
+        name: "call",
+        flags: "",
+        usage: "call <function> [args...]",
+        run: call,
+    },
+    Command {
+        name: "choose-tree",
+        flags: "",
+        usage: "choose-tree",
+        run: choose_tree,
+    },
+    Command {
+        name: "client-access",
+        flags: "",
+        usage: "client-access <client> <full|read-only>",
+        run: client_access,
+    },
+    Command {
+        name: "command-prompt",
+        flags: "",
+        usage: "command-prompt [text]",
+        run: command_prompt,
+    },
+    Command {
+        name: "copy-mode",
+        flags: "",
+        usage: "copy-mode",
+        run: copy_mode,
+    },
+    Command {
+        name: "kill-pane",
+        flags: "t:",
+        usage: "kill-pane [-t pane]",
+        run: kill_pane,
+    },
+    Command {
+        name: "kill-tab",
+        flags: "",
+        usage: "kill-tab [index]",
+        run: kill_tab,
+    },
+    Command {
+        name: "list-clients",
+        flags: "",
+        usage: "list-clients",
+        run: list_clients,
+    },
+    Command {
+        name: "load",
+        flags: "",
+        usage: "load <file>",
+        run: load,
+    },
+    Command {
+        name: "load-script",
+        flags: "",
+        usage: "load-script <file>",
+        run: load_script,
+    },
+    Command {
+        name: "new-tab",
+        flags: "c:",
+        usage: "new-tab [-c dir] [command]",
+        run: new_tab,
+    },
+    Command {
+        name: "next-tab",
+        flags: "",
+        usage: "next-tab",
+        run: next_tab,
+    },
+    Command {
+        name: "pipe-pane",
+        flags: "osIr:k:f:",
+        usage: PipeCommand::USAGE,
+        run: pipe_pane,
+    },
+    Command {
+        name: "popup",
+        flags: "Ckw:h:c:",
+        usage: "popup [-C] [-k] [-w percent] [-h percent] [-c dir] [command...]",
+        run: popup,
+    },
+    Command {
+        name: "previous-tab",
+        flags: "",
+        usage: "previous-tab",
+        run: previous_tab,
+    },
+    Command {
+        name: "rename-tab",
+        flags: "",
+        usage: "rename-tab <name>",
+        run: rename_tab,
+    },
+    Command {
+        name: "resize-client",
+        flags: "",
+        usage: "resize-client <cols> <rows>",
+        run: resize_client,
+    },
+    Command {
+        name: "run-shell",
+        flags: "c:",
+        usage: "run-shell [-c dir] <command...>",
+        run: run_shell,
+    },
+    Command {
+        name: "save-session",
+        flags: "s",
+        usage: "save-session [-s] <file>",
+        run: save_session,
+    },
+    Command {
+        name: "select-pane",
+        flags: "LRUDn",
+        usage: "select-pane [-L|-R|-U|-D|-n]",
+        run: select_pane,
+    },
+    Command {
+        name: "select-tab",
+        flags: "",
+        usage: "select-tab <number>",
+        run: select_tab,
+    },
+    Command {
+        name: "send-prefix",
+        flags: "",
+        usage: "send-prefix",
+        run: send_prefix,
+    },
+    Command {
+        name: "send-keys",
+        flags: "lt:",
+        usage: "send-keys [-l] [-t pane] <keys...>",
+        run: send_keys,
+    },
+    Command {
+        name: "set",
+        flags: "",
+        usage: "set <option> <value>",
+        run: set_option,
+    },
+    Command {
+        name: "set-color",
+        flags: "pt:",
+        usage: "set-color [-p] [-t pane] [<0-15|foreground|background|cursor> <color>]",
+        run: set_color,
+    },
+    Command {
+        name: "set-environment",
+        flags: "u",
+        usage: "set-environment [-u] [name [value]]",
+        run: set_environment,
+    },
+    Command {
+        name: "set-hook",
+        flags: "au",
+        usage: "set-hook [-a] [-u] [event [command...]]",
+        run: set_hook,
+    },
+    Command {
+        name: "source-file",
+        flags: "",
+        usage: "source-file <file>",
+        run: source_file_cmd,
+    },
+    Command {
+        name: "split",
+        flags: "hvc:",
+        usage: "split [-h|-v] [-c dir] [command]",
+        run: split,
+    },
+    Command {
+        name: "sync-input",
+        flags: "p",
+        usage: "sync-input [-p]",
+        run: sync_input,
+    },
+    Command {
+        name: "theme",
+        flags: "pt:u",
+        usage: "theme [-p] [-u] [-t pane] [name]",
+        run: theme,
+    },
+    Command {
+        name: "unbind-key",
+        flags: "",
+        usage: "unbind-key <key>",
+        run: unbind_key,
+    },
+    Command {
+        name: "zoom-pane",
+        flags: "",
+        usage: "zoom-pane",
+        run: zoom_pane,
+    },
+];
+
+/// Commands read-only clients may run: they only report.
+pub const READ_ONLY_COMMANDS: &[&str] = &["list-clients"];
+
+/// Options understood by `set`.
+pub const OPTIONS: &[&str] = &[
+    "ambiguous-width",
+    "attach-access",
+    "color-depth",
+    "frame-rate",
+    "login-shell",
+    "scrollback",
+    "scrollback-bytes",
+    "size-policy",
+];
+
+/// What the prefix key is followed by, and the command it runs.
+pub const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("%", "split -h"),
+    ("\"", "split -v"),
+    ("o", "select-pane -n"),
+    ("Left", "select-pane -L"),
+    ("Right", "select-pane -R"),
+    ("Up", "select-pane -U"),
+    ("Down", "select-pane -D"),
+    ("z", "zoom-pane"),
+    ("s", "sync-input"),
+    ("S", "sync-input -p"),
+    ("P", "pipe-pane -o -s -f myux-pane-{pane}.log"),
+    ("w", "choose-tree"),
+    ("c", "new-tab"),
+    ("x", "kill-pane"),
+    ("&", "kill-tab"),
+    ("n", "next-tab"),
+    ("p", "previous-tab"),
+    (",", "command-prompt \"rename-tab \""),
+    (":", "command-prompt"),
+    ("[", "copy-mode"),
+    ("C-b", "send-prefix"),
+];
+
+/// `DEFAULT_BINDINGS` as a binding table.
+pub fn default_bindings() -> HashMap<Key, Vec<String>> {
+    DEFAULT_BINDINGS
+        .iter()
+        .map(|(key, line)| {
+            let key = Key::parse(key).expect("valid default key");
+            (key, tokenize(line).expect("valid default binding"))
+        })
+        .collect()
+}
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Parsed arguments of one command.
+pub struct Args {
+    flags: Vec<(char, Option<String>)>,
+    /// Everything after the flags.
+    pub rest: Vec<String>,
+    /// `rest` as it was typed, quotes and all, when the command came as a
+    /// line.
+    tail: Option<String>,
+    /// Relative paths are taken from here (the client's directory for
+    /// `myux <command>`, the focused pane's for the prompt).
+    cwd: Option<PathBuf>,
+}
+
+impl Args {
+    /// Parse `words`. With `line`, the line they were split from and where
+    /// each starts in it, the rest is also kept as typed.
+    fn parse(
+        cmd: &Command,
+        words: &[String],
+        line: Option<(&str, &[usize])>,
+        cwd: Option<&Path>,
+    ) -> Result<Self, String> {
+        let mut flags = Vec::new();
+        let mut iter = words.iter();
+        let mut rest = Vec::new();
+
+        while let Some(word) = iter.next() {
+            if word == "--" {
+                break;
+            }
+            let Some(letters) = word.strip_prefix('-').filter(|l| !l.is_empty()) else {
+                rest.push(word.clone());
+                break;
+            };
+
+            // `-hv` is `-h -v`; `-cdir` and `-c dir` both give `c` a value.
+            for (i, c) in letters.char_indices() {
+                let Some(pos) = cmd.flags.find(c).filter(|_| c != ':') else {
+                    return Err(format!(
+                        "{}: unknown flag -{c}; usage: {}",
+                        cmd.name, cmd.usage
+                    ));
+                };
+                if cmd.flags[pos + 1..].starts_with(':') {
+                    let value = match &letters[i + c.len_utf8()..] {
+                        "" => iter
+                            .next()
+                            .ok_or_else(|| format!("{}: -{c} needs a value", cmd.name))?
+                            .clone(),
+                        inline => inline.to_string(),
+                    };
+                    flags.push((c, Some(value)));
+                    break;
+                }
+                flags.push((c, None));
+            }
+        }
+        rest.extend(iter.cloned());
+        let tail = line
+            .filter(|_| !rest.is_empty())
+            .map(|(line, starts)| line[starts[words.len() - rest.len()]..].to_string());
+
+        Ok(Args {
+            flags,
+            rest,
+            tail,
+            cwd: cwd.map(Path::to_path_buf),
+        })
+    }
+
+    pub fn has(&self, flag: char) -> bool {
+        self.flags.iter().any(|(f, _)| *f == flag)
+    }
+
+    /// Value of the last `-flag value`.
+    pub fn value(&self, flag: char) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == flag)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    /// `path`, relative to the directory the command came from.
+    pub fn path(&self, path: &str) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) => cwd.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// The rest as one command line, if there is one: as typed, or with
+    /// each word quoted when the command came as words.
+    pub fn command_line(&self) -> Option<String> {
+        match &self.tail {
+            Some(tail) => Some(tail.clone()),
+            None => (!self.rest.is_empty()).then(|| Program::Argv(self.rest.clone()).cmdline()),
+        }
+    }
+}
+
+/// Split a command line into words. Double or single quotes group words;
+/// there are no backslash escapes, so Windows paths can be typed as they
+/// are.
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    Ok(split_words(line)?
+        .into_iter()
+        .map(|(_, word)| word)
+        .collect())
+}
+
+/// `tokenize`, with where each word starts in `line`.
+fn split_words(line: &str) -> Result<Vec<(usize, String)>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut start = None;
+    let mut quote = None;
+
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                start.get_or_insert(i);
+            }
+            None if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    words.push((start, std::mem::take(&mut word)));
+                }
+            }
+            None => {
+                word.push(c);
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("missing closing quote".into());
+    }
+    if let Some(start) = start {
+        words.push((start, word));
+    }
+    Ok(words)
+}
+
+/// Run one command given as words. Returns a message for the user, which
+/// may be empty.
+pub fn run(app: &mut App, words: &[String], cwd: Option<&Path>) -> Result<String, String> {
+    run_words(app, words, None, cwd)
+}
+
+/// Run a command line. Blank lines and `#` comments do nothing. Command
+/// lines in it (`split cmd /c echo "hi"`) go on as typed.
+pub fn run_line(app: &mut App, line: &str, cwd: Option<&Path>) -> Result<String, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(String::new());
+    }
+    let (starts, words): (Vec<usize>, Vec<String>) = split_words(line)?.into_iter().unzip();
+    run_words(app, &words, Some((line, &starts)), cwd)
+}
+
+/// `run`, with the line the words came from and where each starts in it.
+fn run_words(
+    app: &mut App,
+    words: &[String],
+    line: Option<(&str, &[usize])>,
+    cwd: Option<&Path>,
+) -> Result<String, String> {
+    let Some((name, args)) = words.split_first() else {
+        return Ok(String::new());
+    };
+    let cmd = find(name).ok_or_else(|| format!("unknown command: {name}"))?;
+    let line = line.map(|(line, starts)| (line, &starts[1..]));
+    let args = Args::parse(cmd, args, line, cwd)?;
+    (cmd.run)(app, &args)
+}
+
+/// Run every line of a config file, stopping at the first error.
+pub fn source_file(app: &mut App, path: &Path) -> Result<String, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let dir = path.parent();
+    for (n, line) in text.lines().enumerate() {
+        run_line(app, line, dir).map_err(|e| format!("{}:{}: {e}", path.display(), n + 1))?;
+    }
+    Ok(String::new())
+}
+
+/// Where the config file is: `MYUX_CONFIG`, else `%USERPROFILE%\.myux.conf`.
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("MYUX_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let home = std::env::var("USERPROFILE").ok()?;
+    Some(Path::new(&home).join(".myux.conf"))
+}
+
+/// A key as written in `bind-key`: a character (`%`, `C-b` with Ctrl) or a
+/// name (`Left`, `Enter`, `F5`, ...).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+}
+
+impl Key {
+    pub fn parse(s: &str) -> Result<Key, String> {
+        let (ctrl, name) = match s.strip_prefix("C-") {
+            Some(name) if !name.is_empty() => (true, name),
+            _ => (false, s),
+        };
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name {
+                "Left" => KeyCode::Left,
+                "Right" => KeyCode::Right,
+                "Up" => KeyCode::Up,
+                "Down" => KeyCode::Down,
+                "Enter" => KeyCode::Enter,
+                "Tab" => KeyCode::Tab,
+                "Space" => KeyCode::Char(' '),
+                "Escape" => KeyCode::Esc,
+                "PageUp" => KeyCode::PageUp,
+                "PageDown" => KeyCode::PageDown,
+                "Home" => KeyCode::Home,
+                "End" => KeyCode::End,
+                _ => match name.strip_prefix('F').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key: {s}")),
+                },
+            },
+        };
+        Ok(Key { code, ctrl })
+    }
+}
+
+/// Result of completing the prompt line.
+pub struct Completion {
+    pub line: String,
+    /// All matches when there is more than one.
+    pub candidates: Vec<String>,
+}
+
+/// Complete the last word of `line`: command names first, then the flags
+/// of the command, or option names after `set`.
+pub fn complete(line: &str) -> Completion {
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| {
+        i + line[i..].chars().next().map_or(1, char::len_utf8)
+    });
+    let (head, word) = line.split_at(start);
+    let before: Vec<&str> = head.split_whitespace().collect();
+
+    let choices: Vec<String> = match before.as_slice() {
+        [] => COMMANDS.iter().map(|c| c.name.to_string()).collect(),
+        ["set"] => OPTIONS.iter().map(|o| o.to_string()).collect(),
+        ["theme"] if !word.starts_with('-') => {
+            palette::THEMES.iter().map(|(n, _)| n.to_string()).collect()
+        }
+        [name, ..] if word.starts_with('-') => find(name)
+            .map(|c| {
+                c.flags
+                    .chars()
+                    .filter(|&f| f != ':')
+                    .map(|f| format!("-{f}"))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let matches: Vec<String> = choices
+        .into_iter()
+        .filter(|c| c.starts_with(word))
+        .collect();
+
+    match matches.as_slice() {
+        [] => Completion {
+            line: line.to_string(),
+            candidates: Vec::new(),
+        },
+        [only] => Completion {
+            line: format!("{head}{only} "),
+            candidates: Vec::new(),
+        },
+        _ => {
+            let common = matches.iter().skip(1).fold(matches[0].clone(), |acc, m| {
+                acc.chars()
+                    .zip(m.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect()
+            });
+            Completion {
+                line: format!("{head}{common}"),
+                candidates: matches,
+            }
+        }
+    }
+}
+
+// ---------- Commands ----------
+
+fn usage(name: &str) -> String {
+    let usage = find(name).map_or("", |c| c.usage);
+    format!("usage: {usage}")
+}
+
+fn bind_key(app: &mut App, args: &Args) -> Result<String, String> {
+    let [key, command @ ..] = args.rest.as_slice() else {
+        return Err(usage("bind-key"));
+    };
+    let key = Key::parse(key)?;
+    match command.first() {
+        Some(name) if find(name).is_some() => {
+            app.bindings.insert(key, command.to_vec());
+            Ok(String::new())
+        }
+        Some(name) => Err(format!("unknown command: {name}")),
+        None => Err(usage("bind-key")),
+    }
+}
+
+fn unbind_key(app: &mut App, args: &Args) -> Result<String, String> {
+    let [key] = args.rest.as_slice() else {
+        return Err(usage("unbind-key"));
+    };
+    app.bindings.remove(&Key::parse(key)?);
+    Ok(String::new())
+}
+
+/// Call a function defined by a script.
+fn call(app: &mut App, args: &Args) -> Result<String, String> {
+    let [name, rest @ ..] = args.rest.as_slice() else {
+        return Err(usage("call"));
+    };
+    crate::script::call(app, name, rest)
+}
+
+fn choose_tree(app: &mut App, _: &Args) -> Result<String, String> {
+    let tab = app.active_tab();
+    let selected = Target::Pane(app.active, tab.focus);
+    app.mode = Mode::Choose(Chooser::new(selected));
+    Ok(String::new())
+}
+
+fn client_access(app: &mut App, args: &Args) -> Result<String, String> {
+    let [client, access] = args.rest.as_slice() else {
+        return Err(usage("client-access"));
+    };
+    let id = client
+        .parse()
+        .map_err(|_| format!("not a client: {client}"))?;
+    let access =
+        Access::parse(access).ok_or_else(|| format!("expected full or read-only: {access}"))?;
+    if id == LOCAL && access == Access::ReadOnly {
+        return Err("this console can't be made read-only".into());
+    }
+    if !app.clients.set_access(id, access) {
+        return Err(format!("no such client: {id}"));
+    }
+    // Only clients with full access count for the size.
+    app.apply_size_policy();
+    Ok(String::new())
+}
+
+fn command_prompt(app: &mut App, args: &Args) -> Result<String, String> {
+    app.mode = Mode::Command(crate::prompt::Prompt::new(&args.rest.join(" ")));
+    Ok(String::new())
+}
+
+fn copy_mode(app: &mut App, _: &Args) -> Result<String, String> {
+    app.mode = Mode::Scrollback;
+    Ok(String::new())
+}
+
+fn kill_pane(app: &mut App, args: &Args) -> Result<String, String> {
+    let id = target_pane(app, args)?;
+    app.kill_pane(id)?;
+    Ok(String::new())
+}
+
+fn kill_tab(app: &mut App, args: &Args) -> Result<String, String> {
+    let index = match args.rest.as_slice() {
+        [] => app.active,
+        [n] => {
+            n.parse::<usize>()
+                .ok()
+                .filter(|&n| n >= 1)
+                .ok_or_else(|| format!("no tab {n}"))?
+                - 1
+        }
+        _ => return Err(usage("kill-tab")),
+    };
+    app.kill_tab(index)?;
+    Ok(String::new())
+}
+
+/// The session size and every attached client's, on one line.
+fn list_clients(app: &mut App, _: &Args) -> Result<String, String> {
+    let (cols, rows) = app.size;
+    let mut parts = vec![format!(
+        "session {cols}x{rows} ({})",
+        app.clients.policy.name()
+    )];
+    for client in app.clients.iter() {
+        let (cols, rows) = client.size;
+        let local = if client.id == LOCAL {
+            " (this console)"
+        } else {
+            ""
+        };
+        parts.push(format!(
+            "client {}: {cols}x{rows} {}{local}",
+            client.id,
+            client.access.name()
+        ));
+    }
+    Ok(parts.join("; "))
+}
+
+fn load(app: &mut App, args: &Args) -> Result<String, String> {
+    let [file] = args.rest.as_slice() else {
+        return Err(usage("load"));
+    };
+    let path = args.path(file);
+    let session = Session::load(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    app.open_session(&session);
+    Ok(format!(
+        "loaded {} tab(s) from {}",
+        session.tabs.len(),
+        path.display()
+    ))
+}
+
+/// Set the size the panes are laid out for, without the status bar. For
+/// control mode front-ends, which have no console of ours to follow.
+fn resize_client(app: &mut App, args: &Args) -> Result<String, String> {
+    let [cols, rows] = args.rest.as_slice() else {
+        return Err(usage("resize-client"));
+    };
+    let size = |v: &String| match v.parse::<u16>() {
+        Ok(n) if n >= 2 => Ok(n),
+        _ => Err(format!("not a size: {v}")),
+    };
+    let (cols, rows) = (size(cols)?, size(rows)?);
+    app.resize(cols, rows + 1);
+    Ok(String::new())
+}
+
+/// Start a program through `cmd.exe /C` without waiting for it. When run
+/// by a hook it gets the event's MYUX_* variables.
+fn run_shell(app: &mut App, args: &Args) -> Result<String, String> {
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    let cmdline = args.command_line().ok_or_else(|| usage("run-shell"))?;
+    let mut child = std::process::Command::new("cmd.exe");
+    child
+        .arg("/C")
+        .raw_arg(&cmdline)
+        .envs(app.hook_env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .creation_flags(CREATE_NO_WINDOW);
+    if let Some(dir) = args.value('c') {
+        child.current_dir(args.path(dir));
+    } else if let Some(dir) = &args.cwd {
+        child.current_dir(dir);
+    }
+    child.spawn().map_err(|e| format!("{cmdline}: {e}"))?;
+    Ok(String::new())
+}
+
+fn save_session(app: &mut App, args: &Args) -> Result<String, String> {
+    let [file] = args.rest.as_slice() else {
+        return Err(usage("save-session"));
+    };
+    let path = args.path(file);
+    let session = app.session(args.has('s'));
+    session
+        .save(&path)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(format!(
+        "saved {} tab(s) to {}",
+        session.tabs.len(),
+        path.display()
+    ))
+}
+
+fn load_script(app: &mut App, args: &Args) -> Result<String, String> {
+    let [file] = args.rest.as_slice() else {
+        return Err(usage("load-script"));
+    };
+    crate::script::load_file(app, &args.path(file))?;
+    Ok(String::new())
+}
+
+fn new_tab(app: &mut App, args: &Args) -> Result<String, String> {
+    let cwd = args
+        .value('c')
+        .map(|d| args.path(d).to_string_lossy().into_owned());
+    let command = args.command_line().unwrap_or_else(|| SHELL.to_string());
+    app.new_tab(&command, cwd.as_deref());
+    Ok(String::new())
+}
+
+fn split(app: &mut App, args: &Args) -> Result<String, String> {
+    let dir = if args.has('h') {
+        Split::Horizontal
+    } else {
+        Split::Vertical
+    };
+    let cwd = args
+        .value('c')
+        .map(|d| args.path(d).to_string_lossy().into_owned());
+    let command = args.command_line().unwrap_or_else(|| SHELL.to_string());
+    app.split_focused(dir, &command, cwd)?;
+    Ok(String::new())
+}
+
+fn next_tab(app: &mut App, _: &Args) -> Result<String, String> {
+    app.select_tab((app.active + 1) % app.tabs.len());
+    Ok(String::new())
+}
+
+/// Open a popup, or with `-C` close the one that is open.
+fn popup(app: &mut App, args: &Args) -> Result<String, String> {
+    if args.has('C') {
+        app.close_popup();
+        return Ok(String::new());
+    }
+    let percent = |flag| match args.value(flag) {
+        None => Ok(80),
+        Some(v) => match v.trim_end_matches('%').parse::<u16>() {
+            Ok(n) if (1..=100).contains(&n) => Ok(n),
+            _ => Err(format!("-{flag}: expected a percentage: {v}")),
+        },
+    };
+    let (width, height) = (percent('w')?, percent('h')?);
+    let cwd = args
+        .value('c')
+        .map(|d| args.path(d).to_string_lossy().into_owned());
+    let command = args.command_line().unwrap_or_else(|| SHELL.to_string());
+    app.open_popup(&command, cwd, width, height, args.has('k'));
+    Ok(String::new())
+}
+
+fn previous_tab(app: &mut App, _: &Args) -> Result<String, String> {
+    app.select_tab((app.active + app.tabs.len() - 1) % app.tabs.len());
+    Ok(String::new())
+}
+
+fn select_tab(app: &mut App, args: &Args) -> Result<String, String> {
+    let [n] = args.rest.as_slice() else {
+        return Err(usage("select-tab"));
+    };
+    match n.parse::<usize>() {
+        Ok(n) if (1..=app.tabs.len()).contains(&n) => {
+            app.select_tab(n - 1);
+            Ok(String::new())
+        }
+        _ => Err(format!("no tab {n}")),
+    }
+}
+
+fn rename_tab(app: &mut App, args: &Args) -> Result<String, String> {
+    if args.rest.is_empty() {
+        return Err(usage("rename-tab"));
+    }
+    app.active_tab_mut().name = args.rest.join(" ");
+    Ok(String::new())
+}
+
+fn pipe_pane(app: &mut App, args: &Args) -> Result<String, String> {
+    let pane = app.active_tab().focused();
+    let PipeCommand { toggle, spec } = PipeCommand::from_args(args, pane.id)?;
+    pane.set_pipe(spec, toggle)
+}
+
+fn select_pane(app: &mut App, args: &Args) -> Result<String, String> {
+    let dir = [
+        ('L', Direction::Left),
+        ('R', Direction::Right),
+        ('U', Direction::Up),
+        ('D', Direction::Down),
+    ]
+    .into_iter()
+    .find(|(flag, _)| args.has(*flag));
+    match dir {
+        Some((_, dir)) => app.focus_direction(dir),
+        None => app.focus_next(),
+    }
+    Ok(String::new())
+}
+
+/// The pane given with `-t` (`3` or `%3`), else the focused one.
+fn target_pane(app: &App, args: &Args) -> Result<PaneId, String> {
+    match args.value('t') {
+        Some(t) => t
+            .trim_start_matches('%')
+            .parse()
+            .map_err(|_| format!("not a pane: {t}")),
+        None => Ok(app.active_tab().focus),
+    }
+}
+
+/// Type into a pane. Words are key names (`Enter`, `C-c`, `Up`) where they
+/// are one, else text; `-l` sends them all as text.
+fn send_keys(app: &mut App, args: &Args) -> Result<String, String> {
+    let id = target_pane(app, args)?;
+    let pane = app.pane(id).ok_or_else(|| format!("no pane %{id}"))?;
+
+    let mut bytes = Vec::new();
+    for word in &args.rest {
+        match Key::parse(word).ok().filter(|_| !args.has('l')) {
+            Some(Key {
+                code: KeyCode::Char(c),
+                ctrl: true,
+            }) if c.is_ascii() => bytes.push(c.to_ascii_lowercase() as u8 & 0x1f),
+            Some(key) => bytes.extend(app::key_bytes(key.code)),
+            _ => bytes.extend_from_slice(word.as_bytes()),
+        }
+    }
+    pane.pty.write(&bytes);
+    Ok(String::new())
+}
+
+fn send_prefix(app: &mut App, _: &Args) -> Result<String, String> {
+    let tab = app.active_tab();
+    for pane in tab
+        .input_targets()
+        .into_iter()
+        .filter_map(|id| tab.pane(id))
+    {
+        pane.pty.write(&[0x02]);
+    }
+    Ok(String::new())
+}
+
+fn set_option(app: &mut App, args: &Args) -> Result<String, String> {
+    let [option, value] = args.rest.as_slice() else {
+        return Err(usage("set"));
+    };
+    let number = || {
+        value
+            .parse::<u64>()
+            .map_err(|_| format!("{option}: not a number: {value}"))
+    };
+
+    match option.as_str() {
+        // Total rows of history, the ones kept in memory included.
+        "scrollback" => {
+            let lines = number()? as usize;
+            if lines < SCROLLBACK_LEN {
+                return Err(format!(
+                    "{option}: at least {SCROLLBACK_LEN}, the rows kept in memory"
+                ));
+            }
+            let limits = HistoryLimits {
+                max_lines: lines - SCROLLBACK_LEN,
+                ..app.history_limits
+            };
+            app.set_history_limits(limits);
+        }
+        "scrollback-bytes" => {
+            let limits = HistoryLimits {
+                max_bytes: number()?,
+                ..app.history_limits
+            };
+            app.set_history_limits(limits);
+        }
+        // Whose console size the panes follow when several are attached.
+        "size-policy" => {
+            app.clients.policy = SizePolicy::parse(value).ok_or_else(|| {
+                format!("{option}: expected smallest, largest, latest or <cols>x<rows>")
+            })?;
+            app.apply_size_policy();
+        }
+        // What `myux attach` gets unless it asks for less.
+        "attach-access" => {
+            app.clients.attach_access = Access::parse(value)
+                .ok_or_else(|| format!("{option}: expected full or read-only"))?;
+        }
+        "ambiguous-width" => {
+            app.ambiguous = match value.as_str() {
+                "narrow" => AmbiguousWidth::Narrow,
+                "wide" => AmbiguousWidth::Wide,
+                _ => return Err(format!("{option}: expected narrow or wide")),
+            };
+        }
+        // What the host can show; colors past it are brought down to it.
+        "color-depth" => {
+            app.color_depth = ColorDepth::parse(value)
+                .ok_or_else(|| format!("{option}: expected truecolor, 256 or 16"))?;
+        }
+        "frame-rate" => match number()? {
+            fps @ 1..=1000 => app.frame_rate = fps as u32,
+            _ => return Err(format!("{option}: expected 1 to 1000")),
+        },
+        // Applies to panes started from now on.
+        "login-shell" => {
+            app.login_shell = match value.as_str() {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("{option}: expected on or off")),
+            };
+        }
+        _ => return Err(format!("unknown option: {option}")),
+    }
+    Ok(String::new())
+}
+
+/// Change one color of the session's palette, or with `-p` of a pane's
+/// own. Without a color, list them.
+fn set_color(app: &mut App, args: &Args) -> Result<String, String> {
+    let own = args.has('p') || args.value('t').is_some();
+    let id = target_pane(app, args)?;
+    let pane = app.pane(id).ok_or_else(|| format!("no pane %{id}"))?;
+    let mut palette = if own {
+        pane.term.palette().clone()
+    } else {
+        app.palette.clone()
+    };
+    let [slot, color] = args.rest.as_slice() else {
+        if !args.rest.is_empty() {
+            return Err(usage("set-color"));
+        }
+        let mut lines: Vec<String> = (0..16)
+            .map(|i| format!("{i}: {}", palette.colors[i].html()))
+            .collect();
+        lines.push(format!("foreground: {}", palette.foreground.html()));
+        lines.push(format!("background: {}", palette.background.html()));
+        lines.push(format!(
+            "cursor: {}",
+            palette.cursor.map_or("default".into(), |c| c.html())
+        ));
+        return Ok(lines.join("\n"));
+    };
+    let color = Rgb::parse(color).ok_or_else(|| format!("not a color: {color}"))?;
+    palette.set(slot, color)?;
+    if own {
+        app.set_pane_palette(id, Some(palette));
+    } else {
+        app.set_palette(palette);
+    }
+    Ok(String::new())
+}
+
+/// Set a variable in new panes' environment, or with `-u` remove it.
+/// Without a name, list the changes.
+fn set_environment(app: &mut App, args: &Args) -> Result<String, String> {
+    let (name, value) = match (args.rest.as_slice(), args.has('u')) {
+        ([], false) => {
+            let lines: Vec<_> = app
+                .environment
+                .iter()
+                .map(|(name, value)| match value {
+                    Some(value) => format!("{name}={value}"),
+                    None => format!("-{name}"),
+                })
+                .collect();
+            return Ok(lines.join("\n"));
+        }
+        ([name], true) => (name, None),
+        ([name, value], false) => (name, Some(value.clone())),
+        _ => return Err(usage("set-environment")),
+    };
+    if name.is_empty() || name.contains('=') {
+        return Err(format!("not a variable name: {name}"));
+    }
+    app.set_environment(name, value);
+    Ok(String::new())
+}
+
+fn set_hook(app: &mut App, args: &Args) -> Result<String, String> {
+    let Some((event, command)) = args.rest.split_first() else {
+        return Ok(app.hooks.list());
+    };
+    let hook = Hook::parse(event).ok_or_else(|| {
+        let names: Vec<_> = Hook::ALL.iter().map(|h| h.name()).collect();
+        format!("unknown event: {event} (one of {})", names.join(", "))
+    })?;
+
+    if args.has('u') {
+        app.hooks.unset(hook);
+        return Ok(String::new());
+    }
+    match command.first() {
+        Some(name) if find(name).is_some() => {
+            app.hooks.set(hook, command.to_vec(), args.has('a'));
+            Ok(String::new())
+        }
+        Some(name) => Err(format!("unknown command: {name}")),
+        None => Err(usage("set-hook")),
+    }
+}
+
+fn source_file_cmd(app: &mut App, args: &Args) -> Result<String, String> {
+    let [file] = args.rest.as_slice() else {
+        return Err(usage("source-file"));
+    };
+    source_file(app, &args.path(file))
+}
+
+fn sync_input(app: &mut App, args: &Args) -> Result<String, String> {
+    let tab = app.active_tab_mut();
+    if args.has('p') {
+        let pane = tab.focused_mut();
+        pane.sync = !pane.sync;
+    } else {
+        tab.sync = !tab.sync;
+    }
+    Ok(String::new())
+}
+
+/// Switch the session to a theme, or with `-p` one pane; `-u` makes the
+/// pane follow the session again. Without a name, list the themes.
+fn theme(app: &mut App, args: &Args) -> Result<String, String> {
+    let id = target_pane(app, args)?;
+    if args.has('u') {
+        app.set_pane_palette(id, None);
+        return Ok(String::new());
+    }
+    let [name] = args.rest.as_slice() else {
+        if !args.rest.is_empty() {
+            return Err(usage("theme"));
+        }
+        let names: Vec<_> = palette::THEMES.iter().map(|(name, _)| *name).collect();
+        return Ok(names.join("\n"));
+    };
+    let palette = palette::theme(name).ok_or_else(|| format!("unknown theme: {name}"))?;
+    if args.has('p') || args.value('t').is_some() {
+        app.set_pane_palette(id, Some(palette));
+    } else {
+        app.set_palette(palette);
+    }
+    Ok(String::new())
+}
+
+fn zoom_pane(app: &mut App, _: &Args) -> Result<String, String> {
+    let area = app.area();
+    app.active_tab_mut().toggle_zoom(area);
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    /// `line`'s arguments, parsed as `run_line` does.
+    fn parse_line(line: &str) -> Args {
+        let (starts, words): (Vec<usize>, Vec<String>) =
+            split_words(line).unwrap().into_iter().unzip();
+        let cmd = find(&words[0]).unwrap();
+        Args::parse(cmd, &words[1..], Some((line, &starts[1..])), None).unwrap()
+    }
+
+    #[test]
+    fn quotes_group_words_and_backslashes_stay() {
+        let cases: &[(&str, &[&str])] = &[
+            ("split -h  cmd", &["split", "-h", "cmd"]),
+            (r#"a "b c" 'd "e"'"#, &["a", "b c", r#"d "e""#]),
+            (r#"x"y z"w '' """#, &["xy zw", "", ""]),
+            (r"cd C:\Users\me\", &["cd", r"C:\Users\me\"]),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(tokenize(line).unwrap(), words(expected), "{line}");
+        }
+        assert!(tokenize(r#"a "b"#).is_err());
+        assert_eq!(
+            split_words("  a 'b c'  d").unwrap(),
+            [(2, "a".into()), (4, "b c".into()), (11, "d".into())]
+        );
+    }
+
+    #[test]
+    fn command_lines_round_trip() {
+        // Typed: the rest goes on exactly as it was typed.
+        let cases = [
+            (r#"split -h cmd /c echo "ab""#, r#"cmd /c echo "ab""#),
+            (
+                r#"new-tab -c dir "C:\Program Files\x.exe"  'a b'"#,
+                r#""C:\Program Files\x.exe"  'a b'"#,
+            ),
+            (
+                r#"pipe-pane -s -- findstr "x y" > out.txt"#,
+                r#"findstr "x y" > out.txt"#,
+            ),
+        ];
+        for (line, tail) in cases {
+            assert_eq!(parse_line(line).command_line().as_deref(), Some(tail));
+        }
+        assert_eq!(parse_line("split -h").command_line(), None);
+
+        // As words: quoted so they split back into the same words.
+        let argv = words(&["cmd", "/c", "a b", r"C:\Program Files\x"]);
+        let cmd = find("split").unwrap();
+        let args = Args::parse(cmd, &argv, None, None).unwrap();
+        let cmdline = args.command_line().unwrap();
+        assert_eq!(cmdline, r#"cmd /c "a b" "C:\Program Files\x""#);
+        assert_eq!(tokenize(&cmdline).unwrap(), argv);
+    }
+
+    #[test]
+    fn flags_have_one_spelling() {
+        let cmd = find("save-session").unwrap();
+        let args = Args::parse(cmd, &words(&["-s", "f"]), None, None).unwrap();
+        assert!(args.has('s'));
+        assert_eq!(args.rest, ["f"]);
+        assert!(Args::parse(cmd, &words(&["--scrollback", "f"]), None, None).is_err());
+    }
+}
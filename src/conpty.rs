@@ -14,10 +14,21 @@ use windows::Win32::Security::SECURITY_ATTRIBUTES;
 use windows::Win32::System::Threading::{
     CreateProcessW, DeleteProcThreadAttributeList, InitializeProcThreadAttributeList,
     UpdateProcThreadAttribute, PROCESS_INFORMATION, STARTUPINFOEXW,
-    EXTENDED_STARTUPINFO_PRESENT, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+    CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
 };
 use windows::Win32::System::Memory::{HeapAlloc, HeapFree, GetProcessHeap, HEAP_ZERO_MEMORY};
 use windows::Win32::System::Threading::LPPROC_THREAD_ATTRIBUTE_LIST;
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, GetExitCodeProcess, INFINITE, TerminateProcess, WaitForSingleObject,
+};
+use windows::Win32::Foundation::{DUPLICATE_SAME_ACCESS, DuplicateHandle};
+use windows::Win32::Storage::FileSystem::{ReadFile, WriteFile};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::windows::io::FromRawHandle;
+use std::thread;
+
+use crate::pty::{Output, Pty, SpawnOptions, Spawner};
 
 pub struct TabPty {
     pub hpcon: HPCON,
@@ -27,9 +38,28 @@ pub struct TabPty {
     pub pty_out_read: HANDLE,  // read terminal output from this
 }
 
-impl TabPty {
-    pub fn resize(&self, cols: i16, rows: i16) -> windows::core::Result<()> {
-        unsafe { ResizePseudoConsole(self.hpcon, COORD { X: cols, Y: rows }) }
+impl Pty for TabPty {
+    fn write(&self, bytes: &[u8]) {
+        unsafe {
+            let mut written = 0u32;
+            let _ = WriteFile(self.pty_in_write, Some(bytes), Some(&mut written), None);
+        }
+    }
+
+    fn resize(&self, cols: u16, rows: u16) {
+        let size = COORD { X: cols as i16, Y: rows as i16 };
+        let _ = unsafe { ResizePseudoConsole(self.hpcon, size) };
+    }
+
+    fn kill(&self) {
+        let _ = unsafe { TerminateProcess(self.child_process, 0) };
+    }
+
+    // A duplicate of the input handle, so it stays valid even if the pane
+    // goes away first.
+    fn input(&self) -> io::Result<Box<dyn Write + Send>> {
+        let dup = duplicate(self.pty_in_write)?;
+        Ok(Box::new(unsafe { File::from_raw_handle(dup.0) }))
     }
 }
 
@@ -48,13 +78,31 @@ impl Drop for TabPty {
     }
 }
 
-fn to_wide(s: &str) -> Vec<u16> {
+pub fn to_wide(s: &str) -> Vec<u16> {
     OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
 }
 
-// Spawns a command line attached to a new ConPTY.
+// An environment block for CreateProcessW: NAME=value strings, sorted
+// case-insensitively by name, each NUL-terminated, with one more NUL at
+// the end.
+fn environment_block(vars: &[(String, String)]) -> Vec<u16> {
+    let mut vars: Vec<_> = vars.iter().collect();
+    vars.sort_by_key(|(name, _)| name.to_uppercase());
+    let mut block = Vec::new();
+    for (name, value) in vars {
+        block.extend(OsStr::new(&format!("{name}={value}")).encode_wide());
+        block.push(0);
+    }
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+// Spawns what `options` say, attached to a new ConPTY.
 // cols/rows are the initial pseudo console size.
-pub fn spawn_conpty(cmdline: &str, cols: i16, rows: i16) -> Result<TabPty> {
+pub fn spawn_conpty(options: &SpawnOptions, cols: i16, rows: i16) -> Result<TabPty> {
     unsafe {
         // 1) Create pipes for ConPTY
         // ConPTY needs:
@@ -132,7 +180,13 @@ pub fn spawn_conpty(cmdline: &str, cols: i16, rows: i16) -> Result<TabPty> {
 
         // 4) Spawn child process attached to ConPTY
         // CreateProcessW requires a mutable command line buffer.
-        let mut cmd = to_wide(cmdline);
+        let mut cmd = to_wide(&options.cmdline());
+        let env = environment_block(&options.environment());
+        let cwd = options.cwd.as_deref().map(to_wide);
+        let cwd_ptr = match &cwd {
+            Some(dir) => PCWSTR(dir.as_ptr()),
+            None => PCWSTR::null(),
+        };
 
         let mut pi: PROCESS_INFORMATION = std::mem::zeroed();
 
@@ -142,9 +196,9 @@ pub fn spawn_conpty(cmdline: &str, cols: i16, rows: i16) -> Result<TabPty> {
             None,
             None,
             false,                          // inherit handles
-            EXTENDED_STARTUPINFO_PRESENT,   // IMPORTANT
-            None,
-            PCWSTR::null(),
+            EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT,
+            Some(env.as_ptr() as *const c_void),
+            cwd_ptr,                        // working directory
             &si_ex.StartupInfo,
             &mut pi,
         )?;
@@ -167,3 +221,74 @@ pub fn spawn_conpty(cmdline: &str, cols: i16, rows: i16) -> Result<TabPty> {
     }
 }
 
+
+/// Runs panes in ConPTYs.
+pub struct Conpty;
+
+impl Spawner for Conpty {
+    /// Also starts the threads that read the child's output and wait for
+    /// it to exit.
+    fn spawn(
+        &self,
+        options: &SpawnOptions,
+        cols: u16,
+        rows: u16,
+        output: Output,
+    ) -> io::Result<Box<dyn Pty>> {
+        let pty = spawn_conpty(options, cols as i16, rows as i16)?;
+        watch_exit(pty.child_process, output.clone())?;
+
+        // We capture the raw value of the output handle for the reader thread.
+        let out_raw: isize = pty.pty_out_read.0 as isize;
+
+        // Reader thread: ReadFile from ConPTY → Output.
+        thread::spawn(move || {
+            let out_handle = HANDLE(out_raw as *mut c_void);
+            let mut buf = [0u8; 8192];
+
+            loop {
+                let mut read = 0u32;
+                let res = unsafe { ReadFile(out_handle, Some(&mut buf), Some(&mut read), None) };
+
+                if let Err(err) = res {
+                    eprintln!("[reader] ReadFile error: {err:?}");
+                    break;
+                }
+                if read == 0 {
+                    break;
+                }
+                if !output.send(&buf[..read as usize]) {
+                    break;
+                }
+            }
+        });
+
+        Ok(Box::new(pty))
+    }
+}
+
+fn duplicate(handle: HANDLE) -> Result<HANDLE> {
+    let mut dup = HANDLE::default();
+    unsafe {
+        let current = GetCurrentProcess();
+        DuplicateHandle(current, handle, current, &mut dup, 0, false, DUPLICATE_SAME_ACCESS)?;
+    }
+    Ok(dup)
+}
+
+/// Report the exit once the process ends. The thread waits on a duplicate
+/// of the process handle, which stays valid after the pane is dropped.
+fn watch_exit(process: HANDLE, output: Output) -> Result<()> {
+    let raw = duplicate(process)?.0 as isize;
+    thread::spawn(move || {
+        let process = HANDLE(raw as *mut c_void);
+        let mut status = 0u32;
+        unsafe {
+            WaitForSingleObject(process, INFINITE);
+            let _ = GetExitCodeProcess(process, &mut status);
+            let _ = CloseHandle(process);
+        }
+        output.exited(status);
+    });
+    Ok(())
+}
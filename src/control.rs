@@ -0,0 +1,208 @@
+// src/control.rs
+//
+// Control mode (`myux -CC`), for editors and GUI terminals that draw the
+// panes themselves. There is no screen: the front-end writes myux command
+// lines to our stdin and reads from our stdout, modelled on tmux:
+//
+//   %begin <time> <n> 1, reply lines, %end <time> <n> 1   a command's reply
+//                                     (%error instead of %end if it failed)
+//   %output %<pane> <data>            pane output, octal-escaped
+//   %window-add @<tab>                a tab appeared
+//   %window-close @<tab>
+//   %window-renamed @<tab> <name>
+//   %layout-change @<tab> <layout>    tmux layout notation
+//   %session-window-changed $0 @<tab>
+//   %message <text>
+//   %exit                             we are going away
+//
+// An empty line or the end of stdin ends the session.
+
+use crate::app::{App, PaneEvent, TabId};
+use crate::layout::PaneId;
+use crate::wake::{self, Wake};
+use crate::{command, rpc};
+use std::io::{self, BufRead, Write};
+use std::iter;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size until the front-end says otherwise with `resize-client`.
+pub const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// What the front-end was last told about a tab.
+struct TabState {
+    id: TabId,
+    name: String,
+    layout: String,
+}
+
+/// Serve the front-end until it goes away. `rx` is the main loop's
+/// channel; lines from stdin come in on it through `lines`.
+pub fn run(app: &mut App, rx: Receiver<Wake>, lines: wake::Sender<Option<String>>) {
+    read_stdin(lines);
+    let mut out = io::stdout().lock();
+    let mut known: Vec<TabState> = Vec::new();
+    let mut active = None;
+    let mut commands = 0u64;
+
+    while let Ok(first) = rx.recv() {
+        let mut quit = false;
+        for wake in iter::once(first).chain(rx.try_iter()) {
+            match wake {
+                Wake::Pane(PaneEvent::Output(id)) => {
+                    let bytes = app.pane_output(id);
+                    if !bytes.is_empty() {
+                        let _ = out.write_all(&output_line(id, &bytes));
+                    }
+                }
+                Wake::Pane(PaneEvent::Exited(id, status)) => app.pane_exited(id, status),
+                Wake::Line(Some(line)) if !line.is_empty() => {
+                    commands += 1;
+                    let _ = run_command(app, &line, commands, &mut out);
+                }
+                Wake::Line(_) => {
+                    quit = true;
+                    break;
+                }
+                Wake::Request(req) => {
+                    let result = command::run(app, &req.args, Some(req.cwd.as_ref()));
+                    let reply = match result.and_then(|msg| crate::started(app, msg)) {
+                        Ok(msg) => format!("ok {msg}"),
+                        Err(err) => format!("error: {err}"),
+                    };
+                    let _ = req.reply.send(reply);
+                }
+                Wake::Rpc(message) => rpc::handle(app, message),
+                // No console, and nobody attaches.
+                Wake::Input(_) | Wake::Attach(_) => {}
+            }
+        }
+
+        app.run_hooks();
+        if let Err(err) = crate::run_queued(app) {
+            app.message = Some(err.to_string());
+        }
+        if let Some(message) = app.message.take() {
+            let _ = writeln!(out, "%message {message}");
+        }
+        let _ = notify_changes(app, &mut known, &mut active, &mut out);
+        let _ = out.flush();
+
+        if quit {
+            break;
+        }
+    }
+
+    app.shut_down();
+    let _ = crate::run_queued(app);
+    let _ = writeln!(out, "%exit");
+    let _ = out.flush();
+}
+
+/// Send the lines of stdin, then None once it is closed.
+fn read_stdin(tx: wake::Sender<Option<String>>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx
+                .send(Some(line.trim_end_matches('\r').to_string()))
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = tx.send(None);
+    });
+}
+
+fn run_command(app: &mut App, line: &str, number: u64, out: &mut impl Write) -> io::Result<()> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let cwd = app
+        .active_tab()
+        .focused()
+        .current_dir()
+        .map(std::path::PathBuf::from);
+
+    writeln!(out, "%begin {time} {number} 1")?;
+    let result = command::run_line(app, line, cwd.as_deref());
+    let (reply, end) = match result.and_then(|msg| crate::started(app, msg)) {
+        Ok(msg) => (msg, "%end"),
+        Err(err) => (err, "%error"),
+    };
+    for reply_line in reply.lines() {
+        writeln!(out, "{reply_line}")?;
+    }
+    writeln!(out, "{end} {time} {number} 1")
+}
+
+/// Tell the front-end about tabs that appeared, went away, were renamed or
+/// changed layout since last time, and about a new active tab.
+fn notify_changes(
+    app: &App,
+    known: &mut Vec<TabState>,
+    active: &mut Option<TabId>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let area = app.area();
+    let now: Vec<TabState> = app
+        .tabs
+        .iter()
+        .map(|tab| TabState {
+            id: tab.id,
+            name: tab.name.clone(),
+            layout: tab.layout.describe(area),
+        })
+        .collect();
+
+    for old in known.iter() {
+        if !now.iter().any(|t| t.id == old.id) {
+            writeln!(out, "%window-close @{}", old.id)?;
+        }
+    }
+    for tab in &now {
+        match known.iter().find(|t| t.id == tab.id) {
+            None => {
+                writeln!(out, "%window-add @{}", tab.id)?;
+                writeln!(out, "%window-renamed @{} {}", tab.id, tab.name)?;
+                writeln!(out, "%layout-change @{} {}", tab.id, tab.layout)?;
+            }
+            Some(old) => {
+                if old.name != tab.name {
+                    writeln!(out, "%window-renamed @{} {}", tab.id, tab.name)?;
+                }
+                if old.layout != tab.layout {
+                    writeln!(out, "%layout-change @{} {}", tab.id, tab.layout)?;
+                }
+            }
+        }
+    }
+
+    let current = app.active_tab().id;
+    if *active != Some(current) {
+        writeln!(out, "%session-window-changed $0 @{current}")?;
+        *active = Some(current);
+    }
+    *known = now;
+    Ok(())
+}
+
+/// `%output` line for a chunk of pane output: control characters and
+/// backslashes become `\ooo` like in tmux, other bytes (UTF-8 included)
+/// pass through, even when a character is split between chunks.
+fn output_line(id: PaneId, bytes: &[u8]) -> Vec<u8> {
+    let mut line = format!("%output %{id} ").into_bytes();
+    for &b in bytes {
+        if b < b' ' || b == b'\\' {
+            line.extend_from_slice(format!("\\{b:03o}").as_bytes());
+        } else {
+            line.push(b);
+        }
+    }
+    line.push(b'\n');
+    line
+}
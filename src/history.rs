@@ -0,0 +1,239 @@
+// src/history.rs
+//
+// Scrollback that no longer fits in the vt100 parser. Rows pushed out of the
+// in-memory history are appended to a per-pane segment file as deflate
+// compressed blocks; the block index stays in memory so any row can be read
+// back for scrolling, search and capture.
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Dropped blocks at the start of the file are only reclaimed once there
+/// is at least this much of them.
+const COMPACT_MIN: u64 = 1024 * 1024;
+
+/// Numbers the segment files of this process, so two histories never
+/// share one even when their names do (several apps in one test run).
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// How much spilled history to keep per pane.
+#[derive(Clone, Copy, Debug)]
+pub struct HistoryLimits {
+    pub max_lines: usize,
+    /// Compressed size on disk.
+    pub max_bytes: u64,
+}
+
+impl HistoryLimits {
+    /// Read `MYUX_HISTORY_LINES` / `MYUX_HISTORY_BYTES`, defaulting to a
+    /// million rows and 64 MiB. Zero turns spilling off.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        HistoryLimits {
+            max_lines: var("MYUX_HISTORY_LINES").unwrap_or(1_000_000) as usize,
+            max_bytes: var("MYUX_HISTORY_BYTES").unwrap_or(64 * 1024 * 1024),
+        }
+    }
+}
+
+struct Block {
+    /// Serial number of the first row, counting every row ever pushed.
+    start: usize,
+    rows: usize,
+    /// Pane width when the rows were written.
+    cols: u16,
+    offset: u64,
+    len: u64,
+}
+
+pub struct History {
+    path: PathBuf,
+    file: Option<File>,
+    limits: HistoryLimits,
+    blocks: VecDeque<Block>,
+    /// Rows dropped from the front because of the limits.
+    dropped: usize,
+    rows: usize,
+    /// Compressed size of the blocks still kept.
+    bytes: u64,
+    /// End of the file, where the next block goes.
+    end: u64,
+    /// Rows of the last block read, keyed by its `start`.
+    cache: Option<(usize, Vec<Vec<u8>>)>,
+}
+
+impl History {
+    /// History for one pane; the segment file `<name>-<n>.hist` lives in a
+    /// per-process directory under the temp dir and is only created once
+    /// something spills.
+    pub fn new(name: &str, limits: HistoryLimits) -> Self {
+        let dir = std::env::temp_dir().join(format!("myux-{}", std::process::id()));
+        let n = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        History {
+            path: dir.join(format!("{name}-{n}.hist")),
+            file: None,
+            limits,
+            blocks: VecDeque::new(),
+            dropped: 0,
+            rows: 0,
+            bytes: 0,
+            end: 0,
+            cache: None,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: HistoryLimits) {
+        self.limits = limits;
+        self.enforce_limits();
+    }
+
+    /// Number of rows kept.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// Append formatted rows (oldest first) that were `cols` wide. If the
+    /// file can't be written the rows are lost, like they would have been
+    /// without a history file.
+    pub fn push(&mut self, rows: Vec<Vec<u8>>, cols: u16) {
+        if rows.is_empty() || self.limits.max_lines == 0 || self.limits.max_bytes == 0 {
+            return;
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        let _ = encoder.write_all(&rows.join(&b'\n'));
+        let Ok(data) = encoder.finish() else {
+            return;
+        };
+        if self.write_at(self.end, &data).is_err() {
+            return;
+        }
+
+        self.blocks.push_back(Block {
+            start: self.dropped + self.rows,
+            rows: rows.len(),
+            cols,
+            offset: self.end,
+            len: data.len() as u64,
+        });
+        self.end += data.len() as u64;
+        self.rows += rows.len();
+        self.bytes += data.len() as u64;
+        self.enforce_limits();
+    }
+
+    /// Row `idx` (0 = oldest kept) as formatted text, with the width it
+    /// was written at.
+    pub fn row(&mut self, idx: usize) -> Option<(&[u8], u16)> {
+        if idx >= self.rows {
+            return None;
+        }
+        let serial = self.dropped + idx;
+        let pos = self.blocks.partition_point(|b| b.start + b.rows <= serial);
+        let block = self.blocks.get(pos)?;
+        let (start, cols) = (block.start, block.cols);
+
+        if self.cache.as_ref().is_none_or(|(s, _)| *s != start) {
+            let rows = self.read_block(pos)?;
+            self.cache = Some((start, rows));
+        }
+        let (_, rows) = self.cache.as_ref()?;
+        rows.get(serial - start).map(|r| (r.as_slice(), cols))
+    }
+
+    fn read_block(&mut self, pos: usize) -> Option<Vec<Vec<u8>>> {
+        let (offset, len) = (self.blocks[pos].offset, self.blocks[pos].len);
+        let file = self.file.as_mut()?;
+        let mut data = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        file.read_exact(&mut data).ok()?;
+
+        let mut text = Vec::new();
+        DeflateDecoder::new(data.as_slice())
+            .read_to_end(&mut text)
+            .ok()?;
+        Some(text.split(|&b| b == b'\n').map(<[u8]>::to_vec).collect())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().expect("file just opened");
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    /// Drop whole blocks from the front until we are within the limits, and
+    /// reclaim the space once it is worth it.
+    fn enforce_limits(&mut self) {
+        while self.rows > self.limits.max_lines || self.bytes > self.limits.max_bytes {
+            let Some(block) = self.blocks.pop_front() else {
+                break;
+            };
+            self.rows -= block.rows;
+            self.bytes -= block.len;
+            self.dropped += block.rows;
+        }
+
+        let live_start = self.blocks.front().map_or(self.end, |b| b.offset);
+        if live_start >= COMPACT_MIN && live_start > self.bytes {
+            self.compact(live_start);
+        }
+    }
+
+    /// Move the kept blocks to the start of the file.
+    fn compact(&mut self, live_start: u64) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut live = Vec::new();
+        let moved = file
+            .seek(SeekFrom::Start(live_start))
+            .and_then(|_| file.read_to_end(&mut live))
+            .and_then(|_| file.set_len(0))
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(&live));
+
+        if moved.is_err() {
+            // The file is in an unknown state; start over.
+            self.blocks.clear();
+            self.dropped += self.rows;
+            self.rows = 0;
+            self.bytes = 0;
+            self.end = 0;
+            return;
+        }
+        for block in &mut self.blocks {
+            block.offset -= live_start;
+        }
+        self.end -= live_start;
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+            if let Some(dir) = self.path.parent() {
+                // Only succeeds once the last pane is gone.
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// src/hooks.rs
+//
+// Hooks: myux commands run when something happens. Events are queued where
+// they happen and the main loop runs their hooks once it is done with the
+// event at hand; the context of an event (tab, pane, exit status, ...) is
+// handed to programs started by `run-shell` as MYUX_* environment variables.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hook {
+    TabCreated,
+    TabClosed,
+    PaneExited,
+    ClientAttached,
+    ClientDetached,
+    Resized,
+    Bell,
+    FocusChanged,
+}
+
+impl Hook {
+    pub const ALL: &[Hook] = &[
+        Hook::TabCreated,
+        Hook::TabClosed,
+        Hook::PaneExited,
+        Hook::ClientAttached,
+        Hook::ClientDetached,
+        Hook::Resized,
+        Hook::Bell,
+        Hook::FocusChanged,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::TabCreated => "tab-created",
+            Hook::TabClosed => "tab-closed",
+            Hook::PaneExited => "pane-exited",
+            Hook::ClientAttached => "client-attached",
+            Hook::ClientDetached => "client-detached",
+            Hook::Resized => "resized",
+            Hook::Bell => "bell",
+            Hook::FocusChanged => "focus-changed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Hook> {
+        Hook::ALL.iter().copied().find(|h| h.name() == name)
+    }
+}
+
+/// An event waiting for its hooks to run.
+pub struct Fired {
+    pub hook: Hook,
+    /// `MYUX_*` variables describing it.
+    pub env: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    table: HashMap<Hook, Vec<Vec<String>>>,
+    queue: Vec<Fired>,
+}
+
+impl Hooks {
+    /// Make `command` the hook for `hook`, or with `append` one more.
+    pub fn set(&mut self, hook: Hook, command: Vec<String>, append: bool) {
+        let commands = self.table.entry(hook).or_default();
+        if !append {
+            commands.clear();
+        }
+        commands.push(command);
+    }
+
+    pub fn unset(&mut self, hook: Hook) {
+        self.table.remove(&hook);
+    }
+
+    pub fn commands(&self, hook: Hook) -> &[Vec<String>] {
+        self.table.get(&hook).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every hook as `event: command`, on one line.
+    pub fn list(&self) -> String {
+        let mut lines = Vec::new();
+        for &hook in Hook::ALL {
+            for command in self.commands(hook) {
+                lines.push(format!("{}: {}", hook.name(), command.join(" ")));
+            }
+        }
+        lines.join("; ")
+    }
+
+    /// Queue `hook` if anything is hooked to it.
+    pub fn fire(&mut self, hook: Hook, env: Vec<(String, String)>) {
+        if self.table.contains_key(&hook) {
+            self.queue.push(Fired { hook, env });
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<Fired> {
+        std::mem::take(&mut self.queue)
+    }
+}
@@ -0,0 +1,314 @@
+// src/ipc.rs
+//
+// Control channel between `myux <command>` and the running myux: a named
+// pipe per user. The client writes one request line (its working directory
+// and then the arguments, separated by tabs, which can't appear in Windows
+// paths) and reads back one reply line, `ok ...` or `error: ...`.
+//
+// Also the plumbing of the pipes that serve several clients at once (RPC,
+// attached clients), further down.
+
+use crate::conpty::to_wide;
+use crate::wake;
+use core::ffi::c_void;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::IntoRawHandle;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use windows::Win32::Foundation::{CloseHandle, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, FlushFileBuffers, PIPE_ACCESS_DUPLEX,
+    ReadFile, WriteFile,
+};
+use windows::Win32::System::IO::{GetOverlappedResult, OVERLAPPED};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::CreateEventW;
+use windows::core::PCWSTR;
+
+/// Longest request line we accept.
+const MAX_REQUEST: usize = 64 * 1024;
+const ERROR_PIPE_BUSY: i32 = 231;
+
+/// A request from a client, handed to the main loop. Whatever is sent on
+/// `reply` goes back to the client.
+pub struct Request {
+    /// The client's working directory, for relative paths.
+    pub cwd: String,
+    pub args: Vec<String>,
+    pub reply: mpsc::Sender<String>,
+}
+
+pub fn pipe_name() -> String {
+    let user = std::env::var("USERNAME").unwrap_or_else(|_| "default".into());
+    format!(r"\\.\pipe\myux-{user}")
+}
+
+/// Serve the pipe on a background thread, one client at a time. Fails when
+/// another myux already owns it.
+pub fn serve(tx: wake::Sender<Request>) -> windows::core::Result<()> {
+    let name = to_wide(&pipe_name());
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(name.as_ptr()),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            4096,
+            4096,
+            0,
+            None,
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(windows::core::Error::from_win32());
+    }
+
+    // Same trick as the pane reader threads: HANDLE isn't Send.
+    let raw = pipe.0 as isize;
+    thread::spawn(move || {
+        let pipe = HANDLE(raw as *mut c_void);
+        while serve_client(pipe, &tx) {}
+        unsafe {
+            let _ = CloseHandle(pipe);
+        }
+    });
+    Ok(())
+}
+
+/// Wait for a client and answer its request. Returns false once the main
+/// loop has gone away.
+fn serve_client(pipe: HANDLE, tx: &wake::Sender<Request>) -> bool {
+    unsafe {
+        if let Err(e) = ConnectNamedPipe(pipe, None) {
+            // A client that connected between our calls is fine.
+            if e.code() != ERROR_PIPE_CONNECTED.to_hresult() {
+                return false;
+            }
+        }
+    }
+
+    let mut keep_going = true;
+    if let Some(line) = read_line(pipe) {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let mut fields = line.split('\t').map(str::to_string);
+        let request = Request {
+            cwd: fields.next().unwrap_or_default(),
+            args: fields.collect(),
+            reply: reply_tx,
+        };
+        let reply = if tx.send(request).is_ok() {
+            reply_rx
+                .recv()
+                .unwrap_or_else(|_| "error: no reply".to_string())
+        } else {
+            keep_going = false;
+            "error: myux is shutting down".to_string()
+        };
+
+        unsafe {
+            let mut written = 0u32;
+            let _ = WriteFile(
+                pipe,
+                Some(format!("{reply}\n").as_bytes()),
+                Some(&mut written),
+                None,
+            );
+            let _ = FlushFileBuffers(pipe);
+        }
+    }
+
+    unsafe {
+        let _ = DisconnectNamedPipe(pipe);
+    }
+    keep_going
+}
+
+fn read_line(pipe: HANDLE) -> Option<String> {
+    let mut line = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !line.contains(&b'\n') {
+        let mut read = 0u32;
+        let res = unsafe { ReadFile(pipe, Some(&mut buf), Some(&mut read), None) };
+        if res.is_err() || read == 0 || line.len() > MAX_REQUEST {
+            return None;
+        }
+        line.extend_from_slice(&buf[..read as usize]);
+    }
+
+    let end = line.iter().position(|&b| b == b'\n')?;
+    let line = String::from_utf8(line[..end].to_vec()).ok()?;
+    Some(line.trim_end_matches('\r').to_string())
+}
+
+/// Send `args` to the running myux and return its reply. Fails if no myux
+/// is listening.
+pub fn send(cwd: &str, args: &[String]) -> io::Result<String> {
+    let mut pipe = connect(&pipe_name(), false)?;
+    pipe.write_all(format!("{cwd}\t{}\n", args.join("\t")).as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(pipe).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+/// Open the client end of pipe `name`, waiting while every instance is
+/// busy. `overlapped` is for `Pipe`.
+pub fn connect(name: &str, overlapped: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true);
+    if overlapped {
+        options.custom_flags(FILE_FLAG_OVERLAPPED.0);
+    }
+    let mut attempts = 0;
+    loop {
+        match options.open(name) {
+            Ok(pipe) => return Ok(pipe),
+            // Another client is being served; wait our turn.
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) && attempts < 40 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Serve pipe `name` on a background thread, any number of clients at a
+/// time: `on_client` gets each one as it connects. Fails when another myux
+/// already owns the name.
+pub fn listen(
+    name: &str,
+    mut on_client: impl FnMut(Pipe) + Send + 'static,
+) -> windows::core::Result<()> {
+    let first = create_instance(name, true)?;
+    let name = name.to_string();
+    thread::spawn(move || {
+        let mut pipe = Some(first);
+        loop {
+            let current = match pipe.take() {
+                Some(pipe) => pipe,
+                None => match create_instance(&name, false) {
+                    Ok(pipe) => pipe,
+                    Err(_) => return,
+                },
+            };
+            if current.wait_for_client().is_ok() {
+                on_client(current);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn create_instance(name: &str, first: bool) -> windows::core::Result<Pipe> {
+    let name = to_wide(name);
+    let mut mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+    if first {
+        mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(name.as_ptr()),
+            mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            64 * 1024,
+            64 * 1024,
+            0,
+            None,
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(windows::core::Error::from_win32());
+    }
+    Ok(Pipe(pipe.0 as isize))
+}
+
+/// Either end of a pipe, opened for overlapped I/O so that one thread can
+/// read while another writes (a plain handle does one at a time). HANDLE
+/// isn't Send, so it is kept as a number like the pane reader threads do.
+pub struct Pipe(isize);
+
+impl Pipe {
+    /// Connect to the server of pipe `name`.
+    pub fn open(name: &str) -> io::Result<Pipe> {
+        let file = connect(name, true)?;
+        Ok(Pipe(file.into_raw_handle() as isize))
+    }
+
+    fn handle(&self) -> HANDLE {
+        HANDLE(self.0 as *mut c_void)
+    }
+
+    fn wait_for_client(&self) -> windows::core::Result<()> {
+        let connected = self.run(|ov| unsafe { ConnectNamedPipe(self.handle(), Some(ov)) });
+        match connected {
+            // A client that connected before we started waiting is fine.
+            Err(e) if e.code() != ERROR_PIPE_CONNECTED.to_hresult() => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read whatever arrives next. Fails once the other end is gone.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.run(|ov| unsafe { ReadFile(self.handle(), Some(buf), None, Some(ov)) })?;
+        match read {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            n => Ok(n as usize),
+        }
+    }
+
+    pub fn write_all(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut sent = 0;
+        while sent < bytes.len() {
+            let chunk = &bytes[sent..];
+            let n =
+                self.run(|ov| unsafe { WriteFile(self.handle(), Some(chunk), None, Some(ov)) })?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            sent += n as usize;
+        }
+        Ok(())
+    }
+
+    /// Start an operation with `start` and wait for it to finish, with an
+    /// event of its own so other threads can use the pipe meanwhile.
+    /// Returns the number of bytes transferred.
+    fn run(
+        &self,
+        start: impl FnOnce(*mut OVERLAPPED) -> windows::core::Result<()>,
+    ) -> windows::core::Result<u32> {
+        let event = unsafe { CreateEventW(None, true, false, None)? };
+        let mut overlapped = OVERLAPPED {
+            hEvent: event,
+            ..Default::default()
+        };
+        let mut result = start(&mut overlapped);
+        if matches!(&result, Err(e) if e.code() == ERROR_IO_PENDING.to_hresult()) {
+            result = Ok(());
+        }
+        let mut done = 0u32;
+        if result.is_ok() {
+            result = unsafe { GetOverlappedResult(self.handle(), &overlapped, &mut done, true) };
+        }
+        unsafe {
+            let _ = CloseHandle(event);
+        }
+        result.map(|()| done)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.handle());
+        }
+    }
+}
@@ -0,0 +1,275 @@
+// src/layout.rs
+//
+// Geometry of the panes inside a tab: a binary tree of splits whose leaves
+// are pane ids. Each split leaves one row/column between its halves for the
+// border.
+
+use serde::{Deserialize, Serialize};
+
+pub type PaneId = usize;
+
+/// Smallest pane we are willing to create by splitting.
+const MIN_PANE_COLS: u16 = 2;
+const MIN_PANE_ROWS: u16 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Rect {
+    pub fn new(x: u16, y: u16, cols: u16, rows: u16) -> Self {
+        Rect { x, y, cols, rows }
+    }
+
+    fn right(&self) -> u16 {
+        self.x + self.cols
+    }
+
+    fn bottom(&self) -> u16 {
+        self.y + self.rows
+    }
+
+    /// Cut into two halves plus the one-cell border between them.
+    fn split(&self, dir: Split) -> (Rect, Rect, Rect) {
+        match dir {
+            Split::Horizontal => {
+                let left = self.cols.saturating_sub(1) / 2;
+                let right = self.cols.saturating_sub(left + 1);
+                (
+                    Rect::new(self.x, self.y, left, self.rows),
+                    Rect::new(self.x + left, self.y, 1, self.rows),
+                    Rect::new(self.x + left + 1, self.y, right, self.rows),
+                )
+            }
+            Split::Vertical => {
+                let top = self.rows.saturating_sub(1) / 2;
+                let bottom = self.rows.saturating_sub(top + 1);
+                (
+                    Rect::new(self.x, self.y, self.cols, top),
+                    Rect::new(self.x, self.y + top, self.cols, 1),
+                    Rect::new(self.x, self.y + top + 1, self.cols, bottom),
+                )
+            }
+        }
+    }
+}
+
+/// Split direction, named like tmux: `Horizontal` puts the panes side by
+/// side, `Vertical` stacks them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    Horizontal,
+    Vertical,
+}
+
+/// A one-cell-wide line separating two halves of a split.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Border {
+    pub rect: Rect,
+    pub dir: Split,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Debug)]
+pub enum Layout {
+    Pane(PaneId),
+    Split {
+        dir: Split,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Pane ids in reading order (left to right, top to bottom).
+    pub fn panes(&self) -> Vec<PaneId> {
+        let mut out = Vec::new();
+        self.collect_panes(&mut out);
+        out
+    }
+
+    fn collect_panes(&self, out: &mut Vec<PaneId>) {
+        match self {
+            Layout::Pane(id) => out.push(*id),
+            Layout::Split { first, second, .. } => {
+                first.collect_panes(out);
+                second.collect_panes(out);
+            }
+        }
+    }
+
+    /// Where every pane goes when the layout fills `area`.
+    pub fn rects(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        let mut out = Vec::new();
+        self.walk(area, &mut |node, rect| {
+            if let Layout::Pane(id) = node {
+                out.push((*id, rect));
+            }
+        });
+        out
+    }
+
+    /// The borders between split halves when the layout fills `area`.
+    pub fn borders(&self, area: Rect) -> Vec<Border> {
+        let mut out = Vec::new();
+        self.walk(area, &mut |node, rect| {
+            if let Layout::Split { dir, .. } = node {
+                let (_, border, _) = rect.split(*dir);
+                out.push(Border {
+                    rect: border,
+                    dir: *dir,
+                });
+            }
+        });
+        out
+    }
+
+    /// The layout in tmux's notation, checksum included:
+    /// `WxH,X,Y,pane` for a pane, `WxH,X,Y{...}` for side by side halves and
+    /// `WxH,X,Y[...]` for stacked ones.
+    pub fn describe(&self, area: Rect) -> String {
+        let body = self.describe_node(area);
+        let mut sum: u16 = 0;
+        for b in body.bytes() {
+            sum = (sum >> 1) + ((sum & 1) << 15);
+            sum = sum.wrapping_add(b as u16);
+        }
+        format!("{sum:04x},{body}")
+    }
+
+    fn describe_node(&self, area: Rect) -> String {
+        let geometry = format!("{}x{},{},{}", area.cols, area.rows, area.x, area.y);
+        match self {
+            Layout::Pane(id) => format!("{geometry},{id}"),
+            Layout::Split { dir, first, second } => {
+                let (a, _, b) = area.split(*dir);
+                let (open, close) = match dir {
+                    Split::Horizontal => ('{', '}'),
+                    Split::Vertical => ('[', ']'),
+                };
+                format!(
+                    "{geometry}{open}{},{}{close}",
+                    first.describe_node(a),
+                    second.describe_node(b)
+                )
+            }
+        }
+    }
+
+    fn walk(&self, area: Rect, f: &mut impl FnMut(&Layout, Rect)) {
+        f(self, area);
+        if let Layout::Split { dir, first, second } = self {
+            let (a, _, b) = area.split(*dir);
+            first.walk(a, f);
+            second.walk(b, f);
+        }
+    }
+
+    /// Take pane `id` out; its sibling gets the space. Returns false if the
+    /// pane isn't there or is the only one.
+    pub fn remove(&mut self, id: PaneId) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+        if matches!(**first, Layout::Pane(p) if p == id) {
+            *self = (**second).clone();
+            true
+        } else if matches!(**second, Layout::Pane(p) if p == id) {
+            *self = (**first).clone();
+            true
+        } else {
+            first.remove(id) || second.remove(id)
+        }
+    }
+
+    /// Replace pane `target` with a split of itself and `new` (which goes
+    /// right/below). Returns false if the pane doesn't exist or would end
+    /// up too small to be usable.
+    pub fn split(&mut self, area: Rect, target: PaneId, dir: Split, new: PaneId) -> bool {
+        let Some(rect) = self
+            .rects(area)
+            .into_iter()
+            .find(|(id, _)| *id == target)
+            .map(|(_, r)| r)
+        else {
+            return false;
+        };
+
+        let (a, _, b) = rect.split(dir);
+        let min = |r: Rect| r.cols >= MIN_PANE_COLS && r.rows >= MIN_PANE_ROWS;
+        if !min(a) || !min(b) {
+            return false;
+        }
+
+        self.replace(target, dir, new);
+        true
+    }
+
+    fn replace(&mut self, target: PaneId, dir: Split, new: PaneId) -> bool {
+        match self {
+            Layout::Pane(id) if *id == target => {
+                *self = Layout::Split {
+                    dir,
+                    first: Box::new(Layout::Pane(target)),
+                    second: Box::new(Layout::Pane(new)),
+                };
+                true
+            }
+            Layout::Pane(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.replace(target, dir, new) || second.replace(target, dir, new)
+            }
+        }
+    }
+
+    /// The pane next to `from` in direction `dir`, preferring the one that
+    /// overlaps it the most.
+    pub fn neighbour(&self, area: Rect, from: PaneId, dir: Direction) -> Option<PaneId> {
+        let rects = self.rects(area);
+        let (_, cur) = *rects.iter().find(|(id, _)| *id == from)?;
+
+        rects
+            .iter()
+            .filter(|(id, _)| *id != from)
+            .filter_map(|(id, r)| {
+                // Panes are one border apart from their neighbours.
+                let (adjacent, overlap) = match dir {
+                    Direction::Left => (
+                        r.right() + 1 == cur.x,
+                        overlap(r.y, r.bottom(), cur.y, cur.bottom()),
+                    ),
+                    Direction::Right => (
+                        cur.right() + 1 == r.x,
+                        overlap(r.y, r.bottom(), cur.y, cur.bottom()),
+                    ),
+                    Direction::Up => (
+                        r.bottom() + 1 == cur.y,
+                        overlap(r.x, r.right(), cur.x, cur.right()),
+                    ),
+                    Direction::Down => (
+                        cur.bottom() + 1 == r.y,
+                        overlap(r.x, r.right(), cur.x, cur.right()),
+                    ),
+                };
+                (adjacent && overlap > 0).then_some((*id, overlap))
+            })
+            .max_by_key(|(_, overlap)| *overlap)
+            .map(|(id, _)| id)
+    }
+}
+
+fn overlap(a0: u16, a1: u16, b0: u16, b1: u16) -> u16 {
+    a1.min(b1).saturating_sub(a0.max(b0))
+}
@@ -1,69 +1,175 @@
 // src/main.rs
+mod app;
+mod attach;
+mod chooser;
+mod clients;
+mod command;
 mod conpty;
+mod control;
+mod history;
+mod hooks;
+mod ipc;
+mod layout;
+mod palette;
+mod pipe;
+mod prompt;
+mod pty;
+mod reflow;
+mod rpc;
+mod rpc_client;
+mod script;
+mod session;
 mod terminal;
+#[cfg(test)]
+mod testing;
 mod renderer;
+mod viewer;
+mod wake;
+
+use app::{Action, App, Mode, PaneEvent, SHELL};
+use clients::{Access, ClientId, LOCAL};
+use conpty::Conpty;
+use hooks::Hook;
+use layout::PaneId;
+use renderer::{ChooserView, Overlay, PaneView, Renderer};
+use session::Session;
+use wake::Wake;
 
-use conpty::{spawn_conpty, TabPty};
-use renderer::Renderer;
-use terminal::VirtualTerminal;
-
-use core::ffi::c_void;
 use crossterm::{
     cursor,
     event::{
-        self,
         EnableMouseCapture,
         DisableMouseCapture,
+        EnableBracketedPaste,
+        DisableBracketedPaste,
         Event,
-        KeyCode,
-        KeyEvent,
-        KeyEventKind,
-        MouseEvent,
-        MouseEventKind,
     },
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use std::io;
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::mpsc;
-use std::thread;
-use std::time::Duration;
-use windows::Win32::Foundation::HANDLE;
-use windows::Win32::Storage::FileSystem::{ReadFile, WriteFile};
+use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthStr;
 use windows::Win32::System::Console::{
     GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, SetConsoleScreenBufferSize,
     SetConsoleMode, CONSOLE_SCREEN_BUFFER_INFO, CONSOLE_MODE,
     ENABLE_PROCESSED_OUTPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_OUTPUT_HANDLE,
 };
 use windows::Win32::System::Console::COORD;
-use windows::Win32::System::Threading::TerminateProcess;
 
-struct Tab {
-    pty: TabPty,
-    term: VirtualTerminal,
+/// Output between two frames that makes it a flood.
+const FLOOD_BYTES: usize = 256 * 1024;
+const FLOOD_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `myux <command...>`: run a command in the running myux, print what it
+/// says and exit. `load` with nobody listening returns the session so this
+/// process starts with it instead.
+fn run_client(args: &[String]) -> Option<Session> {
+    if args.is_empty() {
+        return None;
+    }
+    // Relative paths are resolved by the server against our directory.
+    let cwd = std::env::current_dir()
+        .map(|d| d.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    match ipc::send(&cwd, args) {
+        Ok(reply) => {
+            if let Some(err) = reply.strip_prefix("error: ") {
+                eprintln!("myux: {err}");
+                std::process::exit(1);
+            }
+            let msg = reply.strip_prefix("ok").unwrap_or(&reply).trim_start();
+            if !msg.is_empty() {
+                println!("{msg}");
+            }
+            std::process::exit(0);
+        }
+        Err(_) if args.len() == 2 && args[0] == "load" => match Session::load(Path::new(&args[1])) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                eprintln!("myux: {}: {e}", args[1]);
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!("myux: no running myux to talk to ({e})");
+            std::process::exit(1);
+        }
+    }
 }
 
-const SCROLL_STEP: u16 = 5;
+/// Status bar text, plus the column the cursor goes to when it belongs in
+/// the status bar (while the command prompt is open).
+fn status_line(app: &App) -> (String, Option<u16>) {
+    if let Mode::Command(prompt) = &app.mode {
+        let line = format!(":{}", prompt.input);
+        let cursor = line.width() as u16;
+        if prompt.hint.is_empty() {
+            return (line, Some(cursor));
+        }
+        return (format!("{line}  [{}]", prompt.hint), Some(cursor));
+    }
+    if let Mode::Choose(chooser) = &app.mode {
+        let line = format!("choose: {}", chooser.filter);
+        let cursor = line.width() as u16;
+        return (line, Some(cursor));
+    }
+    // A command's message stays up until the next key.
+    if let Some(message) = &app.message {
+        return (message.clone(), None);
+    }
 
-enum Mode {
-    Normal,
-    Scrollback,
-}
+    // Build status line (include mode).
+    let mode_str = match &app.mode {
+        Mode::Normal => "normal".to_string(),
+        Mode::Prefix => "prefix".to_string(),
+        Mode::Scrollback => "scroll".to_string(),
+        Mode::Search(query) => format!("search /{query}"),
+        Mode::Command(_) => "command".to_string(),
+        Mode::Choose(_) => "choose".to_string(),
+    };
 
-struct App {
-    tabs: Vec<Tab>,
-    active: usize,
-    mode: Mode,
-    last_key: Option<(KeyCode, KeyEventKind)>,
-}
+    let tab = app.active_tab();
+    let pane_pos = tab
+        .layout
+        .panes()
+        .iter()
+        .position(|&id| id == tab.focus)
+        .unwrap_or(0);
+    let zoom_str = if tab.zoomed { " (zoomed)" } else { "" };
+    let sync_str = if tab.sync {
+        format!(" | SYNC {}/{}", tab.synced_count(), tab.panes.len())
+    } else {
+        String::new()
+    };
 
-impl App {
-    fn active_tab(&self) -> &Tab {
-        &self.tabs[self.active]
-    }
+    let pipe_str = match tab.focused().pipe_label() {
+        Some(label) => format!(" | PIPE {label}"),
+        None => String::new(),
+    };
 
-    fn active_tab_mut(&mut self) -> &mut Tab {
-        &mut self.tabs[self.active]
-    }
+    let script_str = match app.scripts.status.as_str() {
+        "" => String::new(),
+        status => format!(" | {status}"),
+    };
+
+    let status_line = format!(
+        "[myux] tab {}/{} {} | pane {}/{}{}{}{}{} | mode: {} | ^B: prefix | F10: quit",
+        app.active + 1,
+        app.tabs.len(),
+        tab.name,
+        pane_pos + 1,
+        tab.panes.len(),
+        zoom_str,
+        sync_str,
+        pipe_str,
+        script_str,
+        mode_str,
+    );
+
+    (status_line, None)
 }
 
 /// Enable VT sequences on host console.
@@ -90,8 +196,8 @@ fn clamp_console_buffer_to_window() {
                 let width = info.srWindow.Right - info.srWindow.Left + 1;
                 let height = info.srWindow.Bottom - info.srWindow.Top + 1;
                 let size = COORD {
-                    X: width as i16,
-                    Y: height as i16,
+                    X: width,
+                    Y: height,
                 };
                 let _ = SetConsoleScreenBufferSize(h, size);
             }
@@ -110,62 +216,234 @@ fn console_size() -> (u16, u16) {
     })
 }
 
-/// Write bytes to ConPTY input.
-fn write_all(handle: HANDLE, bytes: &[u8]) {
-    unsafe {
-        let mut written = 0u32;
-        let _ = WriteFile(handle, Some(bytes), Some(&mut written), None);
+/// Carry out what `App::handle_event` asked for on behalf of `client`.
+/// Returns whether to redraw, or None when asked to quit.
+fn execute(app: &mut App, client: ClientId, actions: Vec<Action>) -> Option<bool> {
+    let mut redraw = false;
+    for action in actions {
+        match action {
+            Action::Write(id, bytes) => {
+                if let Some(pane) = app.pane(id) {
+                    pane.pty.write(&bytes);
+                }
+            }
+            Action::Spawn { panes } => {
+                if let Err(err) = spawn(app, &panes) {
+                    app.message = Some(err.to_string());
+                }
+            }
+            Action::Kill(id) => kill(app, id),
+            Action::Command(words) => app.run_command(&words),
+            Action::CommandLine(line) => app.run_command_line(&line),
+            Action::Resize(cols, rows) => app.clients.resize(client, (cols, rows)),
+            Action::Redraw => redraw = true,
+            Action::Quit => return None,
+        }
+        // The processes a command starts and ends, right after it.
+        if let Err(err) = run_queued(app) {
+            app.message = Some(err.to_string());
+        }
     }
+    app.apply_size_policy();
+    Some(redraw)
 }
 
-fn main() -> windows::core::Result<()> {
-    // 1) Enable VT on host console and clamp buffer to window.
-    enable_vt_mode();
-    clamp_console_buffer_to_window();
-    let (cols, rows) = console_size();
-
-    // 2) Spawn a single ConPTY-backed cmd.exe.
-    // println!("Spawning ConPTY {}x{}...", cols, rows);
-    eprintln!("Spawning ConPTY {}x{}...", cols, rows);
-    let pty = spawn_conpty("cmd.exe", cols as i16, rows as i16)?;
-
-    // We capture the raw value of the output handle for the reader thread.
-    let out_raw: isize = pty.pty_out_read.0 as isize;
-
-    let term = VirtualTerminal::new(cols, rows);
-    let app = App {
-        tabs: vec![Tab { pty, term }],
-        active: 0,
-        mode: Mode::Normal,
-        last_key: None,
+/// Start and end the processes `app` has queued, for whoever ran a
+/// command and waits to hear whether it worked. Fails with the first
+/// pane that couldn't be started.
+fn run_queued(app: &mut App) -> io::Result<()> {
+    let mut started = Ok(());
+    for action in app.take_queued() {
+        match action {
+            Action::Spawn { panes } => started = started.and(spawn(app, &panes)),
+            Action::Kill(id) => kill(app, id),
+            // Only processes are queued.
+            _ => {}
+        }
+    }
+    started
+}
+
+/// `msg` from a command someone waits on, once the processes it queued
+/// are started and ended; or why one of them couldn't be started.
+fn started(app: &mut App, msg: String) -> Result<String, String> {
+    run_queued(app).map(|()| msg).map_err(|e| e.to_string())
+}
+
+/// Start the processes of `panes`. If one can't be started, the ones
+/// started before it end again and the app lets go of all of them.
+fn spawn(app: &mut App, panes: &[PaneId]) -> io::Result<()> {
+    for (i, &id) in panes.iter().enumerate() {
+        // Closed again before it got to run.
+        let Some(pane) = app.pane(id) else {
+            continue;
+        };
+        let (cols, rows) = pane.term.size();
+        let options = app.spawn_options(pane);
+        match app.spawner.spawn(&options, cols, rows, app.output(pane)) {
+            Ok(pty) => {
+                let pane = app.pane_mut(id).unwrap();
+                let input = pane.pty.waiting_input();
+                pane.pty = pty;
+                if !input.is_empty() {
+                    pane.pty.write(&input);
+                }
+            }
+            Err(err) => {
+                for pane in panes[..i].iter().filter_map(|&id| app.pane(id)) {
+                    pane.pty.kill();
+                }
+                app.discard(panes);
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// End pane `id`'s process, whether the app let go of the pane or, when
+/// myux quits, still has it.
+fn kill(app: &mut App, id: PaneId) {
+    let closing = app.take_closing(id);
+    if let Some(pane) = closing.as_ref().or_else(|| app.pane(id)) {
+        pane.pty.kill();
+    }
+}
+
+fn is_read_only(app: &App, id: ClientId) -> bool {
+    app.clients.get(id).is_some_and(|c| c.access == Access::ReadOnly)
+}
+
+/// Draw the session at its size, with `status` in the status bar.
+fn render(app: &App, renderer: &mut Renderer, status: (String, Option<u16>)) -> Vec<u8> {
+    let (status_line, status_cursor) = status;
+    let area = app.area();
+
+    let mut overlays = Vec::new();
+    if let Some(popup) = &app.popup {
+        overlays.push(Overlay::Popup {
+            title: popup.title(),
+            pane: PaneView {
+                term: &popup.pane.term,
+                rect: popup.inner(area),
+                focused: true,
+            },
+        });
+    }
+    if let Mode::Choose(chooser) = &app.mode {
+        let entries = app.chooser_entries();
+        let visible = chooser.visible(&entries);
+        overlays.push(Overlay::Chooser(ChooserView {
+            lines: visible
+                .iter()
+                .map(|e| format!("{}{}", "  ".repeat(e.depth), e.label))
+                .collect(),
+            selected: chooser.selected_index(&visible),
+            preview: app.preview_pane(chooser.selected).map(|p| &p.term),
+        }));
+    }
+
+    let tab = app.active_tab();
+    let views: Vec<PaneView> = tab
+        .visible(area)
+        .into_iter()
+        .filter_map(|(id, rect)| {
+            tab.pane(id).map(|p| PaneView {
+                term: &p.term,
+                rect,
+                focused: id == tab.focus && app.popup.is_none(),
+            })
+        })
+        .collect();
+    let borders = if tab.zoomed {
+        Vec::new()
+    } else {
+        tab.layout.borders(area)
     };
+    let mut frame = Vec::new();
+    renderer
+        .draw(
+            &mut frame,
+            app.size,
+            &views,
+            &borders,
+            &overlays,
+            &status_line,
+            status_cursor,
+            tab.sync,
+        )
+        .ok();
+    frame
+}
 
-    // 3) Channel: reader thread → main thread.
-    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+fn main() -> windows::core::Result<()> {
+    // 0) `myux <command>` talks to a running myux instead of starting one,
+    // `myux rpc ...` does so over JSON-RPC and `myux attach` shows it on
+    // this console too; `myux -CC` runs without a screen, for a front-end
+    // on stdio.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "rpc") {
+        std::process::exit(rpc_client::cli(&args[1..]));
+    }
+    if args.first().is_some_and(|a| a == "attach") {
+        std::process::exit(attach::run(&args[1..]));
+    }
+    let control = args.len() == 1 && args[0] == "-CC";
+    let startup = if control { None } else { run_client(&args) };
 
-    // Reader thread: ReadFile from ConPTY → send Vec<u8> via channel.
-    let _reader = thread::spawn(move || {
-        let out_handle = HANDLE(out_raw as *mut c_void);
-        let mut buf = [0u8; 8192];
+    // 1) Enable VT on host console and clamp buffer to window.
+    let (cols, rows) = if control {
+        // Control mode has no status bar.
+        let (cols, rows) = control::DEFAULT_SIZE;
+        (cols, rows + 1)
+    } else {
+        enable_vt_mode();
+        clamp_console_buffer_to_window();
+        console_size()
+    };
 
-        loop {
-            let mut read = 0u32;
-            let res = unsafe { ReadFile(out_handle, Some(&mut buf), Some(&mut read), None) };
+    // 2) Channel: every thread that has something for the main loop →
+    // main thread.
+    let (wake_tx, wake_rx) = mpsc::channel::<Wake>();
+    let tx = wake::Sender::new(&wake_tx, Wake::Pane);
+
+    // 3) Spawn a single ConPTY-backed cmd.exe filling everything but the
+    // status bar, or the tabs of the session we were asked to load.
+    let mut app = App::new((cols, rows), Box::new(Conpty), tx);
+    let area = app.area();
+    eprintln!("Spawning ConPTY {}x{}...", area.cols, area.rows);
+    if let Some(session) = &startup {
+        app.open_session(session);
+    }
+    if app.tabs.is_empty() {
+        app.new_tab(SHELL, None);
+    }
+    run_queued(&mut app)?;
+
+    // Options and key bindings from the config file; the first error shows
+    // up in the status bar.
+    if let Some(path) = command::config_path().filter(|p| p.exists())
+        && let Err(err) = command::source_file(&mut app, &path)
+    {
+        app.message = Some(err);
+    }
+    if let Some(dir) = script::script_dir()
+        && let Err(err) = script::load_dir(&mut app, &dir)
+    {
+        app.message = Some(err);
+    }
 
-            if let Err(err) = res {
-                eprintln!("[reader] ReadFile error: {err:?}");
-                break;
-            }
-            if read == 0 {
-                break;
-            }
+    // Requests from `myux <command>`. If another myux already owns the
+    // pipe, this one simply isn't reachable.
+    let _ = ipc::serve(wake::Sender::new(&wake_tx, Wake::Request));
+    let _ = rpc::serve(wake::Sender::new(&wake_tx, Wake::Rpc));
 
-            let chunk = buf[..read as usize].to_vec();
-            if tx.send(chunk).is_err() {
-                break;
-            }
-        }
-    });
+    if control {
+        let lines = wake::Sender::new(&wake_tx, Wake::Line);
+        drop(wake_tx);
+        control::run(&mut app, wake_rx, lines);
+        return Ok(());
+    }
 
     // 4) Terminal setup in main thread.
     enable_raw_mode().unwrap();
@@ -175,11 +453,12 @@ fn main() -> windows::core::Result<()> {
         crossterm::terminal::Clear(crossterm::terminal::ClearType::All),
         crossterm::cursor::MoveTo(0, 0),
         EnableMouseCapture,
+        EnableBracketedPaste,
     )
     .ok();
 
-    let mut app = app;
     let mut renderer = Renderer::new();
+    wake::read_input(wake::Sender::new(&wake_tx, Wake::Input));
 
     // Hide cursor once; renderer no longer hides it every frame.
     // crossterm::execute!(io::stdout(), cursor::Hide).ok();
@@ -187,197 +466,222 @@ fn main() -> windows::core::Result<()> {
     // Track whether we need to redraw.
     let mut dirty = true;
 
-    // 5) Main loop: drain output, handle input, redraw.
-    loop {
-        // Drain ConPTY output into the virtual terminal.
-        while let Ok(bytes) = rx.try_recv() {
-            app.active_tab_mut().term.feed_bytes(&bytes);
-            dirty = true;
-        }
+    // Tab and pane with the focus, to notice when that changes.
+    let mut focus = (app.active, app.active_tab().focus);
+    app.clients.attach(LOCAL, app.size, Access::Full, None);
+    app.fire(Hook::ClientAttached, app.client_env(LOCAL));
 
-        // Build status line (include mode).
-        let mode_str = match app.mode {
-            Mode::Normal => "normal",
-            Mode::Scrollback => "scroll",
-        };
+    // Other consoles attaching with `myux attach`.
+    let _ = attach::serve(wake::Sender::new(&wake_tx, Wake::Attach));
+    drop(wake_tx);
+
+    // When the last frame was drawn; frames come at most `frame_rate`
+    // times a second, however fast output arrives.
+    let mut last_frame: Option<Instant> = None;
+    // Output taken in since then.
+    let mut flooded = 0;
 
-        let key_dbg = match &app.last_key {
-            Some((c, k)) => format!(" | key={:?} kind={:?}", c, k),
-            None => "".to_string(),
+    // 5) Main loop: sleep until something arrives or a frame or the
+    // status scripts are due, take in everything that is waiting, redraw.
+    loop {
+        let interval = frame_interval(&app, flooded);
+        let frame_due = dirty.then(|| last_frame.map_or_else(Instant::now, |t| t + interval));
+        let deadline = [frame_due, script::refresh_due(&app)].into_iter().flatten().min();
+        let first = match deadline {
+            Some(deadline) => {
+                match wake_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(wake) => Some(wake),
+                    Err(mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match wake_rx.recv() {
+                Ok(wake) => Some(wake),
+                Err(_) => break,
+            },
         };
 
-        let status_line = format!(
-            "[myux] tab {}/{} | mode: {} | F10: quit{}",
-            app.active + 1,
-            app.tabs.len(),
-            mode_str,
-            key_dbg,
-        );
-
-        // let status_line = format!(
-        //     "[myux] tab {}/{} | mode: {} | F10: quit",
-        //     app.active + 1,
-        //     app.tabs.len(),
-        //     mode_str,
-        // );
-
-        // Handle input if any.
-        if event::poll(Duration::from_millis(50)).unwrap_or(false) {
-            match event::read().unwrap() {
-                Event::Key(KeyEvent { code, kind, .. }) => {
-
-                    app.last_key = Some((code, kind));
-                    if kind == KeyEventKind::Release {
-                        continue;
+        let mut next = first;
+        while let Some(wake) = next {
+            let redraw = match wake {
+                Wake::Pane(PaneEvent::Output(id)) => {
+                    flooded += app.pane_output(id).len();
+                    true
+                }
+                Wake::Pane(PaneEvent::Exited(id, status)) => {
+                    app.pane_exited(id, status);
+                    true
+                }
+                Wake::Input(event) => {
+                    if matches!(event, Event::Key(_) | Event::Mouse(_) | Event::Paste(_)) {
+                        app.clients.touch(LOCAL);
                     }
-
-
-                    // Global: F10 quits.
-                    if code == KeyCode::F(10) {
-                        unsafe {
-                            let child = app.active_tab().pty.child_process;
-                            let _ = TerminateProcess(child, 0);
-                        }
+                    let actions = app.handle_event(event);
+                    let Some(redraw) = execute(&mut app, LOCAL, actions) else {
+                        app.shut_down();
+                        let _ = run_queued(&mut app);
                         disable_raw_mode().ok();
+                        // The host's own cursor color and shape again.
+                        let _ = io::stdout().write_all(b"\x1b]112\x1b\\");
                         crossterm::execute!(
                             io::stdout(),
                             DisableMouseCapture,
+                            DisableBracketedPaste,
+                            cursor::SetCursorStyle::DefaultUserShape,
                             cursor::Show,
-                            crossterm::terminal::Clear(
-                                crossterm::terminal::ClearType::All
-                            ),
+                            crossterm::terminal::Clear(crossterm::terminal::ClearType::All),
                             crossterm::cursor::MoveTo(0, 0),
                         )
                         .ok();
                         return Ok(());
-                    }
-
-                    // -------- Scrollback mode handling --------
-                    match app.mode {
-                        Mode::Normal => {
-                            match code {
-                                // Enter scrollback mode on PageUp
-                                KeyCode::PageUp => {
-                                    app.mode = Mode::Scrollback;
-                                    app.active_tab_mut().term.scroll_up(5);
-                                    dirty = true;
-                                    continue; // don't send PageUp to the child
-                                }
-                                _ => { /* fall through to normal key handling */ }
-                            }
-                        }
-                        Mode::Scrollback => {
-                            match code {
-                                KeyCode::PageUp => {
-                                    app.active_tab_mut().term.scroll_up(5);
-                                    dirty = true;
-                                    continue;
-                                }
-                                KeyCode::PageDown => {
-                                    app.active_tab_mut().term.scroll_down(5);
-                                    if app.active_tab().term.is_at_bottom() {
-                                        app.mode = Mode::Normal;
-                                    }
-                                    dirty = true;
-                                    continue;
-                                }
-                                KeyCode::Esc => {
-                                    app.active_tab_mut().term.reset_scrollback();
-                                    app.mode = Mode::Normal;
-                                    dirty = true;
-                                    continue;
-                                }
-                                _ => {
-                                    // while in scrollback, ignore all other keys
-                                    continue;
-                                }
-                            }
-                        }
-                    }
+                    };
+                    redraw
+                }
+                Wake::Request(req) => {
+                    let result = command::run(&mut app, &req.args, Some(Path::new(&req.cwd)));
+                    let reply = match result.and_then(|msg| started(&mut app, msg)) {
+                        Ok(msg) => format!("ok {msg}"),
+                        Err(err) => format!("error: {err}"),
+                    };
+                    let _ = req.reply.send(reply);
+                    true
+                }
+                Wake::Rpc(message) => {
+                    rpc::handle(&mut app, message);
+                    true
+                }
+                Wake::Attach(message) => {
+                    attach_message(&mut app, message);
+                    app.apply_size_policy();
+                    true
+                }
+                Wake::Line(_) => false,
+            };
+            dirty |= redraw;
 
-                    // -------- Normal key → ConPTY --------
-                    let pty_in = app.active_tab().pty.pty_in_write;
-                    match code {
-                        KeyCode::Enter => write_all(pty_in, b"\r"),
-                        KeyCode::Backspace => write_all(pty_in, &[0x08]),
-                        KeyCode::Tab => write_all(pty_in, b"\t"),
-
-                        KeyCode::Char(' ') => write_all(pty_in, &[0x20]), // <-- ADD THIS
-
-                        KeyCode::Char(c) => {
-                            let mut s = [0u8; 4];
-                            let n = c.encode_utf8(&mut s).len();
-                            write_all(pty_in, &s[..n]);
-                        }
-
-                        KeyCode::Left => write_all(pty_in, b"\x1b[D"),
-                        KeyCode::Right => write_all(pty_in, b"\x1b[C"),
-                        KeyCode::Up => write_all(pty_in, b"\x1b[A"),
-                        KeyCode::Down => write_all(pty_in, b"\x1b[B"),
-                        KeyCode::Esc => write_all(pty_in, b"\x1b"),
-                        _ => {}
-                    }
+            // Take in whatever else is waiting, but not past a frame that
+            // is due: a flood of output still gets drawn as it goes.
+            if dirty && last_frame.is_none_or(|t| t.elapsed() >= frame_interval(&app, flooded)) {
+                break;
+            }
+            next = wake_rx.try_recv().ok();
+        }
 
-                    dirty = true;
+        let now = (app.active, app.active_tab().focus);
+        if now != focus {
+            app.fire(Hook::FocusChanged, app.tab_env(app.active));
+        }
+        app.run_hooks();
+        if script::refresh_status(&mut app) {
+            dirty = true;
+        }
+        // Whatever hooks, scripts and RPC calls left to start and end.
+        if let Err(err) = run_queued(&mut app) {
+            app.message = Some(err.to_string());
+            dirty = true;
+        }
+        // Hooks may have moved the focus themselves; that doesn't count.
+        focus = (app.active, app.active_tab().focus);
+
+        // Redraw only when something changed, and not more often than the
+        // frame rate allows.
+        if dirty && last_frame.is_none_or(|t| t.elapsed() >= frame_interval(&app, flooded)) {
+            renderer.set_ambiguous_width(app.ambiguous);
+            renderer.set_color_depth(app.color_depth);
+            let area = app.area();
+
+            // Whatever is on screen now has been seen.
+            let tab = app.active_tab_mut();
+            for (id, _) in tab.visible(area) {
+                if let Some(pane) = tab.pane_mut(id) {
+                    pane.activity = false;
                 }
+            }
 
-                Event::Mouse(mouse) => {
-                        use MouseEventKind::*;
-
-                        match mouse.kind {
-                            MouseEventKind::ScrollUp => {
-                                match app.mode {
-                                    Mode::Normal => {
-                                        // Same as first PageUp: enter scrollback mode.
-                                        app.mode = Mode::Scrollback;
-                                        app.active_tab_mut().term.scroll_up(SCROLL_STEP);
-                                    }
-                                    Mode::Scrollback => {
-                                        app.active_tab_mut().term.scroll_up(SCROLL_STEP);
-                                    }
-                                }
-                                dirty = true;
-                            }
-                            MouseEventKind::ScrollDown => {
-                                match app.mode {
-                                    Mode::Normal => {
-                                        // In normal mode at bottom: you could choose to ignore,
-                                        // or later, pass wheel to child. For now: ignore.
-                                    }
-                                    Mode::Scrollback => {
-                                        app.active_tab_mut().term.scroll_down(SCROLL_STEP);
-                                        if app.active_tab().term.is_at_bottom() {
-                                            app.mode = Mode::Normal;
-                                        }
-                                        dirty = true;
-                                    }
-                                }
-                            }
-                            _ => {
-                                // Ignore other mouse events for now (clicks, moves).
-                            }
-                        }
+            let frame = render(&app, &mut renderer, status_line(&app));
+
+            // Every client gets the frame, fitted to its console; read-only
+            // clients looking at something of their own get their own.
+            let clients: Vec<_> = app
+                .clients
+                .iter()
+                .map(|c| (c.id, c.size, c.out.clone(), c.has_own_view()))
+                .collect();
+            for (id, size, out, own_view) in clients {
+                let own;
+                let mut bytes = &frame;
+                if own_view {
+                    own = viewer::render(&mut app, id, |app| {
+                        let status = app.clients.get(id).and_then(viewer::status);
+                        render(app, &mut renderer, status.unwrap_or_else(|| status_line(app)))
+                    });
+                    bytes = &own;
+                }
+                let fitted;
+                if size != app.size {
+                    fitted = renderer::fit_frame(bytes, app.size, size);
+                    bytes = &fitted;
+                }
+                match out {
+                    None => {
+                        let mut stdout = io::stdout().lock();
+                        let _ = stdout.write_all(bytes);
+                        let _ = stdout.flush();
+                    }
+                    Some(out) => {
+                        let _ = out.send(bytes.clone());
                     }
-
-                Event::Resize(new_cols, new_rows) => {
-                    // Resize VT
-                    app.active_tab_mut().term.resize(new_cols, new_rows);
-                    // Resize ConPTY
-                    let _ = app
-                        .active_tab()
-                        .pty
-                        .resize(new_cols as i16, new_rows as i16);
-                    dirty = true;
                 }
-
-                _ => {}
             }
+            dirty = false;
+            last_frame = Some(Instant::now());
+            flooded = 0;
         }
+    }
+    Ok(())
+}
 
-        // Redraw only when something changed.
-        if dirty {
-            renderer.draw(&app.active_tab().term, &status_line).ok();
-            dirty = false;
+/// How long to wait between frames. While output floods in, most of it
+/// is never on screen anyway: frames then come a few times a second, and
+/// the time goes into taking the output in.
+fn frame_interval(app: &App, flooded: usize) -> Duration {
+    if flooded >= FLOOD_BYTES {
+        FLOOD_FRAME_INTERVAL
+    } else {
+        Duration::from_secs(1) / app.frame_rate
+    }
+}
+
+/// A console attaching, sending input or going away.
+fn attach_message(app: &mut App, message: attach::Message) {
+    match message {
+        attach::Message::Attached { id, hello, out } => {
+            let access = if hello.read_only {
+                Access::ReadOnly
+            } else {
+                app.clients.attach_access
+            };
+            let size = (hello.cols, hello.rows);
+            app.clients.attach(id, size, access, Some(out));
+            app.fire(Hook::ClientAttached, app.client_env(id));
+        }
+        attach::Message::Input(id, event) => match event {
+            // Watching doesn't make a client the latest one.
+            Event::Key(key) if is_read_only(app, id) => viewer::key(app, id, key),
+            Event::Mouse(mouse) if is_read_only(app, id) => viewer::mouse(app, id, mouse),
+            Event::Paste(_) if is_read_only(app, id) => {}
+            event @ (Event::Key(_) | Event::Mouse(_) | Event::Paste(_)) => {
+                app.clients.touch(id);
+                let actions = app.handle_event(event);
+                // Only the console myux runs in can quit it.
+                let _ = execute(app, id, actions);
+            }
+            Event::Resize(cols, rows) => app.clients.resize(id, (cols, rows)),
+            _ => {}
+        },
+        attach::Message::Detached(id) => {
+            app.clients.detach(id);
+            app.fire(Hook::ClientDetached, app.client_env(id));
         }
     }
 }
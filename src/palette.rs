@@ -0,0 +1,336 @@
+// src/palette.rs
+//
+// The colors panes are drawn in: the 16 ANSI colors, the default
+// foreground and background and the cursor color. The session has one
+// (`theme`, `set-color`); a pane can have its own, and its child can change
+// it with OSC 4 / 10 / 11 / 12 and put it back with OSC 104 / 110 / 111 /
+// 112. Programs that ask get these colors too, so they can tell a dark
+// background from a light one.
+//
+// Hosts that can't show 24-bit color get the closest of the colors they
+// have, closest as the eye sees it (in Oklab).
+
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    const fn hex(rgb: u32) -> Self {
+        Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+
+    /// `#rgb`, `#rrggbb` or X11's `rgb:r/g/b` with one to four hex digits
+    /// a channel.
+    pub fn parse(spec: &str) -> Option<Self> {
+        if let Some(hex) = spec.strip_prefix('#').filter(|h| h.is_ascii()) {
+            let digits = match hex.len() {
+                3 => 1,
+                6 => 2,
+                _ => return None,
+            };
+            let channel = |i: usize| channel(&hex[i * digits..(i + 1) * digits]);
+            return Some(Rgb(channel(0)?, channel(1)?, channel(2)?));
+        }
+        let mut parts = spec.strip_prefix("rgb:")?.split('/');
+        let rgb = Rgb(
+            channel(parts.next()?)?,
+            channel(parts.next()?)?,
+            channel(parts.next()?)?,
+        );
+        parts.next().is_none().then_some(rgb)
+    }
+
+    /// `rgb:rrrr/gggg/bbbb`, the way xterm reports colors.
+    pub fn x11(self) -> String {
+        let Rgb(r, g, b) = self;
+        format!("rgb:{r:02x}{r:02x}/{g:02x}{g:02x}/{b:02x}{b:02x}")
+    }
+
+    pub fn html(self) -> String {
+        let Rgb(r, g, b) = self;
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// A channel of one to four hex digits, scaled to 8 bits.
+fn channel(hex: &str) -> Option<u8> {
+    if hex.is_empty() || hex.len() > 4 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let max = (1u32 << (4 * hex.len())) - 1;
+    Some((value * 255 / max) as u8)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 16],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    /// None leaves the cursor to the host.
+    pub cursor: Option<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        CAMPBELL
+    }
+}
+
+impl Palette {
+    /// Set one color, named the way `set-color` takes it: 0 to 15,
+    /// `foreground`, `background` or `cursor`.
+    pub fn set(&mut self, slot: &str, color: Rgb) -> Result<(), String> {
+        match slot {
+            "foreground" | "fg" => self.foreground = color,
+            "background" | "bg" => self.background = color,
+            "cursor" => self.cursor = Some(color),
+            _ => {
+                let index = slot
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&i| i < 16)
+                    .ok_or_else(|| format!("not a color: {slot}"))?;
+                self.colors[index] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+const fn palette(colors: [u32; 16], foreground: u32, background: u32) -> Palette {
+    let mut rgb = [Rgb(0, 0, 0); 16];
+    let mut i = 0;
+    while i < 16 {
+        rgb[i] = Rgb::hex(colors[i]);
+        i += 1;
+    }
+    Palette {
+        colors: rgb,
+        foreground: Rgb::hex(foreground),
+        background: Rgb::hex(background),
+        cursor: None,
+    }
+}
+
+/// The Windows console's own.
+#[rustfmt::skip]
+const CAMPBELL: Palette = palette(
+    [
+        0x0c0c0c, 0xc50f1f, 0x13a10e, 0xc19c00, 0x0037da, 0x881798, 0x3a96dd, 0xcccccc,
+        0x767676, 0xe74856, 0x16c60c, 0xf9f1a5, 0x3b78ff, 0xb4009e, 0x61d6d6, 0xf2f2f2,
+    ],
+    0xcccccc,
+    0x0c0c0c,
+);
+
+#[rustfmt::skip]
+const SOLARIZED: [u32; 16] = [
+    0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+    0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+];
+
+pub const THEMES: &[(&str, Palette)] = &[
+    ("campbell", CAMPBELL),
+    (
+        "one-half-dark",
+        palette(
+            [
+                0x282c34, 0xe06c75, 0x98c379, 0xe5c07b, 0x61afef, 0xc678dd, 0x56b6c2, 0xdcdfe4,
+                0x5a6374, 0xe06c75, 0x98c379, 0xe5c07b, 0x61afef, 0xc678dd, 0x56b6c2, 0xdcdfe4,
+            ],
+            0xdcdfe4,
+            0x282c34,
+        ),
+    ),
+    (
+        "one-half-light",
+        palette(
+            [
+                0x383a42, 0xe45649, 0x50a14f, 0xc18301, 0x0184bc, 0xa626a4, 0x0997b3, 0xfafafa,
+                0x4f525d, 0xdf6c75, 0x98c379, 0xe4c07a, 0x61afef, 0xc577dd, 0x56b5c1, 0xffffff,
+            ],
+            0x383a42,
+            0xfafafa,
+        ),
+    ),
+    ("solarized-dark", palette(SOLARIZED, 0x839496, 0x002b36)),
+    ("solarized-light", palette(SOLARIZED, 0x657b83, 0xfdf6e3)),
+    (
+        "vintage",
+        palette(
+            [
+                0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xc0c0c0,
+                0x808080, 0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
+            ],
+            0xc0c0c0,
+            0x000000,
+        ),
+    ),
+];
+
+pub fn theme(name: &str) -> Option<Palette> {
+    THEMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, p)| p.clone())
+}
+
+/// The colors the host console can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "truecolor" | "24bit" => Some(ColorDepth::TrueColor),
+            "256" => Some(ColorDepth::Ansi256),
+            "16" => Some(ColorDepth::Ansi16),
+            _ => None,
+        }
+    }
+
+    /// `MYUX_COLOR_DEPTH` (as `set color-depth` takes it) if set, else what
+    /// COLORTERM and TERM say. Without either, the Windows console, which
+    /// shows 24-bit color.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).unwrap_or_default().to_ascii_lowercase();
+        if let Some(depth) = ColorDepth::parse(&var("MYUX_COLOR_DEPTH")) {
+            return depth;
+        }
+        if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+            return ColorDepth::TrueColor;
+        }
+        match var("TERM").as_str() {
+            "" => ColorDepth::TrueColor,
+            term if term.contains("direct") => ColorDepth::TrueColor,
+            term if term.contains("256color") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+}
+
+/// Picks the host color for a 24-bit one, remembering its answers.
+pub struct Quantizer {
+    depth: ColorDepth,
+    /// What there is to choose from, with where each is in Oklab.
+    targets: Vec<(u8, [f32; 3])>,
+    cache: HashMap<Rgb, u8>,
+}
+
+/// Colors remembered before the cache starts over; gradients would
+/// otherwise grow it without end.
+const CACHE_LIMIT: usize = 4096;
+
+impl Quantizer {
+    pub fn new(depth: ColorDepth) -> Self {
+        let indexes = match depth {
+            ColorDepth::TrueColor => 0..0,
+            // The first 16 look different on every host; the cube and the
+            // grays don't.
+            ColorDepth::Ansi256 => 16..256,
+            ColorDepth::Ansi16 => 0..16,
+        };
+        Quantizer {
+            depth,
+            targets: indexes
+                .map(|i| (i as u8, oklab(xterm_rgb(i as u8))))
+                .collect(),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    /// The host's color index closest to `rgb`. Not for true color.
+    pub fn index(&mut self, rgb: Rgb) -> u8 {
+        if let Some(&index) = self.cache.get(&rgb) {
+            return index;
+        }
+        let lab = oklab(rgb);
+        let distance = |t: &[f32; 3]| (0..3).map(|i| (t[i] - lab[i]).powi(2)).sum::<f32>();
+        let index = self
+            .targets
+            .iter()
+            .min_by(|a, b| distance(&a.1).total_cmp(&distance(&b.1)))
+            .map_or(0, |&(i, _)| i);
+        if self.cache.len() >= CACHE_LIMIT {
+            self.cache.clear();
+        }
+        self.cache.insert(rgb, index);
+        index
+    }
+}
+
+/// Color `index` of a 256-color host: the 16 of the Windows console, then
+/// xterm's 6x6x6 cube and 24 grays.
+pub fn xterm_rgb(index: u8) -> Rgb {
+    match index {
+        0..16 => CAMPBELL.colors[index as usize],
+        16..232 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n };
+            let n = index - 16;
+            Rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            Rgb(gray, gray, gray)
+        }
+    }
+}
+
+fn oklab(Rgb(r, g, b): Rgb) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let l = (0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_specs() {
+        assert_eq!(Rgb::parse("#fff"), Some(Rgb(255, 255, 255)));
+        assert_eq!(Rgb::parse("#102030"), Some(Rgb(0x10, 0x20, 0x30)));
+        assert_eq!(Rgb::parse("rgb:ffff/8080/0"), Some(Rgb(255, 0x80, 0)));
+        assert_eq!(Rgb::parse("rgb:1/2"), None);
+        assert_eq!(Rgb::parse("red"), None);
+        assert_eq!(Rgb(0x10, 0x20, 0x30).x11(), "rgb:1010/2020/3030");
+    }
+
+    #[test]
+    fn colors_go_to_the_closest_the_host_has() {
+        let mut q = Quantizer::new(ColorDepth::Ansi256);
+        assert_eq!(q.index(Rgb(255, 0, 0)), 196);
+        assert_eq!(q.index(Rgb(0x80, 0x80, 0x80)), 244);
+        assert_eq!(q.index(xterm_rgb(67)), 67);
+
+        let mut q = Quantizer::new(ColorDepth::Ansi16);
+        assert_eq!(q.index(Rgb(0xc0, 0x10, 0x20)), 1);
+        assert_eq!(q.index(Rgb(0xfd, 0xf6, 0xe3)), 15);
+        assert_eq!(q.index(Rgb(0, 0x2b, 0x36)), 0);
+    }
+}
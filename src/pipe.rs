@@ -0,0 +1,351 @@
+// src/pipe.rs
+//
+// pipe-pane: a copy of everything a pane prints, taken straight from its
+// reader thread, goes to a log file (rotated by size) or to the stdin of
+// another program. That program's stdout can be fed back into the pane as
+// if it had been typed.
+//
+// The file or the program's stdin belongs to a writer thread of the pipe's
+// own, fed through a bounded queue. The reader thread strips the output
+// under the pane's pipe lock and queues it after letting go, so a full
+// queue blocks that pane's reader and never whoever else takes the lock.
+// A stalled program holds up that pane's output and nothing else.
+
+use crate::command::Args;
+use crate::layout::PaneId;
+use crate::pty::Pty;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 5;
+/// Chunks queued for the writer thread before the pane's reader waits.
+const QUEUE_LEN: usize = 64;
+
+pub struct PipeSpec {
+    pub target: PipeTarget,
+    /// Remove escape sequences and control characters first.
+    pub strip: bool,
+}
+
+pub enum PipeTarget {
+    /// Append to `path`; once it would grow past `max_bytes` it is renamed
+    /// to `path.1` (older ones shift up to `path.<keep>`) and a new file is
+    /// started. `max_bytes == 0` never rotates.
+    File {
+        path: PathBuf,
+        max_bytes: u64,
+        keep: usize,
+    },
+    /// Run `cmdline` through `cmd.exe /C`. With `to_pane` its stdout is
+    /// written to the pane's input.
+    Command { cmdline: String, to_pane: bool },
+}
+
+/// A parsed `pipe-pane` command.
+pub struct PipeCommand {
+    /// `-o`: stop if the pane is already piped instead of replacing it.
+    pub toggle: bool,
+    /// None: just stop piping.
+    pub spec: Option<PipeSpec>,
+}
+
+impl PipeCommand {
+    pub const USAGE: &str = "pipe-pane [-o] [-s] [-I] [-r bytes] [-k count] [-f file | command...]";
+
+    /// Build from parsed `pipe-pane` arguments. `{pane}` in a file name
+    /// becomes the id of `pane`.
+    pub fn from_args(args: &Args, pane: PaneId) -> Result<Self, String> {
+        let number = |flag, default| match args.value(flag) {
+            Some(v) => v.parse().map_err(|_| format!("-{flag}: not a number: {v}")),
+            None => Ok(default),
+        };
+        let max_bytes = number('r', DEFAULT_MAX_BYTES)?;
+        let keep = number('k', DEFAULT_KEEP as u64)? as usize;
+
+        let target = match (args.value('f'), args.rest.is_empty()) {
+            (Some(_), false) => return Err(format!("usage: {}", Self::USAGE)),
+            (Some(file), true) => Some(PipeTarget::File {
+                path: args.path(&file.replace("{pane}", &pane.to_string())),
+                max_bytes,
+                keep,
+            }),
+            (None, false) => Some(PipeTarget::Command {
+                cmdline: args.command_line().unwrap_or_default(),
+                to_pane: args.has('I'),
+            }),
+            (None, true) => None,
+        };
+
+        Ok(PipeCommand {
+            toggle: args.has('o'),
+            spec: target.map(|target| PipeSpec {
+                target,
+                strip: args.has('s'),
+            }),
+        })
+    }
+}
+
+/// An open pipe of one pane, fed by its reader thread.
+pub struct PanePipe {
+    /// Shown in the status bar.
+    label: String,
+    stripper: Option<Stripper>,
+    queue: SyncSender<Vec<u8>>,
+    /// Set by the writer thread when the file or command stops taking
+    /// output.
+    broken: Arc<AtomicBool>,
+    /// The command, killed when the pipe closes.
+    child: Option<Child>,
+}
+
+/// Stripped pane output on its way to the writer thread.
+pub struct PipeChunk {
+    queue: SyncSender<Vec<u8>>,
+    bytes: Vec<u8>,
+}
+
+impl PipeChunk {
+    /// Queue the chunk, waiting while the queue is full. False if the
+    /// writer thread is gone.
+    pub fn send(self) -> bool {
+        self.queue.send(self.bytes).is_ok()
+    }
+}
+
+impl PanePipe {
+    /// Open the file or start the command. `pane` gets a command's stdout
+    /// as input when `to_pane` is set.
+    pub fn open(spec: &PipeSpec, pane: &dyn Pty) -> io::Result<Self> {
+        let (label, sink, child): (_, Box<dyn Write + Send>, _) = match &spec.target {
+            PipeTarget::File {
+                path,
+                max_bytes,
+                keep,
+            } => (
+                path.file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                Box::new(RotatingFile::open(path, *max_bytes, *keep)?),
+                None,
+            ),
+            PipeTarget::Command { cmdline, to_pane } => {
+                let mut child = Command::new("cmd.exe")
+                    .arg("/C")
+                    .raw_arg(cmdline)
+                    .stdin(Stdio::piped())
+                    .stdout(if *to_pane {
+                        Stdio::piped()
+                    } else {
+                        Stdio::null()
+                    })
+                    .stderr(Stdio::null())
+                    .spawn()?;
+                if let Some(stdout) = child.stdout.take() {
+                    forward_to_pane(stdout, pane.input()?);
+                }
+                let stdin = child.stdin.take().expect("piped stdin");
+                (cmdline.clone(), Box::new(stdin), Some(child))
+            }
+        };
+
+        let (queue, chunks) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LEN);
+        let broken = Arc::new(AtomicBool::new(false));
+        let done = Arc::clone(&broken);
+        thread::spawn(move || {
+            let mut sink = sink;
+            // `chunks` stays open until `broken` is set.
+            for bytes in chunks.iter() {
+                if sink.write_all(&bytes).and_then(|_| sink.flush()).is_err() {
+                    break;
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        Ok(PanePipe {
+            label,
+            stripper: spec.strip.then(Stripper::default),
+            queue,
+            broken,
+            child,
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Whether the command exited, the disk is full, ...
+    pub fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+    }
+
+    /// Make a chunk of pane output ready to send, if there is anything
+    /// left of it. Cheap: the writing is up to the writer thread.
+    pub fn chunk(&mut self, bytes: &[u8]) -> Option<PipeChunk> {
+        let bytes = match &mut self.stripper {
+            Some(stripper) => {
+                let mut stripped = Vec::new();
+                stripper.strip(bytes, &mut stripped);
+                stripped
+            }
+            None => bytes.to_vec(),
+        };
+        (!bytes.is_empty()).then(|| PipeChunk {
+            queue: self.queue.clone(),
+            bytes,
+        })
+    }
+}
+
+impl Drop for PanePipe {
+    fn drop(&mut self) {
+        // Not every command exits on EOF, so don't wait for it to. Killing
+        // it also ends a writer thread stuck on its stdin.
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Copy `stdout` into the pane's input on a thread of its own.
+fn forward_to_pane(mut stdout: impl Read + Send + 'static, mut input: Box<dyn Write + Send>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stdout.read(&mut buf) {
+            if n == 0 || input.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + bytes.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// `path.<n>` → `path.<n+1>`, `path` → `path.1`, start a new `path`.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = rotated(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.append(bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Removes escape sequences and control characters from a byte stream,
+/// keeping text, tabs and newlines. Sequences may be split across chunks.
+#[derive(Default)]
+struct Stripper {
+    state: StripState,
+}
+
+#[derive(Default, Clone, Copy)]
+enum StripState {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    /// OSC, DCS, APC, PM, SOS: everything up to BEL or ST.
+    String,
+    StringEscape,
+}
+
+impl Stripper {
+    fn strip(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        use StripState::*;
+
+        for &b in input {
+            self.state = match (self.state, b) {
+                (Ground, 0x1b) => Escape,
+                (Ground, b'\n' | b'\t') => {
+                    out.push(b);
+                    Ground
+                }
+                (Ground, 0x00..=0x1f | 0x7f) => Ground,
+                (Ground, _) => {
+                    out.push(b);
+                    Ground
+                }
+                (Escape, b'[') => Csi,
+                (Escape, b']' | b'P' | b'_' | b'^' | b'X') => String,
+                // Intermediate bytes, e.g. `ESC ( B`.
+                (Escape, 0x20..=0x2f) => Escape,
+                (Escape, _) => Ground,
+                (Csi, 0x40..=0x7e) => Ground,
+                (Csi, _) => Csi,
+                (String, 0x07) => Ground,
+                (String, 0x1b) => StringEscape,
+                (String, _) => String,
+                (StringEscape, b'\\') => Ground,
+                (StringEscape, _) => String,
+            };
+        }
+    }
+}
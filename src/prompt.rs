@@ -0,0 +1,82 @@
+// src/prompt.rs
+//
+// The `:` command prompt in the status line: a one-line editor with history
+// and tab completion. Running what was typed is up to the caller.
+
+use crate::command;
+use crossterm::event::KeyCode;
+
+/// Lines kept in the prompt history.
+pub const HISTORY_LEN: usize = 100;
+
+pub struct Prompt {
+    pub input: String,
+    /// Shown after the input: completion candidates.
+    pub hint: String,
+    /// Index into the history while browsing it with Up/Down.
+    history_pos: Option<usize>,
+    /// What was typed before browsing the history.
+    draft: String,
+}
+
+pub enum PromptAction {
+    None,
+    Cancel,
+    Submit(String),
+}
+
+impl Prompt {
+    pub fn new(initial: &str) -> Self {
+        Prompt {
+            input: initial.to_string(),
+            hint: String::new(),
+            history_pos: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Handle one key. `history` is oldest first.
+    pub fn key(&mut self, code: KeyCode, history: &[String]) -> PromptAction {
+        self.hint.clear();
+        match code {
+            KeyCode::Enter => return PromptAction::Submit(std::mem::take(&mut self.input)),
+            KeyCode::Esc => return PromptAction::Cancel,
+            // Backspace on an empty line gives up, like tmux.
+            KeyCode::Backspace if self.input.is_empty() => return PromptAction::Cancel,
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Tab => {
+                let completion = command::complete(&self.input);
+                self.input = completion.line;
+                self.hint = completion.candidates.join(" ");
+            }
+            KeyCode::Up => {
+                let pos = match self.history_pos {
+                    Some(pos) => pos.saturating_sub(1),
+                    None if history.is_empty() => return PromptAction::None,
+                    None => {
+                        self.draft = self.input.clone();
+                        history.len() - 1
+                    }
+                };
+                self.history_pos = Some(pos);
+                self.input = history[pos].clone();
+            }
+            KeyCode::Down => match self.history_pos {
+                Some(pos) if pos + 1 < history.len() => {
+                    self.history_pos = Some(pos + 1);
+                    self.input = history[pos + 1].clone();
+                }
+                Some(_) => {
+                    self.history_pos = None;
+                    self.input = std::mem::take(&mut self.draft);
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        PromptAction::None
+    }
+}
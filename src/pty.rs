@@ -0,0 +1,417 @@
+// src/pty.rs
+//
+// A pane's process as the rest of myux sees it: something to write input
+// to, resize and end, whose output and exit come back through an
+// `Output`. ConPTY in real life (conpty.rs); a scripted fake in tests
+// (testing.rs).
+//
+// Output piles up in the pane's `Pending` until the main loop takes all of
+// it at once; the loop is only woken when the pile was empty. Once a pile
+// is `PENDING_LIMIT` bytes the reader waits, and so does the child: a
+// flood can't get further ahead of the screen than that.
+//
+// What to start comes as `SpawnOptions`: a command line the user typed,
+// passed on untouched, or a program and its arguments quoted the way
+// CommandLineToArgvW splits them again; the directory and the environment.
+
+use crate::app::PaneEvent;
+use crate::layout::PaneId;
+use crate::pipe::PanePipe;
+use crate::wake::Sender;
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+
+pub const PENDING_LIMIT: usize = 1 << 20;
+
+pub trait Pty {
+    /// Input for the child. A child that is gone just doesn't get it.
+    fn write(&self, bytes: &[u8]);
+    fn resize(&self, cols: u16, rows: u16);
+    /// End the child; its exit is reported as usual.
+    fn kill(&self);
+    /// Somewhere to write input from another thread, which keeps working
+    /// after the pane is dropped (pipe-pane -I).
+    fn input(&self) -> io::Result<Box<dyn Write + Send>>;
+    /// Input written before there was a process to take it.
+    fn waiting_input(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// A pane's pty until its process is started: input waits for it.
+#[derive(Default)]
+pub struct NotStarted {
+    input: RefCell<Vec<u8>>,
+}
+
+impl Pty for NotStarted {
+    fn write(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend_from_slice(bytes);
+    }
+    fn resize(&self, _: u16, _: u16) {}
+    fn kill(&self) {}
+    fn input(&self) -> io::Result<Box<dyn Write + Send>> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "not started yet",
+        ))
+    }
+    fn waiting_input(&self) -> Vec<u8> {
+        self.input.take()
+    }
+}
+
+/// Starts the processes of new panes.
+pub trait Spawner {
+    /// Run what `options` say at `cols` x `rows`. Its output and exit go to
+    /// `output`.
+    fn spawn(
+        &self,
+        options: &SpawnOptions,
+        cols: u16,
+        rows: u16,
+        output: Output,
+    ) -> io::Result<Box<dyn Pty>>;
+}
+
+/// What a pane runs.
+#[derive(Clone, Debug)]
+pub enum Program {
+    /// A command line, passed on as it is: cmd.exe and others split theirs
+    /// their own way, so it mustn't be taken apart and put back together.
+    Cmdline(String),
+    /// A program and its arguments, quoted into a command line.
+    Argv(Vec<String>),
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::Cmdline(String::new())
+    }
+}
+
+impl Program {
+    /// The command line to hand to CreateProcess.
+    pub fn cmdline(&self) -> String {
+        match self {
+            Program::Cmdline(cmdline) => cmdline.clone(),
+            Program::Argv(argv) => {
+                let words: Vec<String> = argv.iter().map(|w| quote(w)).collect();
+                words.join(" ")
+            }
+        }
+    }
+}
+
+/// How to start a pane's process.
+#[derive(Clone, Debug, Default)]
+pub struct SpawnOptions {
+    pub program: Program,
+    /// None: myux's own.
+    pub cwd: Option<String>,
+    /// Variables to set, or with None to remove, in order.
+    pub env: Vec<(String, Option<String>)>,
+    /// Start from an empty environment instead of myux's.
+    pub clear_env: bool,
+    /// Ask the shell to run as a login shell, if it knows how.
+    pub login: bool,
+}
+
+impl SpawnOptions {
+    /// The program's command line, with the login flag after the program
+    /// name if there is to be one.
+    pub fn cmdline(&self) -> String {
+        let cmdline = self.program.cmdline();
+        let (program, args) = split_program(&cmdline);
+        match self
+            .login
+            .then(|| login_flag(program.trim_matches('"')))
+            .flatten()
+        {
+            Some(flag) => format!("{program} {flag}{args}"),
+            None => cmdline,
+        }
+    }
+
+    /// The child's whole environment.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = if self.clear_env {
+            Vec::new()
+        } else {
+            env::vars_os()
+                .map(|(k, v)| {
+                    (
+                        k.to_string_lossy().into_owned(),
+                        v.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect()
+        };
+        for (name, value) in &self.env {
+            vars.retain(|(k, _)| !same_name(k, name));
+            if let Some(value) = value {
+                vars.push((name.clone(), value.clone()));
+            }
+        }
+        vars
+    }
+}
+
+/// The flag that makes `program` a login shell.
+fn login_flag(program: &str) -> Option<&'static str> {
+    let name = program.rsplit(['\\', '/']).next()?.to_ascii_lowercase();
+    let name = name.strip_suffix(".exe").unwrap_or(&name);
+    matches!(name, "bash" | "sh" | "zsh" | "ksh" | "fish").then_some("-l")
+}
+
+/// Variable names are case-insensitive on Windows.
+fn same_name(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// Split the program name off `cmdline` where CreateProcess does: after
+/// the closing quote if it starts with one, else at the first blank.
+fn split_program(cmdline: &str) -> (&str, &str) {
+    let end = match cmdline.strip_prefix('"') {
+        Some(quoted) => quoted.find('"').map_or(cmdline.len(), |i| i + 2),
+        None => cmdline.find([' ', '\t']).unwrap_or(cmdline.len()),
+    };
+    cmdline.split_at(end)
+}
+
+/// Quote `word` so CommandLineToArgvW gives it back: blanks separate words
+/// outside of double quotes, `\"` is a quote, and backslashes are only
+/// special before one.
+fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return word.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in word.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // Backslashes before a quote are doubled, and the quote escaped.
+        let escaped = if c == '"' {
+            2 * backslashes + 1
+        } else {
+            backslashes
+        };
+        quoted.extend(std::iter::repeat_n('\\', escaped));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    // The closing quote mustn't be escaped either.
+    quoted.extend(std::iter::repeat_n('\\', 2 * backslashes));
+    quoted.push('"');
+    quoted
+}
+
+/// Output of a pane's child that the main loop hasn't taken yet.
+#[derive(Default)]
+pub struct Pending {
+    state: Mutex<PendingState>,
+    room: Condvar,
+}
+
+#[derive(Default)]
+struct PendingState {
+    bytes: Vec<u8>,
+    /// The pane is gone; nobody takes the output any more.
+    closed: bool,
+}
+
+impl Pending {
+    /// Add `chunk` once there is room. None if the pane is gone, else
+    /// whether the pile was empty, so the main loop has to be woken.
+    fn push(&self, chunk: &[u8]) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        while state.bytes.len() >= PENDING_LIMIT && !state.closed {
+            state = self.room.wait(state).unwrap();
+        }
+        if state.closed {
+            return None;
+        }
+        let was_empty = state.bytes.is_empty();
+        state.bytes.extend_from_slice(chunk);
+        Some(was_empty)
+    }
+
+    /// Everything read since the last time.
+    pub fn take(&self) -> Vec<u8> {
+        let bytes = mem::take(&mut self.state.lock().unwrap().bytes);
+        self.room.notify_all();
+        bytes
+    }
+
+    /// The pane is gone: a reader waiting for room gives up.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.room.notify_all();
+    }
+}
+
+/// Where a pane's output goes: its pipe-pane copy, then the main loop.
+#[derive(Clone)]
+pub struct Output {
+    pub id: PaneId,
+    tx: Sender<PaneEvent>,
+    pipe: Arc<Mutex<Option<PanePipe>>>,
+    pending: Arc<Pending>,
+}
+
+impl Output {
+    pub fn new(
+        id: PaneId,
+        tx: Sender<PaneEvent>,
+        pipe: Arc<Mutex<Option<PanePipe>>>,
+        pending: Arc<Pending>,
+    ) -> Self {
+        Output {
+            id,
+            tx,
+            pipe,
+            pending,
+        }
+    }
+
+    /// Pass on a chunk of output, waiting while the main loop is too far
+    /// behind. False once nobody listens any more.
+    pub fn send(&self, chunk: &[u8]) -> bool {
+        // pipe-pane gets its copy before the main loop sees the output,
+        // queued outside the lock so a full queue doesn't hold up whoever
+        // looks at the pipe. A broken pipe just closes.
+        let copy = self
+            .pipe
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|p| p.chunk(chunk));
+        if let Some(copy) = copy
+            && !copy.send()
+        {
+            let mut pipe = self.pipe.lock().unwrap();
+            if pipe.as_ref().is_some_and(PanePipe::is_broken) {
+                *pipe = None;
+            }
+        }
+        match self.pending.push(chunk) {
+            None => false,
+            Some(true) => self.tx.send(PaneEvent::Output(self.id)).is_ok(),
+            Some(false) => true,
+        }
+    }
+
+    pub fn exited(&self, status: u32) {
+        let _ = self.tx.send(PaneEvent::Exited(self.id, status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn command_lines_pass_as_they_are_and_argv_gets_quoted() {
+        let typed = r#"cmd /c echo "ab""#;
+        assert_eq!(Program::Cmdline(typed.into()).cmdline(), typed);
+
+        let argv = [
+            r"C:\Program Files\x.exe",
+            "a b",
+            r#"say "hi""#,
+            r"x\",
+            r"y\ z\",
+            "",
+        ];
+        let argv = Program::Argv(argv.map(String::from).to_vec());
+        assert_eq!(
+            argv.cmdline(),
+            r#""C:\Program Files\x.exe" "a b" "say \"hi\"" x\ "y\ z\\" """#
+        );
+    }
+
+    #[test]
+    fn login_shells_get_their_flag() {
+        let login = |cmdline: &str| {
+            SpawnOptions {
+                program: Program::Cmdline(cmdline.into()),
+                login: true,
+                ..SpawnOptions::default()
+            }
+            .cmdline()
+        };
+        assert_eq!(
+            login(r#""C:\msys64\usr\bin\bash.exe" -i"#),
+            r#""C:\msys64\usr\bin\bash.exe" -l -i"#
+        );
+        assert_eq!(login("zsh"), "zsh -l");
+        assert_eq!(login("cmd.exe /k"), "cmd.exe /k");
+    }
+
+    #[test]
+    fn environment_overrides_and_removals() {
+        let options = SpawnOptions {
+            env: vec![
+                ("A".into(), Some("1".into())),
+                ("B".into(), Some("2".into())),
+                ("a".into(), Some("3".into())),
+                ("B".into(), None),
+            ],
+            clear_env: true,
+            ..SpawnOptions::default()
+        };
+        assert_eq!(options.environment(), [("a".to_string(), "3".to_string())]);
+    }
+
+    /// A reader thread pushing `more`; what `push` returned comes back
+    /// once it has.
+    fn reader(pending: &Arc<Pending>) -> mpsc::Receiver<Option<bool>> {
+        let (tx, rx) = mpsc::channel();
+        let pending = Arc::clone(pending);
+        thread::spawn(move || tx.send(pending.push(b"more")));
+        rx
+    }
+
+    #[test]
+    fn only_the_first_chunk_of_a_batch_wakes_the_loop() {
+        let pending = Pending::default();
+        assert_eq!(pending.push(b"a"), Some(true));
+        assert_eq!(pending.push(b"b"), Some(false));
+        assert_eq!(pending.take(), b"ab");
+        assert_eq!(pending.push(b"c"), Some(true));
+    }
+
+    #[test]
+    fn a_full_pile_holds_the_reader_until_taken() {
+        let pending = Arc::new(Pending::default());
+        pending.push(&vec![0; PENDING_LIMIT]);
+        let pushed = reader(&pending);
+        assert!(pushed.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(pending.take().len(), PENDING_LIMIT);
+        // Pushed onto an empty pile, so after the take.
+        assert_eq!(pushed.recv().unwrap(), Some(true));
+        assert_eq!(pending.take(), b"more");
+    }
+
+    #[test]
+    fn closing_lets_a_waiting_reader_go() {
+        let pending = Arc::new(Pending::default());
+        pending.push(&vec![0; PENDING_LIMIT]);
+        let pushed = reader(&pending);
+        pending.close();
+        assert_eq!(pushed.recv().unwrap(), None);
+    }
+}
@@ -0,0 +1,445 @@
+// src/reflow.rs
+//
+// Re-wrapping of soft-wrapped lines when the terminal width changes.
+//
+// vt100 only truncates or pads rows on `set_size`, so we read every row
+// (history + screen) back out, join rows that were soft-wrapped into logical
+// lines, and replay those lines into a fresh parser at the new size. The
+// parser then does the wrapping for us and sets the wrap flags again.
+//
+// The scroll region, origin mode and saved cursor can't be read from a
+// vt100 screen, so they are measured on copies of it: where the cursor
+// goes after a few sequences gives them away.
+
+use vt100::{Callbacks, Cell, Color, Parser, Screen};
+
+/// One logical line: the cells of one or more physical rows that were joined
+/// by soft wraps. Wide characters keep their continuation cell so offsets
+/// stay in cell units.
+type Line = Vec<Cell>;
+
+pub struct Snapshot {
+    lines: Vec<Line>,
+    /// Index of the logical line that contains the first row of the screen.
+    screen_start: usize,
+    /// Logical line of the cursor and its cell offset inside that line.
+    cursor: (usize, usize),
+    /// Pen attributes and input modes to restore after the replay.
+    modes: Vec<u8>,
+    /// Rows of the screen it was taken from.
+    rows: u16,
+    grid: GridState,
+}
+
+/// What vt100 keeps of the primary grid without telling.
+struct GridState {
+    /// Top and bottom row of the scroll region (DECSTBM).
+    region: (u16, u16),
+    /// DECOM.
+    origin: bool,
+    /// Cursor position, origin mode and pen saved with DECSC.
+    saved: (u16, u16),
+    saved_origin: bool,
+    saved_pen: Vec<u8>,
+    /// DECTCEM reset.
+    hidden: bool,
+}
+
+impl GridState {
+    fn probe(screen: &Screen) -> Self {
+        let copy = || {
+            let mut parser = Parser::new(1, 1, 0);
+            *parser.screen_mut() = screen.clone();
+            parser
+        };
+        let row = |parser: &mut Parser, bytes: &[u8]| {
+            parser.process(bytes);
+            parser.screen().cursor_position().0
+        };
+        // With a region starting at the second row, `CSI H` only goes
+        // there in origin mode (so a screen of two rows reads as off).
+        let origin_probe = b"\x1b[2;3r\x1b[H";
+
+        // DECRC brings back what DECSC saved.
+        let mut saved = copy();
+        saved.process(b"\x1b8");
+        let saved_at = saved.screen().cursor_position();
+        let saved_pen = saved.screen().attributes_formatted();
+        let saved_origin = row(&mut saved, origin_probe) == 1;
+
+        // In origin mode the cursor can't leave the region, and DECSC keeps
+        // the origin mode meanwhile.
+        let mut current = copy();
+        let top = row(&mut current, b"\x1b7\x1b[?6h");
+        let bottom = row(&mut current, b"\x1b[9999H");
+        let origin = row(&mut current, &[b"\x1b8".as_slice(), origin_probe].concat()) == 1;
+
+        GridState {
+            region: (top, bottom),
+            origin,
+            saved: saved_at,
+            saved_origin,
+            saved_pen,
+            hidden: screen.hide_cursor(),
+        }
+    }
+
+    /// Sequences that set it up again on a screen of `rows` x `cols`,
+    /// clamped the way vt100 clamps them on a resize, and put the cursor
+    /// at `cursor`.
+    fn restore(&self, old_rows: u16, rows: u16, cols: u16, cursor: (u16, u16)) -> Vec<u8> {
+        let (top, mut bottom) = self.region;
+        if bottom == old_rows - 1 {
+            bottom = rows - 1;
+        }
+        bottom = bottom.min(rows - 1);
+        let top = if top < bottom { top } else { 0 };
+        let bottom = if top < bottom { bottom } else { rows - 1 };
+
+        let goto = |buf: &mut Vec<u8>, origin: bool, (row, col): (u16, u16)| {
+            let flag = if origin { 'h' } else { 'l' };
+            let row = if origin { row.saturating_sub(top) } else { row };
+            let at = format!("\x1b[?6{flag}\x1b[{};{}H", row + 1, col + 1);
+            buf.extend_from_slice(at.as_bytes());
+        };
+
+        let mut buf = Vec::new();
+        if (top, bottom) != (0, rows - 1) {
+            buf.extend_from_slice(format!("\x1b[{};{}r", top + 1, bottom + 1).as_bytes());
+        }
+        let saved = (self.saved.0.min(rows - 1), self.saved.1.min(cols - 1));
+        buf.extend_from_slice(&self.saved_pen);
+        goto(&mut buf, self.saved_origin, saved);
+        buf.extend_from_slice(b"\x1b7");
+        goto(&mut buf, self.origin, cursor);
+        if self.hidden {
+            buf.extend_from_slice(b"\x1b[?25l");
+        }
+        buf
+    }
+}
+
+impl Snapshot {
+    /// Read scrollback and screen rows of the primary screen.
+    ///
+    /// Leaves the scrollback offset at 0.
+    pub fn capture(screen: &mut Screen) -> Self {
+        let (rows, cols) = screen.size();
+        let (cur_row, cur_col) = screen.cursor_position();
+
+        screen.set_scrollback(usize::MAX);
+        let history = screen.scrollback();
+        let total = history + rows as usize;
+
+        let mut lines = Vec::new();
+        let mut current: Line = Vec::new();
+        let mut screen_start = 0;
+        let mut cursor = (0, 0);
+
+        let mut physical = 0;
+        while physical < total {
+            // Show the page whose first visible row is `physical` (or the
+            // live screen once we are past the history).
+            let top = physical.min(history);
+            screen.set_scrollback(history - top);
+
+            for row in (physical - top) as u16..rows {
+                if physical == history {
+                    screen_start = lines.len();
+                }
+                if physical == history + cur_row as usize {
+                    cursor = (lines.len(), current.len() + cur_col as usize);
+                }
+
+                current.extend((0..cols).filter_map(|col| screen.cell(row, col).cloned()));
+
+                if !screen.row_wrapped(row) {
+                    trim_blanks(&mut current);
+                    lines.push(std::mem::take(&mut current));
+                }
+                physical += 1;
+            }
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+        screen.set_scrollback(0);
+
+        // Nothing below the cursor and the last non-empty line is worth
+        // replaying; those rows would just push content up.
+        let last = lines
+            .iter()
+            .rposition(|l| !l.is_empty())
+            .unwrap_or(0)
+            .max(cursor.0);
+        lines.truncate(last + 1);
+
+        // The cursor may sit past the trimmed content (e.g. after a prompt's
+        // trailing space); keep those blanks so it lands in the same spot.
+        let (line, offset) = cursor;
+        if let Some(l) = lines.get_mut(line)
+            && l.len() < offset
+        {
+            l.resize(offset, blank_cell());
+        }
+
+        let mut modes = screen.attributes_formatted();
+        modes.extend(screen.input_mode_formatted());
+
+        Snapshot {
+            lines,
+            screen_start,
+            cursor,
+            modes,
+            rows,
+            grid: GridState::probe(screen),
+        }
+    }
+
+    /// Replay into a freshly created `parser` of the new size (at least two
+    /// rows). History lines are written first and then scrolled off the
+    /// screen, so the old first screen row is again the top row. The scroll
+    /// region, origin mode and saved cursor come back last.
+    pub fn replay<CB: Callbacks>(&self, parser: &mut Parser<CB>) {
+        let (rows, cols) = parser.screen().size();
+        let mut buf = Vec::new();
+
+        if self.screen_start > 0 {
+            for line in &self.lines[..self.screen_start] {
+                write_line(&mut buf, line);
+                buf.extend_from_slice(b"\x1b[m\r\n");
+            }
+            // Wherever the cursor ended up, `rows - 1` more newlines reach
+            // the bottom and push every remaining history row into the
+            // scrollback, leaving a blank screen.
+            buf.extend_from_slice(&b"\r\n".repeat(rows as usize - 1));
+            buf.extend_from_slice(b"\x1b[H");
+            parser.process(&buf);
+        }
+
+        buf.clear();
+        for (i, line) in self.lines[self.screen_start..].iter().enumerate() {
+            if i > 0 {
+                buf.extend_from_slice(b"\x1b[m\r\n");
+            }
+            write_line(&mut buf, line);
+        }
+        parser.process(&buf);
+
+        // Put the cursor back on its logical position: count how many rows
+        // the replay wrote after it and walk back up from where it stopped.
+        let (line, offset) = self.cursor;
+        let (end_row, _) = parser.screen().cursor_position();
+        let (cur_row, cur_col) = position_of(&self.lines[line], offset, cols);
+        let rows_after = rows_needed(&self.lines[line], cols) - 1 - cur_row
+            + self.lines[line + 1..]
+                .iter()
+                .map(|l| rows_needed(l, cols))
+                .sum::<usize>();
+        let row = (end_row as usize).saturating_sub(rows_after);
+        let col = cur_col.min(cols as usize - 1);
+
+        let cursor = (row as u16, col as u16);
+        let mut buf = self.grid.restore(self.rows, rows, cols, cursor);
+        buf.extend_from_slice(&self.modes);
+        parser.process(&buf);
+    }
+
+    /// Number of rows the history lines take up at width `cols`.
+    pub fn history_rows(&self, cols: u16) -> usize {
+        self.lines[..self.screen_start]
+            .iter()
+            .map(|l| rows_needed(l, cols))
+            .sum()
+    }
+
+    /// Remove the oldest `count` history rows (as wrapped at `cols`) and
+    /// return them formatted, one entry per row. A line that is only partly
+    /// taken keeps its remainder as a line of its own.
+    pub fn take_history_rows(&mut self, count: usize, cols: u16) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut taken = 0;
+
+        while out.len() < count && taken < self.screen_start {
+            let rows = wrap_rows(&self.lines[taken], cols);
+            let need = count - out.len();
+            if rows.len() > need {
+                let cells: usize = rows[..need].iter().map(Vec::len).sum();
+                out.extend(rows[..need].iter().map(format_row));
+                self.lines[taken].drain(..cells);
+                break;
+            }
+            out.extend(rows.iter().map(format_row));
+            taken += 1;
+        }
+
+        self.lines.drain(..taken);
+        self.screen_start -= taken;
+        self.cursor.0 -= taken;
+        out
+    }
+
+    /// All captured lines as text with SGR attributes, separated by `\r\n`.
+    pub fn contents_formatted(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                buf.extend_from_slice(b"\x1b[m\r\n");
+            }
+            write_line(&mut buf, line);
+        }
+        buf.extend_from_slice(b"\x1b[m");
+        buf
+    }
+}
+
+fn blank_cell() -> Cell {
+    // vt100 doesn't expose a Cell constructor; borrow one from a scratch
+    // screen.
+    Parser::new(1, 1, 0).screen().cell(0, 0).unwrap().clone()
+}
+
+fn is_blank(cell: &Cell) -> bool {
+    !cell.has_contents()
+        && !cell.is_wide_continuation()
+        && cell.bgcolor() == Color::Default
+        && !cell.inverse()
+}
+
+fn trim_blanks(line: &mut Line) {
+    while line.last().is_some_and(is_blank) {
+        line.pop();
+    }
+}
+
+/// Width of the cell at `idx` as the parser will lay it out (continuation
+/// cells are skipped, so they contribute nothing on their own).
+fn cell_width(line: &Line, idx: usize) -> usize {
+    if line[idx].is_wide_continuation() {
+        0
+    } else if line[idx].is_wide() {
+        2
+    } else {
+        1
+    }
+}
+
+/// Row and column, relative to the line's first row, at which the cell at
+/// `offset` ends up when `line` is written at width `cols`. Mirrors vt100's
+/// rule that a character which doesn't fit moves to the next row.
+fn position_of(line: &Line, offset: usize, cols: u16) -> (usize, usize) {
+    let cols = cols as usize;
+    let (mut row, mut col) = (0, 0);
+
+    for idx in 0..offset.min(line.len()) {
+        let w = cell_width(line, idx);
+        if w == 0 {
+            continue;
+        }
+        if col + w > cols {
+            row += 1;
+            col = 0;
+        }
+        col += w;
+    }
+
+    // Cursor on the second half of a wide character.
+    if line.get(offset).is_some_and(Cell::is_wide_continuation) {
+        return (row, col.saturating_sub(1));
+    }
+    // A character placed at the cursor would wrap first.
+    let w = if offset < line.len() {
+        cell_width(line, offset)
+    } else {
+        0
+    };
+    if w > 0 && col + w > cols {
+        return (row + 1, 0);
+    }
+    (row, col)
+}
+
+fn rows_needed(line: &Line, cols: u16) -> usize {
+    let (row, _) = position_of(line, line.len(), cols);
+    row + 1
+}
+
+/// Split `line` into the rows it occupies at width `cols`, following the
+/// same rule as `position_of`.
+fn wrap_rows(line: &Line, cols: u16) -> Vec<Line> {
+    let mut rows = vec![Vec::new()];
+    let mut col = 0;
+
+    for (idx, cell) in line.iter().enumerate() {
+        let w = cell_width(line, idx);
+        if w > 0 && col + w > cols as usize {
+            rows.push(Vec::new());
+            col = 0;
+        }
+        col += w;
+        rows.last_mut().expect("at least one row").push(cell.clone());
+    }
+    rows
+}
+
+/// One row as self-contained text: it starts with its own SGR.
+fn format_row(row: &Line) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_line(&mut buf, row);
+    buf
+}
+
+/// Append the cells of `line` as text, emitting SGR only when the
+/// attributes change.
+fn write_line(buf: &mut Vec<u8>, line: &Line) {
+    let mut prev: Option<Vec<u8>> = None;
+
+    for cell in line {
+        if cell.is_wide_continuation() {
+            continue;
+        }
+        let sgr = sgr_for(cell);
+        if prev.as_ref() != Some(&sgr) {
+            buf.extend_from_slice(&sgr);
+            prev = Some(sgr);
+        }
+        if cell.has_contents() {
+            buf.extend_from_slice(cell.contents().as_bytes());
+        } else {
+            buf.push(b' ');
+        }
+    }
+}
+
+fn sgr_for(cell: &Cell) -> Vec<u8> {
+    let mut params = vec!["0".to_string()];
+    if cell.bold() {
+        params.push("1".into());
+    }
+    if cell.dim() {
+        params.push("2".into());
+    }
+    if cell.italic() {
+        params.push("3".into());
+    }
+    if cell.underline() {
+        params.push("4".into());
+    }
+    if cell.inverse() {
+        params.push("7".into());
+    }
+    push_color(&mut params, cell.fgcolor(), 30, 90, 38);
+    push_color(&mut params, cell.bgcolor(), 40, 100, 48);
+
+    format!("\x1b[{}m", params.join(";")).into_bytes()
+}
+
+fn push_color(params: &mut Vec<String>, color: Color, base: u8, bright: u8, ext: u8) {
+    match color {
+        Color::Default => {}
+        Color::Idx(i) if i < 8 => params.push((base + i).to_string()),
+        Color::Idx(i) if i < 16 => params.push((bright + i - 8).to_string()),
+        Color::Idx(i) => params.push(format!("{ext};5;{i}")),
+        Color::Rgb(r, g, b) => params.push(format!("{ext};2;{r};{g};{b}")),
+    }
+}
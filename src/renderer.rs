@@ -1,73 +1,553 @@
 // src/renderer.rs
+use crate::layout::{Border, Rect, Split};
+use crate::palette::{self, ColorDepth, Palette, Quantizer, Rgb};
 use crate::terminal::VirtualTerminal;
 use crossterm::{
     cursor,
-    queue,
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal::{Clear, ClearType},
 };
 use std::io::{self, Write};
+use unicode_width::UnicodeWidthStr;
 
-pub struct Renderer;
+/// `crossterm::queue!`, but always as escape sequences. Frames go to
+/// attached clients and to the tests' `Host` as well, so they mustn't turn
+/// into console calls where crossterm thinks the console can't take them.
+macro_rules! queue {
+    ($out:expr $(, $command:expr)* $(,)?) => {{
+        let mut ansi = String::new();
+        $(let _ = crossterm::Command::write_ansi(&$command, &mut ansi);)*
+        $out.write_all(ansi.as_bytes())
+    }};
+}
+
+/// How the host console draws East Asian "ambiguous" width characters
+/// (box drawing, Greek, some symbols). vt100 always treats them as narrow,
+/// so when the host draws them wide we have to re-sync the cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmbiguousWidth {
+    Narrow,
+    Wide,
+}
+
+impl AmbiguousWidth {
+    /// Read `MYUX_AMBIGUOUS_WIDTH` (`narrow` / `wide`), defaulting to narrow.
+    pub fn from_env() -> Self {
+        match std::env::var("MYUX_AMBIGUOUS_WIDTH") {
+            Ok(v) if v.eq_ignore_ascii_case("wide") => AmbiguousWidth::Wide,
+            _ => AmbiguousWidth::Narrow,
+        }
+    }
+}
+
+pub struct Renderer {
+    ambiguous: AmbiguousWidth,
+    /// Brings colors down to what the host can show.
+    quantizer: Quantizer,
+}
 
 impl Renderer {
     pub fn new() -> Self {
-        Renderer
+        Renderer {
+            ambiguous: AmbiguousWidth::Narrow,
+            quantizer: Quantizer::new(ColorDepth::TrueColor),
+        }
+    }
+
+    pub fn set_ambiguous_width(&mut self, ambiguous: AmbiguousWidth) {
+        self.ambiguous = ambiguous;
+    }
+
+    pub fn set_color_depth(&mut self, depth: ColorDepth) {
+        if self.quantizer.depth() != depth {
+            self.quantizer = Quantizer::new(depth);
+        }
     }
 
-    /// Redraw the entire screen from the VT model plus a status bar.
-    pub fn draw(&mut self, term: &VirtualTerminal, status_line: &str) -> io::Result<()> {
-        let (cols, rows) = term.size();
-        let cols = cols as usize;
-        let rows_u16 = rows;
+    /// Number of columns the host console will advance when printing `s`.
+    fn host_width(&self, s: &str) -> usize {
+        match self.ambiguous {
+            AmbiguousWidth::Narrow => s.width(),
+            AmbiguousWidth::Wide => s.width_cjk(),
+        }
+    }
 
-        let mut stdout = io::stdout();
+    /// Draw the entire console into `out`: every visible pane at its
+    /// rectangle, the borders between them, and the status bar on the last
+    /// row.
+    ///
+    /// While input is `synchronized` the borders and status bar turn red so
+    /// nobody types into several shells by accident. `status_cursor` puts
+    /// the cursor in the status bar instead of the focused pane. `overlays`
+    /// are drawn on top of the panes, in order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        out: &mut impl Write,
+        size: (u16, u16),
+        panes: &[PaneView],
+        borders: &[Border],
+        overlays: &[Overlay],
+        status_line: &str,
+        status_cursor: Option<u16>,
+        synchronized: bool,
+    ) -> io::Result<()> {
+        let (cols, rows) = size;
+        let (border_fg, status_bg) = if synchronized {
+            (Color::Red, Color::DarkRed)
+        } else {
+            (Color::DarkGrey, Color::DarkGrey)
+        };
 
-        // Get the already-interpreted terminal contents.
-        let lines = term.render_lines();
-        let usable_height = rows_u16.saturating_sub(1) as usize; // last line for status
+        // The cursor stays hidden while it moves around drawing, and after
+        // that unless the focused pane shows it.
+        queue!(out, cursor::Hide)?;
+        for pane in panes {
+            for row in 0..pane.rect.rows {
+                self.draw_row(out, pane.term, pane.rect, 0, row)?;
+            }
+        }
 
-        for row in 0..usable_height {
-            queue!(
-                stdout,
-                cursor::MoveTo(0, row as u16),
-                Clear(ClearType::CurrentLine),
-            )?;
+        queue!(out, SetForegroundColor(border_fg))?;
+        for border in borders {
+            self.draw_border(out, border)?;
+        }
+        queue!(out, ResetColor)?;
 
-            if row < lines.len() {
-                write!(stdout, "{}", lines[row])?;
+        let area = Rect::new(0, 0, cols, rows.saturating_sub(1));
+        for overlay in overlays {
+            match overlay {
+                Overlay::Popup { title, pane } => self.draw_popup(out, title, pane)?,
+                Overlay::Chooser(chooser) => self.draw_chooser(out, chooser, area)?,
             }
         }
 
         // Status bar on the last line.
-        let last_row = rows_u16.saturating_sub(1);
-        let mut status = status_line.to_string();
-        if status.len() < cols {
-            status.push_str(&" ".repeat(cols - status.len()));
-        } else {
-            status.truncate(cols);
-        }
+        let last_row = rows.saturating_sub(1);
+        let status = fit_to_width(status_line, cols as usize);
 
         queue!(
-            stdout,
+            out,
             cursor::MoveTo(0, last_row),
-            SetBackgroundColor(Color::DarkGrey),
+            SetBackgroundColor(status_bg),
             SetForegroundColor(Color::White),
             Clear(ClearType::CurrentLine),
         )?;
-        write!(stdout, "{}", status)?;
-        queue!(stdout, ResetColor)?;
+        write!(out, "{}", status)?;
+        queue!(out, ResetColor)?;
+
+        // Cursor goes to the focused pane, clamped to its rectangle, in
+        // the shape the pane's child asked for. It stays hidden if the
+        // child hid it, or while the pane is scrolled back.
+        if let Some(col) = status_cursor {
+            let col = col.min(cols.saturating_sub(1));
+            queue!(
+                out,
+                cursor::MoveTo(col, last_row),
+                cursor::SetCursorStyle::DefaultUserShape,
+            )?;
+            set_cursor_color(out, None)?;
+            queue!(out, cursor::Show)?;
+        } else if let Some(pane) = overlays
+            .iter()
+            .rev()
+            .find_map(|o| match o {
+                Overlay::Popup { pane, .. } if pane.focused => Some(pane),
+                _ => None,
+            })
+            .or_else(|| panes.iter().find(|p| p.focused))
+            .filter(|p| !p.term.cursor_hidden() && p.term.is_at_bottom())
+        {
+            let (cur_row, cur_col) = pane.term.cursor_pos();
+            let row = pane.rect.y + cur_row.min(pane.rect.rows.saturating_sub(1));
+            let col = pane.rect.x + cur_col.min(pane.rect.cols.saturating_sub(1));
+            queue!(out, cursor::MoveTo(col, row), pane.term.cursor_style())?;
+            set_cursor_color(out, pane.term.palette().cursor)?;
+            queue!(out, cursor::Show)?;
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Write one row of a pane cell by cell, blanks included, so whatever
+    /// was on the console there before is overwritten.
+    ///
+    /// `host_col` tracks where the host cursor really is. Whenever the host's
+    /// idea of a cell's width differs from vt100's (ambiguous characters,
+    /// zero-width leftovers), we move explicitly so the next cell still lands
+    /// in the column the child put it in.
+    ///
+    /// `first_row` is the terminal row shown at the top of `rect`.
+    fn draw_row(
+        &mut self,
+        out: &mut impl Write,
+        term: &VirtualTerminal,
+        rect: Rect,
+        first_row: u16,
+        row: u16,
+    ) -> io::Result<()> {
+        queue!(out, cursor::MoveTo(rect.x, rect.y + row))?;
+        let mut host_col: u16 = 0;
+        let palette = term.palette();
+        let mut style = None;
+
+        for col in 0..rect.cols {
+            let cell = term.cell(first_row + row, col);
+            if cell.is_some_and(|c| c.is_wide_continuation()) {
+                continue;
+            }
+            let cell_style = Style::of(cell, palette, &mut self.quantizer);
+            if style != Some(cell_style) {
+                cell_style.apply(out)?;
+                style = Some(cell_style);
+            }
+
+            let text = cell.map(|c| c.contents()).unwrap_or("");
+            // A character the host draws wider than the pane thinks would
+            // spill onto the border or the next pane at the right edge.
+            let (text, width) = match self.host_width(text) as u16 {
+                0 => (" ", 1),
+                w if col + w > rect.cols => (" ", 1),
+                w => (text, w),
+            };
+
+            if host_col != col {
+                queue!(out, cursor::MoveTo(rect.x + col, rect.y + row))?;
+            }
+            out.write_all(text.as_bytes())?;
+            host_col = col + width;
+        }
+
+        queue!(out, SetAttribute(Attribute::Reset), ResetColor)?;
+        Ok(())
+    }
+
+    /// A popup pane with a frame around it and its title in the top edge.
+    fn draw_popup(&mut self, out: &mut impl Write, title: &str, pane: &PaneView) -> io::Result<()> {
+        let r = pane.rect;
+        let frame = Rect::new(r.x.saturating_sub(1), r.y.saturating_sub(1), r.cols + 2, r.rows + 2);
+        self.draw_frame(out, frame)?;
+        if frame.cols > 6 {
+            queue!(out, cursor::MoveTo(frame.x + 2, frame.y))?;
+            let title = fit_to_width(title, frame.cols as usize - 6);
+            write!(out, " {} ", title.trim_end())?;
+        }
+        for row in 0..r.rows {
+            self.draw_row(out, pane.term, r, 0, row)?;
+        }
+        Ok(())
+    }
+
+    /// The chooser box, inset from `area`: the tree on top, the preview of
+    /// the selected pane below a separator.
+    fn draw_chooser(&mut self, out: &mut impl Write, chooser: &ChooserView, area: Rect) -> io::Result<()> {
+        if area.cols < 8 || area.rows < 5 {
+            return Ok(());
+        }
+        let frame = Rect::new(area.x + 2, area.y + 1, area.cols - 4, area.rows - 2);
+        self.draw_frame(out, frame)?;
+        let inner = Rect::new(frame.x + 1, frame.y + 1, frame.cols - 2, frame.rows - 2);
+
+        // The list gets half the box when there is a preview to show.
+        let list_rows = match chooser.preview {
+            Some(_) if inner.rows >= 5 => (inner.rows / 2).min(chooser.lines.len().max(1) as u16),
+            _ => inner.rows,
+        };
+        let first = match chooser.selected {
+            Some(sel) if sel >= list_rows as usize => sel + 1 - list_rows as usize,
+            _ => 0,
+        };
+        for row in 0..list_rows {
+            let idx = first + row as usize;
+            let line = chooser.lines.get(idx).map_or("", String::as_str);
+            queue!(out, cursor::MoveTo(inner.x, inner.y + row))?;
+            if chooser.selected == Some(idx) {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            write!(out, "{}", fit_to_width(line, inner.cols as usize))?;
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+
+        let Some(term) = chooser.preview.filter(|_| list_rows < inner.rows) else {
+            return Ok(());
+        };
+        self.draw_border(
+            out,
+            &Border {
+                rect: Rect::new(inner.x, inner.y + list_rows, inner.cols, 1),
+                dir: Split::Vertical,
+            },
+        )?;
+        let rect = Rect::new(
+            inner.x,
+            inner.y + list_rows + 1,
+            inner.cols,
+            inner.rows - list_rows - 1,
+        );
+        // Show the rows around the cursor, where things happen. Cells past
+        // the pane's edge come out blank.
+        let (cur_row, _) = term.cursor_pos();
+        let first_row = (cur_row + 1).saturating_sub(rect.rows);
+        for row in 0..rect.rows {
+            self.draw_row(out, term, rect, first_row, row)?;
+        }
+        Ok(())
+    }
 
-        let (cur_row, cur_col) = term.cursor_pos();
+    /// A box outline around `rect`.
+    fn draw_frame(&self, out: &mut impl Write, rect: Rect) -> io::Result<()> {
+        let [tl, tr, bl, br, h, v] = match self.ambiguous {
+            AmbiguousWidth::Narrow => ["┌", "┐", "└", "┘", "─", "│"],
+            AmbiguousWidth::Wide => ["+", "+", "+", "+", "-", "|"],
+        };
+        let inner = rect.cols.saturating_sub(2) as usize;
+        let bottom = rect.y + rect.rows - 1;
+        queue!(out, cursor::MoveTo(rect.x, rect.y))?;
+        write!(out, "{tl}{}{tr}", h.repeat(inner))?;
+        for y in rect.y + 1..bottom {
+            queue!(out, cursor::MoveTo(rect.x, y))?;
+            write!(out, "{v}")?;
+            queue!(out, cursor::MoveTo(rect.x + rect.cols - 1, y))?;
+            write!(out, "{v}")?;
+        }
+        queue!(out, cursor::MoveTo(rect.x, bottom))?;
+        write!(out, "{bl}{}{br}", h.repeat(inner))?;
+        Ok(())
+    }
+
+    fn draw_border(&self, out: &mut impl Write, border: &Border) -> io::Result<()> {
+        // Box drawing characters are ambiguous width; fall back to ASCII
+        // when the host would draw them two cells wide.
+        let (vertical, horizontal) = match self.ambiguous {
+            AmbiguousWidth::Narrow => ("│", "─"),
+            AmbiguousWidth::Wide => ("|", "-"),
+        };
+
+        let r = border.rect;
+        match border.dir {
+            // Side-by-side panes: vertical line.
+            Split::Horizontal => {
+                for y in r.y..r.y + r.rows {
+                    queue!(out, cursor::MoveTo(r.x, y))?;
+                    out.write_all(vertical.as_bytes())?;
+                }
+            }
+            Split::Vertical => {
+                queue!(out, cursor::MoveTo(r.x, r.y))?;
+                out.write_all(horizontal.repeat(r.cols as usize).as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
 
-        // keep cursor out of the status bar row:
-        let max_row = rows_u16.saturating_sub(2);
-        let row = cur_row.min(max_row);
-        let col = cur_col.min((cols as u16).saturating_sub(1));
+/// How a cell looks on the host: its colors through the pane's palette,
+/// and its attributes.
+#[derive(Clone, Copy, PartialEq)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
 
-        queue!(stdout, cursor::MoveTo(col, row), cursor::Show)?;
+impl Style {
+    /// A cell past the edge of the pane (None) is blank.
+    fn of(cell: Option<&vt100::Cell>, palette: &Palette, quantizer: &mut Quantizer) -> Self {
+        // Short of true color, the host's own defaults and first 16 stand
+        // in for the palette's; only the rest has to come down to its colors.
+        let truecolor = quantizer.depth() == ColorDepth::TrueColor;
+        let mut color = |color: vt100::Color, default: Rgb| {
+            let rgb = match color {
+                vt100::Color::Default if !truecolor => return Color::Reset,
+                vt100::Color::Idx(i) if i < 16 && !truecolor => return Color::AnsiValue(i),
+                vt100::Color::Default => default,
+                vt100::Color::Idx(i) if i < 16 => palette.colors[i as usize],
+                vt100::Color::Idx(i) if quantizer.depth() != ColorDepth::Ansi16 => {
+                    return Color::AnsiValue(i);
+                }
+                vt100::Color::Idx(i) => palette::xterm_rgb(i),
+                vt100::Color::Rgb(r, g, b) => Rgb(r, g, b),
+            };
+            match quantizer.depth() {
+                ColorDepth::TrueColor => Color::Rgb {
+                    r: rgb.0,
+                    g: rgb.1,
+                    b: rgb.2,
+                },
+                _ => Color::AnsiValue(quantizer.index(rgb)),
+            }
+        };
+        let (fg, bg) = match cell {
+            Some(c) => (c.fgcolor(), c.bgcolor()),
+            None => (vt100::Color::Default, vt100::Color::Default),
+        };
+        Style {
+            fg: color(fg, palette.foreground),
+            bg: color(bg, palette.background),
+            bold: cell.is_some_and(|c| c.bold()),
+            dim: cell.is_some_and(|c| c.dim()),
+            italic: cell.is_some_and(|c| c.italic()),
+            underline: cell.is_some_and(|c| c.underline()),
+            inverse: cell.is_some_and(|c| c.inverse()),
+        }
+    }
 
-        stdout.flush()?;
+    fn apply(self, out: &mut impl Write) -> io::Result<()> {
+        queue!(out, SetAttribute(Attribute::Reset))?;
+        set_color(out, self.fg, 30)?;
+        set_color(out, self.bg, 40)?;
+        for (on, attribute) in [
+            (self.bold, Attribute::Bold),
+            (self.dim, Attribute::Dim),
+            (self.italic, Attribute::Italic),
+            (self.underline, Attribute::Underlined),
+            (self.inverse, Attribute::Reverse),
+        ] {
+            if on {
+                queue!(out, SetAttribute(attribute))?;
+            }
+        }
         Ok(())
     }
 }
+
+/// SGR for a foreground (`base` 30) or background (40) color. The first 16
+/// get the codes every host knows rather than `38;5;n`.
+fn set_color(out: &mut impl Write, color: Color, base: u8) -> io::Result<()> {
+    match color {
+        Color::AnsiValue(i @ 0..8) => write!(out, "\x1b[{}m", base + i),
+        Color::AnsiValue(i @ 8..16) => write!(out, "\x1b[{}m", base + 60 + i - 8),
+        color if base == 30 => queue!(out, SetForegroundColor(color)),
+        color => queue!(out, SetBackgroundColor(color)),
+    }
+}
+
+/// OSC 12 for the pane's cursor color, or OSC 112 for the host's own.
+fn set_cursor_color(out: &mut impl Write, color: Option<Rgb>) -> io::Result<()> {
+    match color {
+        Some(color) => write!(out, "\x1b]12;{}\x1b\\", color.html()),
+        None => write!(out, "\x1b]112\x1b\\"),
+    }
+}
+
+/// A pane to draw: its terminal and where it goes on the console.
+pub struct PaneView<'a> {
+    pub term: &'a VirtualTerminal,
+    pub rect: Rect,
+    pub focused: bool,
+}
+
+/// Drawn over the panes.
+pub enum Overlay<'a> {
+    /// `pane.rect` is the inside of the frame.
+    Popup { title: String, pane: PaneView<'a> },
+    Chooser(ChooserView<'a>),
+}
+
+/// The chooser overlay: tree lines, the selected one, and the pane to
+/// preview.
+pub struct ChooserView<'a> {
+    pub lines: Vec<String>,
+    pub selected: Option<usize>,
+    pub preview: Option<&'a VirtualTerminal>,
+}
+
+/// Fit a frame drawn for a console of size `from` onto one of size `to`,
+/// for a client whose size isn't the session's. The panes stay at the top
+/// left, cropped if they don't fit; the status bar goes to the bottom row;
+/// whatever the session doesn't cover is filled with dots.
+pub fn fit_frame(frame: &[u8], from: (u16, u16), to: (u16, u16)) -> Vec<u8> {
+    let (from_cols, from_rows) = (from.0.max(1), from.1.max(2));
+    let (to_cols, to_rows) = (to.0.max(1), to.1.max(1));
+    let status_row = from_rows - 1;
+
+    // Replay the frame on a screen of its own size, then copy it over.
+    let mut canvas = vt100::Parser::new(from_rows, from_cols, 0);
+    canvas.process(frame);
+    let screen = canvas.screen();
+    let width = from_cols.min(to_cols);
+    let rows: Vec<Vec<u8>> = screen.rows_formatted(0, width).collect();
+
+    let mut out = Vec::new();
+    let _ = queue!(out, cursor::Hide);
+    for row in 0..to_rows {
+        let source = if row == to_rows - 1 {
+            Some(status_row)
+        } else {
+            (row < status_row).then_some(row)
+        };
+        let _ = queue!(
+            out,
+            cursor::MoveTo(0, row),
+            SetAttribute(Attribute::Reset),
+            ResetColor,
+            Clear(ClearType::UntilNewLine),
+        );
+        let unused = match source {
+            Some(source) => {
+                out.extend_from_slice(&rows[source as usize]);
+                let _ = queue!(
+                    out,
+                    SetAttribute(Attribute::Reset),
+                    ResetColor,
+                    cursor::MoveTo(width, row)
+                );
+                to_cols - width
+            }
+            None => to_cols,
+        };
+        let _ = queue!(out, SetForegroundColor(Color::DarkGrey));
+        out.extend(std::iter::repeat_n(b'.', unused as usize));
+        let _ = queue!(out, ResetColor);
+    }
+
+    // The cursor only shows if its cell made it onto this console.
+    let (row, col) = screen.cursor_position();
+    let row = if row == status_row {
+        Some(to_rows - 1)
+    } else {
+        (row < to_rows - 1).then_some(row)
+    };
+    if let Some(row) = row
+        && col < to_cols
+        && !screen.hide_cursor()
+    {
+        let _ = queue!(out, cursor::MoveTo(col, row));
+        out.extend_from_slice(cursor_style(frame).unwrap_or(b"\x1b[0 q"));
+        let _ = queue!(out, cursor::Show);
+    }
+    out
+}
+
+/// The last cursor shape (DECSCUSR) set in `frame`, which vt100 doesn't
+/// keep.
+fn cursor_style(frame: &[u8]) -> Option<&[u8]> {
+    frame
+        .windows(5)
+        .rev()
+        .find(|w| w.starts_with(b"\x1b[") && w[2].is_ascii_digit() && w.ends_with(b" q"))
+}
+
+/// Truncate or pad `s` to exactly `width` display columns. A wide character
+/// that would straddle the edge is replaced by padding.
+fn fit_to_width(s: &str, width: usize) -> String {
+    let mut out = String::with_capacity(width);
+    let mut used = 0;
+
+    for c in s.chars() {
+        let w = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+
+    out.push_str(&" ".repeat(width - used));
+    out
+}
@@ -0,0 +1,659 @@
+// src/rpc.rs
+//
+// JSON-RPC 2.0 for tools that want structured access to myux instead of
+// scraping the screen. It is served on its own named pipe (`<ipc pipe>-rpc`),
+// any number of clients at a time, one JSON message per line each way.
+//
+// Methods:
+//
+//   list_tabs                                  [{id, index, name, active, focus, panes}]
+//   list_panes {tab?}                          [{id, tab, command, cwd, title, cols, rows, focused}]
+//   new_tab {command?, cwd?}                   {tab, pane}
+//   close_tab {tab}
+//   split {pane?, direction?, command?, cwd?}  {pane}   direction: "horizontal" | "vertical"
+//   close_pane {pane}
+//   send_input {pane, data}
+//   capture {pane, cells?, scrollback?}        {cols, rows, cursor, lines, cells?, scrollback?}
+//   resize {cols, rows}                        sets size-policy to <cols>x<rows+1>
+//   subscribe {events} / unsubscribe {events?}  events: "output", "exit", "title", "bell"
+//
+// Tabs are named by their id, which doesn't change as tabs come and go.
+// Subscribers get notifications `{"method": "event", "params": {"event": ...,
+// "pane": ..., ...}}`: output carries `data`, exit `status`, title `title`.
+//
+// Each connection has a reader thread handing calls to the main loop and a
+// writer thread sending whatever the main loop replies or publishes.
+
+use crate::app::{App, SHELL, TabId};
+use crate::clients::SizePolicy;
+use crate::ipc::{self, Pipe};
+use crate::layout::{PaneId, Split};
+use crate::wake;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Longest message we accept.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The call was understood but myux couldn't do it.
+const FAILED: i64 = -32000;
+
+pub fn pipe_name() -> String {
+    format!("{}-rpc", ipc::pipe_name())
+}
+
+/// One client connection, as far as the main loop is concerned.
+#[derive(Clone)]
+pub struct Conn {
+    id: u64,
+    out: Sender<String>,
+}
+
+impl Conn {
+    fn send(&self, message: &Value) {
+        let _ = self.out.send(message.to_string());
+    }
+}
+
+/// What the connection threads hand to the main loop.
+pub enum Message {
+    Call {
+        conn: Conn,
+        id: Value,
+        method: String,
+        params: Value,
+    },
+    /// The client went away.
+    Gone(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    Output,
+    Exit,
+    Title,
+    Bell,
+}
+
+impl Event {
+    const ALL: &[Event] = &[Event::Output, Event::Exit, Event::Title, Event::Bell];
+
+    fn name(self) -> &'static str {
+        match self {
+            Event::Output => "output",
+            Event::Exit => "exit",
+            Event::Title => "title",
+            Event::Bell => "bell",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Event> {
+        Event::ALL.iter().copied().find(|e| e.name() == name)
+    }
+}
+
+/// Clients that asked for events, and what they asked for.
+#[derive(Default)]
+pub struct Subscribers {
+    conns: Vec<(Conn, HashSet<Event>)>,
+    /// Start of a UTF-8 sequence split between two chunks of a pane's
+    /// output, held back until the rest arrives.
+    partial: HashMap<PaneId, Vec<u8>>,
+}
+
+impl Subscribers {
+    pub fn wants(&self, event: Event) -> bool {
+        self.conns.iter().any(|(_, events)| events.contains(&event))
+    }
+
+    pub fn publish(&self, event: Event, mut params: Value) {
+        params["event"] = event.name().into();
+        let message = json!({ "jsonrpc": "2.0", "method": "event", "params": params });
+        for (conn, events) in &self.conns {
+            if events.contains(&event) {
+                conn.send(&message);
+            }
+        }
+    }
+
+    /// Publish a chunk of pane output as text.
+    pub fn output(&mut self, pane: PaneId, bytes: &[u8]) {
+        if !self.wants(Event::Output) {
+            return;
+        }
+        let mut data = self.partial.remove(&pane).unwrap_or_default();
+        data.extend_from_slice(bytes);
+        // Only an incomplete sequence at the very end is held back.
+        if let Err(e) = std::str::from_utf8(&data)
+            && e.error_len().is_none()
+        {
+            self.partial.insert(pane, data.split_off(e.valid_up_to()));
+        }
+        if !data.is_empty() {
+            let data = String::from_utf8_lossy(&data);
+            self.publish(Event::Output, json!({ "pane": pane, "data": data }));
+        }
+    }
+
+    pub fn pane_gone(&mut self, pane: PaneId) {
+        self.partial.remove(&pane);
+    }
+
+    fn entry(&mut self, conn: &Conn) -> &mut HashSet<Event> {
+        let index = match self.conns.iter().position(|(c, _)| c.id == conn.id) {
+            Some(index) => index,
+            None => {
+                self.conns.push((conn.clone(), HashSet::new()));
+                self.conns.len() - 1
+            }
+        };
+        &mut self.conns[index].1
+    }
+
+    fn remove(&mut self, conn: u64) {
+        self.conns.retain(|(c, _)| c.id != conn);
+    }
+}
+
+/// Serve the RPC pipe on a background thread. Fails when another myux
+/// already owns it.
+pub fn serve(tx: wake::Sender<Message>) -> windows::core::Result<()> {
+    let mut next_id = 0;
+    ipc::listen(&pipe_name(), move |pipe| {
+        next_id += 1;
+        serve_client(Arc::new(pipe), next_id, &tx);
+    })
+}
+
+/// Start the threads of a new connection. The pipe is closed once both are
+/// done: the reader when the client goes away, the writer when nothing is
+/// left to send.
+fn serve_client(pipe: Arc<Pipe>, id: u64, tx: &wake::Sender<Message>) {
+    let (out_tx, out_rx) = mpsc::channel::<String>();
+    let conn = Conn { id, out: out_tx };
+
+    let writer = Arc::clone(&pipe);
+    thread::spawn(move || {
+        for line in out_rx {
+            if writer.write_all(format!("{line}\n").as_bytes()).is_err() {
+                return;
+            }
+        }
+    });
+
+    let tx = tx.clone();
+    thread::spawn(move || {
+        read_calls(&pipe, &conn, &tx);
+        let _ = tx.send(Message::Gone(conn.id));
+    });
+}
+
+/// Hand every call on the connection to the main loop until the client
+/// goes away.
+fn read_calls(pipe: &Pipe, conn: &Conn, tx: &wake::Sender<Message>) {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pending = Vec::new();
+    loop {
+        let Ok(read) = pipe.read(&mut buf) else {
+            return;
+        };
+        pending.extend_from_slice(&buf[..read]);
+
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_call(line.trim()) {
+                Ok((id, method, params)) => {
+                    let call = Message::Call {
+                        conn: conn.clone(),
+                        id,
+                        method,
+                        params,
+                    };
+                    if tx.send(call).is_err() {
+                        return;
+                    }
+                }
+                Err((id, err)) => conn.send(&err.reply(id)),
+            }
+        }
+        if pending.len() > MAX_MESSAGE {
+            conn.send(&RpcError::new(PARSE_ERROR, "message too long").reply(Value::Null));
+            return;
+        }
+    }
+}
+
+/// The id, method and params of a request.
+fn parse_call(line: &str) -> Result<(Value, String, Value), (Value, RpcError)> {
+    let message: Value = serde_json::from_str(line)
+        .map_err(|e| (Value::Null, RpcError::new(PARSE_ERROR, e.to_string())))?;
+    let id = message.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        return Err((id, RpcError::new(INVALID_REQUEST, "no method")));
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    Ok((id, method.to_string(), params))
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn params(message: impl Into<String>) -> Self {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+
+    fn reply(&self, id: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": self.code, "message": self.message },
+        })
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        RpcError::new(FAILED, message)
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+/// Act on a message from a connection thread.
+pub fn handle(app: &mut App, message: Message) {
+    match message {
+        Message::Gone(conn) => app.rpc.remove(conn),
+        Message::Call {
+            conn,
+            id,
+            method,
+            params,
+        } => {
+            // A pane the call opened that can't be started fails it.
+            let result = call(app, &conn, &method, &params).and_then(|value| {
+                crate::run_queued(app)
+                    .map(|()| value)
+                    .map_err(|e| RpcError::from(e.to_string()))
+            });
+            // Notifications (no id) get no reply.
+            if id.is_null() {
+                return;
+            }
+            conn.send(&match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(err) => err.reply(id),
+            });
+        }
+    }
+}
+
+fn call(app: &mut App, conn: &Conn, method: &str, params: &Value) -> RpcResult {
+    match method {
+        "list_tabs" => Ok(list_tabs(app)),
+        "list_panes" => list_panes(app, params),
+        "new_tab" => new_tab(app, params),
+        "close_tab" => {
+            let index = tab_index(app, params)?;
+            app.kill_tab(index)?;
+            Ok(Value::Null)
+        }
+        "split" => split(app, params),
+        "close_pane" => {
+            let pane = pane_param(app, params)?;
+            app.kill_pane(pane)?;
+            Ok(Value::Null)
+        }
+        "send_input" => {
+            let pane = pane_param(app, params)?;
+            let data = str_param(params, "data")?.ok_or_else(|| RpcError::params("no data"))?;
+            let pane = app.pane(pane).unwrap();
+            pane.pty.write(data.as_bytes());
+            Ok(Value::Null)
+        }
+        "capture" => capture(app, params),
+        "resize" => {
+            let size = |name| match params.get(name).and_then(Value::as_u64) {
+                Some(n @ 2..=10_000) => Ok(n as u16),
+                _ => Err(RpcError::params(format!("bad {name}"))),
+            };
+            let (cols, rows) = (size("cols")?, size("rows")?);
+            // `rows` is what the panes get; the status bar comes on top.
+            // Fixed, like `set size-policy`, so clients don't undo it.
+            app.clients.policy = SizePolicy::Fixed(cols, rows + 1);
+            app.apply_size_policy();
+            Ok(Value::Null)
+        }
+        "subscribe" => {
+            let events = events_param(params)?.unwrap_or_else(|| Event::ALL.to_vec());
+            let subscribed = app.rpc.entry(conn);
+            subscribed.extend(events);
+            let mut names: Vec<&str> = subscribed.iter().map(|e| e.name()).collect();
+            names.sort_unstable();
+            Ok(json!(names))
+        }
+        "unsubscribe" => {
+            match events_param(params)? {
+                Some(events) => {
+                    let subscribed = app.rpc.entry(conn);
+                    for event in events {
+                        subscribed.remove(&event);
+                    }
+                }
+                None => app.rpc.remove(conn.id),
+            }
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no method {method}"),
+        )),
+    }
+}
+
+fn list_tabs(app: &App) -> Value {
+    let tabs: Vec<Value> = app
+        .tabs
+        .iter()
+        .enumerate()
+        .map(|(i, tab)| {
+            json!({
+                "id": tab.id,
+                "index": i + 1,
+                "name": tab.name,
+                "active": i == app.active,
+                "focus": tab.focus,
+                "panes": tab.panes.iter().map(|p| p.id).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    tabs.into()
+}
+
+fn list_panes(app: &App, params: &Value) -> RpcResult {
+    let only = match params.get("tab") {
+        Some(_) => Some(app.tabs[tab_index(app, params)?].id),
+        None => None,
+    };
+    let mut panes = Vec::new();
+    for (i, tab) in app.tabs.iter().enumerate() {
+        if only.is_some_and(|id| id != tab.id) {
+            continue;
+        }
+        for pane in &tab.panes {
+            let (cols, rows) = pane.term.size();
+            panes.push(json!({
+                "id": pane.id,
+                "tab": tab.id,
+                "command": pane.command,
+                "cwd": pane.current_dir(),
+                "title": pane.term.title(),
+                "cols": cols,
+                "rows": rows,
+                "focused": i == app.active && pane.id == tab.focus,
+            }));
+        }
+    }
+    Ok(panes.into())
+}
+
+fn new_tab(app: &mut App, params: &Value) -> RpcResult {
+    let command = str_param(params, "command")?.unwrap_or(SHELL);
+    let cwd = str_param(params, "cwd")?;
+    app.new_tab(command, cwd);
+    let tab = app.active_tab();
+    Ok(json!({ "tab": tab.id, "pane": tab.focus }))
+}
+
+fn split(app: &mut App, params: &Value) -> RpcResult {
+    let pane = match params.get("pane") {
+        Some(_) => pane_param(app, params)?,
+        None => app.active_tab().focus,
+    };
+    let Some(index) = app.tabs.iter().position(|t| t.pane(pane).is_some()) else {
+        return Err(RpcError::params(format!("pane {pane} is a popup")));
+    };
+    let dir = match str_param(params, "direction")? {
+        None | Some("vertical") => Split::Vertical,
+        Some("horizontal") => Split::Horizontal,
+        Some(other) => return Err(RpcError::params(format!("bad direction: {other}"))),
+    };
+    let command = str_param(params, "command")?.unwrap_or(SHELL);
+    let cwd = str_param(params, "cwd")?.map(str::to_string);
+    let new = app.split_pane(index, pane, dir, command, cwd)?;
+    Ok(json!({ "pane": new }))
+}
+
+fn capture(app: &mut App, params: &Value) -> RpcResult {
+    let id = pane_param(app, params)?;
+    let flag = |name| params.get(name).and_then(Value::as_bool).unwrap_or(false);
+    let (cells, scrollback) = (flag("cells"), flag("scrollback"));
+    let term = &mut app.pane_mut(id).unwrap().term;
+
+    let (cols, rows) = term.size();
+    let (cursor_row, cursor_col) = term.cursor_pos();
+    let screen = term.live_cells();
+    let lines: Vec<String> = screen
+        .iter()
+        .map(|row| {
+            let text: String = row.iter().map(cell_text).collect();
+            text.trim_end().to_string()
+        })
+        .collect();
+
+    let mut result = json!({
+        "cols": cols,
+        "rows": rows,
+        "cursor": [cursor_row, cursor_col],
+        "lines": lines,
+    });
+    if cells {
+        let cells: Vec<Vec<Value>> = screen
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .filter(|c| !c.is_wide_continuation())
+                    .map(cell_json)
+                    .collect()
+            })
+            .collect();
+        result["cells"] = cells.into();
+    }
+    if scrollback {
+        result["scrollback"] = term.history_text().into();
+    }
+    Ok(result)
+}
+
+fn cell_text(cell: &vt100::Cell) -> &str {
+    match cell.contents() {
+        "" if !cell.is_wide_continuation() => " ",
+        text => text,
+    }
+}
+
+/// A cell: its text, plus whatever differs from the default look.
+fn cell_json(cell: &vt100::Cell) -> Value {
+    let mut value = json!({ "text": cell_text(cell) });
+    if cell.is_wide() {
+        value["wide"] = true.into();
+    }
+    for (name, color) in [("fg", cell.fgcolor()), ("bg", cell.bgcolor())] {
+        match color {
+            vt100::Color::Default => {}
+            vt100::Color::Idx(i) => value[name] = i.into(),
+            vt100::Color::Rgb(r, g, b) => value[name] = format!("#{r:02x}{g:02x}{b:02x}").into(),
+        }
+    }
+    for (name, on) in [
+        ("bold", cell.bold()),
+        ("dim", cell.dim()),
+        ("italic", cell.italic()),
+        ("underline", cell.underline()),
+        ("inverse", cell.inverse()),
+    ] {
+        if on {
+            value[name] = true.into();
+        }
+    }
+    value
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<Option<&'a str>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(RpcError::params(format!("{name}: expected a string"))),
+    }
+}
+
+/// The `pane` param, which has to name a pane that exists.
+fn pane_param(app: &App, params: &Value) -> Result<PaneId, RpcError> {
+    let id = params
+        .get("pane")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::params("no pane"))? as PaneId;
+    match app.pane(id) {
+        Some(_) => Ok(id),
+        None => Err(RpcError::params(format!("no pane {id}"))),
+    }
+}
+
+/// Position of the tab named by the `tab` param.
+fn tab_index(app: &App, params: &Value) -> Result<usize, RpcError> {
+    let id = params
+        .get("tab")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::params("no tab"))? as TabId;
+    app.tabs
+        .iter()
+        .position(|t| t.id == id)
+        .ok_or_else(|| RpcError::params(format!("no tab {id}")))
+}
+
+/// The `events` param; None if it is missing.
+fn events_param(params: &Value) -> Result<Option<Vec<Event>>, RpcError> {
+    let Some(events) = params.get("events") else {
+        return Ok(None);
+    };
+    let names = events
+        .as_array()
+        .ok_or_else(|| RpcError::params("events: expected a list"))?;
+    names
+        .iter()
+        .map(|name| {
+            name.as_str()
+                .and_then(Event::parse)
+                .ok_or_else(|| RpcError::params(format!("unknown event: {name}")))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use std::sync::mpsc::Receiver;
+
+    /// A connection without the pipe.
+    struct Client {
+        conn: Conn,
+        rx: Receiver<String>,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let (out, rx) = mpsc::channel();
+            Client {
+                conn: Conn { id: 1, out },
+                rx,
+            }
+        }
+
+        /// Call `method` and return the reply.
+        fn call(&self, h: &mut Harness, method: &str, params: Value) -> Value {
+            let message = Message::Call {
+                conn: self.conn.clone(),
+                id: json!(7),
+                method: method.into(),
+                params,
+            };
+            handle(&mut h.app, message);
+            h.settle();
+            self.next()
+        }
+
+        fn next(&self) -> Value {
+            serde_json::from_str(&self.rx.try_recv().expect("a message")).unwrap()
+        }
+    }
+
+    #[test]
+    fn calls_get_results_or_errors() {
+        let mut h = Harness::new(80, 24);
+        let client = Client::new();
+
+        let reply = client.call(&mut h, "new_tab", json!({ "command": "vim.exe" }));
+        assert_eq!(reply["id"], 7);
+        assert_eq!(reply["result"], json!({ "tab": 1, "pane": 1 }));
+        let reply = client.call(&mut h, "list_panes", json!({ "tab": 1 }));
+        assert_eq!(reply["result"][0]["command"], "vim.exe");
+        assert_eq!(reply["result"][0]["focused"], true);
+
+        let reply = client.call(&mut h, "split", json!({ "direction": "diagonal" }));
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+        let reply = client.call(&mut h, "nope", Value::Null);
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn resizing_outlasts_the_next_key() {
+        let mut h = Harness::new(80, 24);
+        let client = Client::new();
+        let reply = client.call(&mut h, "resize", json!({ "cols": 100, "rows": 30 }));
+        assert_eq!(reply["result"], Value::Null);
+        assert_eq!(h.child_size(0), (100, 30));
+        h.type_text("x");
+        assert_eq!(h.child_size(0), (100, 30));
+    }
+
+    #[test]
+    fn subscribers_hear_about_output_and_exits() {
+        let mut h = Harness::new(80, 24);
+        let client = Client::new();
+        let reply = client.call(&mut h, "subscribe", json!({ "events": ["output", "exit"] }));
+        assert_eq!(reply["result"], json!(["exit", "output"]));
+
+        h.print(0, "h\u{e9}llo");
+        let event = client.next();
+        assert_eq!(event["method"], "event");
+        assert_eq!(event["params"]["data"], "h\u{e9}llo");
+        h.exit(0, 3);
+        let event = client.next();
+        assert_eq!(
+            event["params"],
+            json!({ "event": "exit", "pane": 0, "status": 3 })
+        );
+    }
+}
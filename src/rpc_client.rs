@@ -0,0 +1,160 @@
+// src/rpc_client.rs
+//
+// Client side of the JSON-RPC pipe (see rpc.rs), and `myux rpc`, which
+// wraps it for scripts:
+//
+//   myux rpc <method> [params as JSON]    print the result
+//   myux rpc subscribe [event...]         print events as they come, one per line
+
+use crate::{ipc, rpc};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// myux answered with an error.
+    Rpc {
+        code: i64,
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Rpc { code, message } => write!(f, "{message} ({code})"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub struct Client {
+    reader: BufReader<File>,
+    writer: File,
+    next_id: u64,
+    /// Events that arrived while waiting for a reply.
+    events: VecDeque<Value>,
+}
+
+impl Client {
+    /// Connect to the running myux.
+    pub fn connect() -> io::Result<Self> {
+        let pipe = ipc::connect(&rpc::pipe_name(), false)?;
+        Ok(Client {
+            writer: pipe.try_clone()?,
+            reader: BufReader::new(pipe),
+            next_id: 1,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Call `method` and wait for its result.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        writeln!(self.writer, "{request}")?;
+
+        loop {
+            let message = self.read()?;
+            if message.get("method").is_some() {
+                self.events.push_back(message["params"].clone());
+                continue;
+            }
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(err) = message.get("error") {
+                return Err(Error::Rpc {
+                    code: err["code"].as_i64().unwrap_or(0),
+                    message: err["message"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+            return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+
+    /// The next event of a subscription, waiting for one if need be.
+    pub fn next_event(&mut self) -> Result<Value, Error> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let message = self.read()?;
+            if message.get("method").is_some() {
+                return Ok(message["params"].clone());
+            }
+        }
+    }
+
+    fn read(&mut self) -> io::Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// `myux rpc ...`; returns the exit code.
+pub fn cli(args: &[String]) -> i32 {
+    match run_cli(args) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("myux: {err}");
+            1
+        }
+    }
+}
+
+fn run_cli(args: &[String]) -> Result<(), Error> {
+    let Some((method, rest)) = args.split_first() else {
+        return Err(invalid(
+            "usage: myux rpc <method> [params] | myux rpc subscribe [event...]",
+        ));
+    };
+    let mut client = Client::connect()
+        .map_err(|e| io::Error::new(e.kind(), format!("no running myux to talk to ({e})")))?;
+
+    if method == "subscribe" {
+        let params = match rest {
+            [] => Value::Null,
+            events => json!({ "events": events }),
+        };
+        client.call("subscribe", params)?;
+        let mut out = io::stdout().lock();
+        loop {
+            let event = client.next_event()?;
+            writeln!(out, "{event}")?;
+            out.flush()?;
+        }
+    }
+
+    let params = match rest {
+        [] => Value::Null,
+        [json] => serde_json::from_str(json).map_err(|e| invalid(&format!("params: {e}")))?,
+        _ => return Err(invalid("params go in one JSON argument")),
+    };
+    let result = client.call(method, params)?;
+    if !result.is_null() {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&result).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
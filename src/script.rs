@@ -0,0 +1,381 @@
+// src/script.rs
+//
+// Rhai scripts: custom commands (`call <function>`), hook handlers and
+// status bar segments. Scripts are loaded from the scripts directory at
+// startup or with `load-script`; their top-level code runs once, their
+// functions stay around.
+//
+// The functions scripts call reach the App through a pointer that is only
+// set while the main thread is running script code on its behalf (see
+// `with_app`); outside of that they fail. A script can't start another one
+// through a myux command (`call`, `load-script`) while it holds the App.
+
+use crate::app::App;
+use crate::command;
+use crate::hooks::Hook;
+use rhai::{AST, Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::rc::Rc;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often status segments are recomputed.
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub struct Scripts {
+    engine: Rc<Engine>,
+    /// Functions of every script loaded so far.
+    ast: AST,
+    /// Functions whose results make up the script part of the status bar.
+    segments: Vec<String>,
+    /// Their last results, joined.
+    pub status: String,
+    refreshed: Option<Instant>,
+    /// The segments are being recomputed.
+    in_segment: bool,
+    /// `shell()` calls of the segments, by command line.
+    shells: HashMap<String, ShellRun>,
+}
+
+/// A status segment's `shell()` command, run on a thread of its own so a
+/// slow one can't hold up the screen.
+#[derive(Default)]
+struct ShellRun {
+    /// Output of the last run that finished.
+    output: String,
+    running: Option<mpsc::Receiver<String>>,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        Scripts {
+            engine: Rc::new(new_engine()),
+            ast: AST::empty(),
+            segments: Vec::new(),
+            status: String::new(),
+            refreshed: None,
+            in_segment: false,
+            shells: HashMap::new(),
+        }
+    }
+
+    /// What `shell(line)` gave last time, starting another run unless one
+    /// is still going. The first call gives nothing.
+    fn shell_later(&mut self, line: &str) -> String {
+        let run = self.shells.entry(line.to_string()).or_default();
+        if let Some(rx) = &run.running {
+            match rx.try_recv() {
+                Ok(output) => run.output = output,
+                Err(TryRecvError::Empty) => return run.output.clone(),
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+        let (tx, rx) = mpsc::channel();
+        let line = line.to_string();
+        thread::spawn(move || {
+            let _ = tx.send(shell(&line).unwrap_or_default());
+        });
+        run.running = Some(rx);
+        run.output.clone()
+    }
+}
+
+thread_local! {
+    static APP: Cell<*mut App> = const { Cell::new(std::ptr::null_mut()) };
+}
+
+/// Run `f` with the script API pointed at `app`, unless a script is
+/// running already: then the App is lent to it.
+fn with_app<T>(app: &mut App, f: impl FnOnce() -> T) -> Result<T, String> {
+    struct Lent;
+    impl Drop for Lent {
+        fn drop(&mut self) {
+            APP.set(std::ptr::null_mut());
+        }
+    }
+
+    if !APP.get().is_null() {
+        return Err("scripts can't be run from a script".into());
+    }
+    APP.set(app);
+    let _lent = Lent;
+    Ok(f())
+}
+
+/// Give a script function the App it is running for.
+fn app<T>(f: impl FnOnce(&mut App) -> T) -> ScriptResult<T> {
+    let app = APP.get();
+    if app.is_null() {
+        return Err("myux is not available here".into());
+    }
+    // SAFETY: set by `with_app` from a `&mut App` that the main thread
+    // isn't using while the script runs. Each script function takes the
+    // App for the length of `f` only, and nothing in `f` gets here again:
+    // `with_app` won't start another script meanwhile.
+    Ok(f(unsafe { &mut *app }))
+}
+
+/// Where scripts are loaded from at startup: `MYUX_SCRIPTS`, else
+/// `%USERPROFILE%\.myux\scripts`.
+pub fn script_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("MYUX_SCRIPTS") {
+        return Some(PathBuf::from(dir));
+    }
+    let home = std::env::var("USERPROFILE").ok()?;
+    Some(Path::new(&home).join(".myux").join("scripts"))
+}
+
+/// Load every `*.rhai` in `dir`, in name order, stopping at the first error.
+pub fn load_dir(app: &mut App, dir: &Path) -> Result<(), String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|x| x.eq_ignore_ascii_case("rhai"))
+        })
+        .collect();
+    files.sort();
+    for file in files {
+        load_file(app, &file)?;
+    }
+    Ok(())
+}
+
+/// Compile a script, keep its functions and run its top-level code.
+pub fn load_file(app: &mut App, path: &Path) -> Result<(), String> {
+    let engine = Rc::clone(&app.scripts.engine);
+    let ast = engine
+        .compile_file(path.to_path_buf())
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    // Earlier scripts' functions are callable from this one's top level.
+    let mut merged = app.scripts.ast.merge(&ast);
+    with_app(app, || engine.run_ast(&merged))?
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    merged.clear_statements();
+    app.scripts.ast = merged;
+    Ok(())
+}
+
+/// Call script function `name` with string arguments.
+pub fn call(app: &mut App, name: &str, args: &[String]) -> Result<String, String> {
+    let engine = Rc::clone(&app.scripts.engine);
+    let ast = app.scripts.ast.clone();
+    let args: Vec<Dynamic> = args.iter().map(|a| a.clone().into()).collect();
+    let result = with_app(app, || {
+        let options = CallFnOptions::new().eval_ast(false);
+        engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &ast, name, args)
+    })?;
+    match result {
+        Ok(value) if value.is_unit() => Ok(String::new()),
+        Ok(value) => Ok(value.to_string()),
+        Err(e) => Err(format!("{name}: {e}")),
+    }
+}
+
+/// When the status segments are next due, if there are any.
+pub fn refresh_due(app: &App) -> Option<Instant> {
+    let scripts = &app.scripts;
+    if scripts.segments.is_empty() {
+        return None;
+    }
+    Some(
+        scripts
+            .refreshed
+            .map_or_else(Instant::now, |t| t + STATUS_INTERVAL),
+    )
+}
+
+/// Recompute the status segments if they are due. Returns true if the
+/// text changed.
+pub fn refresh_status(app: &mut App) -> bool {
+    let scripts = &app.scripts;
+    if scripts.segments.is_empty()
+        || scripts
+            .refreshed
+            .is_some_and(|t| t.elapsed() < STATUS_INTERVAL)
+    {
+        return false;
+    }
+    app.scripts.refreshed = Some(Instant::now());
+
+    let mut parts = Vec::new();
+    app.scripts.in_segment = true;
+    for name in app.scripts.segments.clone() {
+        match call(app, &name, &[]) {
+            Ok(text) if text.is_empty() => {}
+            Ok(text) => parts.push(text),
+            Err(_) => parts.push(format!("{name}: error")),
+        }
+    }
+    app.scripts.in_segment = false;
+    let status = parts.join(" | ");
+    let changed = status != app.scripts.status;
+    app.scripts.status = status;
+    changed
+}
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine.on_print(|text| {
+        let _ = app(|app| app.message = Some(text.to_string()));
+    });
+
+    engine.register_fn("tabs", || -> ScriptResult<Array> {
+        app(|app| {
+            app.tabs
+                .iter()
+                .enumerate()
+                .map(|(i, tab)| {
+                    let mut map = Map::new();
+                    map.insert("index".into(), (i as i64 + 1).into());
+                    map.insert("name".into(), tab.name.clone().into());
+                    map.insert("active".into(), (i == app.active).into());
+                    map.insert("panes".into(), (tab.panes.len() as i64).into());
+                    map.into()
+                })
+                .collect()
+        })
+    });
+
+    engine.register_fn("panes", || -> ScriptResult<Array> {
+        app(|app| {
+            let mut panes = Array::new();
+            for (i, tab) in app.tabs.iter().enumerate() {
+                for pane in &tab.panes {
+                    let (cols, rows) = pane.term.size();
+                    let mut map = Map::new();
+                    map.insert("id".into(), (pane.id as i64).into());
+                    map.insert("tab".into(), (i as i64 + 1).into());
+                    map.insert("command".into(), pane.command.clone().into());
+                    map.insert("cwd".into(), pane.current_dir().unwrap_or_default().into());
+                    map.insert("cols".into(), (cols as i64).into());
+                    map.insert("rows".into(), (rows as i64).into());
+                    map.insert(
+                        "focused".into(),
+                        (i == app.active && pane.id == tab.focus).into(),
+                    );
+                    panes.push(map.into());
+                }
+            }
+            panes
+        })
+    });
+
+    engine.register_fn("focused_pane", || -> ScriptResult<i64> {
+        app(|app| app.active_tab().focus as i64)
+    });
+
+    engine.register_fn("send_keys", |pane: i64, text: &str| -> ScriptResult<()> {
+        app(|app| {
+            let pane = app
+                .pane(pane as _)
+                .ok_or_else(|| format!("no pane {pane}"))?;
+            pane.pty.write(text.as_bytes());
+            Ok(())
+        })?
+    });
+
+    engine.register_fn("screen_text", |pane: i64| -> ScriptResult<String> {
+        app(|app| match app.pane(pane as _) {
+            Some(p) => Ok(p.term.screen_text()),
+            None => Err(format!("no pane {pane}").into()),
+        })?
+    });
+
+    // Any myux command line; returns what the command says.
+    engine.register_fn("myux", |line: &str| -> ScriptResult<String> {
+        run_line(line)
+    });
+    engine.register_fn("split", |dir: &str| -> ScriptResult<String> {
+        run(&["split".into(), format!("-{dir}")])
+    });
+    engine.register_fn("rename_tab", |name: &str| -> ScriptResult<String> {
+        run(&["rename-tab".into(), name.into()])
+    });
+    engine.register_fn("select_tab", |index: i64| -> ScriptResult<String> {
+        run(&["select-tab".into(), index.to_string()])
+    });
+
+    // Run function `name` whenever `event` fires.
+    engine.register_fn("on", |event: &str, name: &str| -> ScriptResult<()> {
+        let hook = Hook::parse(event).ok_or_else(|| format!("unknown event: {event}"))?;
+        app(|app| {
+            app.hooks
+                .set(hook, vec!["call".into(), name.to_string()], true)
+        })
+    });
+
+    engine.register_fn("status_segment", |name: &str| -> ScriptResult<()> {
+        app(|app| {
+            app.scripts.segments.push(name.to_string());
+            app.scripts.refreshed = None;
+        })
+    });
+
+    // MYUX_* variables of the event being handled, else the environment.
+    engine.register_fn("env", |name: &str| -> ScriptResult<String> {
+        app(|app| {
+            app.hook_env
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+                .or_else(|| std::env::var(name).ok())
+                .unwrap_or_default()
+        })
+    });
+
+    // Output of `cmd.exe /C line`, trailing newline removed. Status
+    // segments get the previous run's.
+    engine.register_fn("shell", |line: &str| -> ScriptResult<String> {
+        if app(|app| app.scripts.in_segment)? {
+            return app(|app| app.scripts.shell_later(line));
+        }
+        shell(line).map_err(Into::into)
+    });
+
+    engine
+}
+
+fn shell(line: &str) -> Result<String, String> {
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let output = std::process::Command::new("cmd.exe")
+        .arg("/C")
+        .raw_arg(line)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .map_err(|e| format!("{line}: {e}"))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+fn run_line(line: &str) -> ScriptResult<String> {
+    app(|app| {
+        let cwd = app.active_tab().focused().current_dir().map(PathBuf::from);
+        command::run_line(app, line, cwd.as_deref())
+    })?
+    .map_err(Into::into)
+}
+
+/// Run a command given as words, so nothing in them needs quoting.
+fn run(words: &[String]) -> ScriptResult<String> {
+    app(|app| {
+        let cwd = app.active_tab().focused().current_dir().map(PathBuf::from);
+        command::run(app, words, cwd.as_deref())
+    })?
+    .map_err(Into::into)
+}
@@ -0,0 +1,96 @@
+// src/session.rs
+//
+// Saved workspaces: tabs, their pane layout and what runs in each pane,
+// written as JSON by `myux save-session` and read back by `myux load`.
+
+use crate::layout::{Layout, PaneId, Split};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// Index of the tab that was active.
+    #[serde(default)]
+    pub active: usize,
+    pub tabs: Vec<TabSpec>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TabSpec {
+    pub name: String,
+    /// Position of the focused pane in layout order.
+    #[serde(default)]
+    pub focus: usize,
+    pub layout: LayoutSpec,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutSpec {
+    Pane(PaneSpec),
+    Split {
+        dir: Split,
+        first: Box<LayoutSpec>,
+        second: Box<LayoutSpec>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PaneSpec {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// History as text with SGR attributes, replayed into the new pane.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrollback: Option<String>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> io::Result<Session> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+}
+
+impl LayoutSpec {
+    /// Describe `layout`, asking `pane` for the details of every leaf.
+    pub fn from_layout(layout: &Layout, pane: &mut impl FnMut(PaneId) -> PaneSpec) -> Self {
+        match layout {
+            Layout::Pane(id) => LayoutSpec::Pane(pane(*id)),
+            Layout::Split { dir, first, second } => LayoutSpec::Split {
+                dir: *dir,
+                first: Box::new(LayoutSpec::from_layout(first, pane)),
+                second: Box::new(LayoutSpec::from_layout(second, pane)),
+            },
+        }
+    }
+
+    /// Build a layout with fresh pane ids starting at `next_id`, collecting
+    /// the spec of every pane under its new id.
+    pub fn to_layout<'a>(
+        &'a self,
+        next_id: &mut PaneId,
+        panes: &mut Vec<(PaneId, &'a PaneSpec)>,
+    ) -> Layout {
+        match self {
+            LayoutSpec::Pane(spec) => {
+                let id = *next_id;
+                *next_id += 1;
+                panes.push((id, spec));
+                Layout::Pane(id)
+            }
+            LayoutSpec::Split { dir, first, second } => Layout::Split {
+                dir: *dir,
+                first: Box::new(first.to_layout(next_id, panes)),
+                second: Box::new(second.to_layout(next_id, panes)),
+            },
+        }
+    }
+}
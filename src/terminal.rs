@@ -1,33 +1,238 @@
 // src/terminal.rs
 
+use crate::history::{History, HistoryLimits};
+use crate::palette::{Palette, Rgb};
+use crate::reflow::Snapshot;
+use crossterm::cursor::SetCursorStyle;
 use vt100::Parser;
 
-const SCROLLBACK_LEN: usize = 2000; // number of lines of history
+pub const SCROLLBACK_LEN: usize = 2000; // rows of history kept in memory
+
+/// Output is parsed in pieces that can scroll at most this many rows (see
+/// `piece_len`); the parser keeps that much room on top of `SCROLLBACK_LEN`,
+/// so nothing falls off before we spill it.
+const FEED_ROOM: usize = 4096;
+
+/// Rows moved to the history file at a time, so we don't rebuild the
+/// parser for every line.
+const SPILL_BATCH: usize = 500;
+
+/// Escape sequences vt100 leaves to us.
+#[derive(Default)]
+struct Callbacks {
+    /// Working directory last reported by the shell, if it reports one.
+    cwd: Option<String>,
+    /// A BEL arrived since `take_bell` was last called.
+    bell: bool,
+    /// Window title set with OSC 0 / OSC 2.
+    title: String,
+    title_changed: bool,
+    /// Cursor shape asked for with DECSCUSR (`CSI Ps SP q`); 0 is the
+    /// host's own.
+    cursor_style: u16,
+    /// Colors the pane is drawn in, as the child left them.
+    palette: Palette,
+    /// And as myux set them, for OSC 104 / 110 / 111 / 112.
+    base: Palette,
+    /// Answers to the child's queries, for its input.
+    replies: Vec<u8>,
+}
+
+impl vt100::Callbacks for Callbacks {
+    fn audible_bell(&mut self, _: &mut vt100::Screen) {
+        self.bell = true;
+    }
+
+    fn set_window_title(&mut self, _: &mut vt100::Screen, title: &[u8]) {
+        let title = String::from_utf8_lossy(title);
+        if title != self.title {
+            self.title = title.into_owned();
+            self.title_changed = true;
+        }
+    }
+
+    fn unhandled_csi(
+        &mut self,
+        screen: &mut vt100::Screen,
+        i1: Option<u8>,
+        _: Option<u8>,
+        params: &[&[u16]],
+        c: char,
+    ) {
+        let param = params.first().and_then(|p| p.first()).copied().unwrap_or(0);
+        let reply = match (i1, c, param) {
+            (Some(b' '), 'q', 0..=6) => {
+                self.cursor_style = param;
+                return;
+            }
+            // Primary device attributes: a VT220 with ANSI colors.
+            (None, 'c', 0) => "\x1b[?62;22c".to_string(),
+            // Secondary device attributes.
+            (Some(b'>'), 'c', 0) => "\x1b[>1;10;0c".to_string(),
+            // XTVERSION.
+            (Some(b'>'), 'q', 0) => format!("\x1bP>|myux {}\x1b\\", env!("CARGO_PKG_VERSION")),
+            // Device status, and where the cursor is.
+            (None, 'n', 5) => "\x1b[0n".to_string(),
+            (None | Some(b'?'), 'n', 6) => {
+                let (row, col) = screen.cursor_position();
+                let private = if i1.is_some() { "?" } else { "" };
+                format!("\x1b[{private}{};{}R", row + 1, col + 1)
+            }
+            _ => return,
+        };
+        self.replies.extend_from_slice(reply.as_bytes());
+    }
+
+    fn unhandled_osc(&mut self, _: &mut vt100::Screen, params: &[&[u8]]) {
+        match params {
+            // OSC 7 ; file://host/path
+            [b"7", url] => {
+                if let Some(path) = path_from_file_url(&String::from_utf8_lossy(url)) {
+                    self.cwd = Some(path);
+                }
+            }
+            // OSC 9 ; 9 ; "path" (ConEmu / Windows Terminal, e.g. from a
+            // cmd.exe PROMPT of `$e]9;9;$P$e\$P$G`)
+            [b"9", b"9", path] => {
+                let path = String::from_utf8_lossy(path);
+                self.cwd = Some(path.trim_matches('"').to_string());
+            }
+            // OSC 4 ; index ; color [; index ; color ...]: ANSI colors.
+            [b"4", pairs @ ..] => {
+                for pair in pairs.chunks_exact(2) {
+                    let Some(index) = ansi_index(pair[0]) else {
+                        continue;
+                    };
+                    let color = &mut self.palette.colors[index];
+                    if !set_or_report(color, pair[1]) {
+                        let reply = format!("\x1b]4;{index};{}\x1b\\", color.x11());
+                        self.replies.extend_from_slice(reply.as_bytes());
+                    }
+                }
+            }
+            // OSC 10 / 11 / 12 ; color [; color ...]: default foreground,
+            // background and the cursor, each further color going to the
+            // next of them. `?` for a color asks for it.
+            [which @ (b"10" | b"11" | b"12"), specs @ ..] => {
+                let first = which[1] - b'0';
+                for (n, spec) in (first..3).zip(specs) {
+                    self.dynamic_color(10 + n, spec);
+                }
+            }
+            // OSC 104 [; index ...], 110, 111 and 112 put them back.
+            [b"104"] => self.palette.colors = self.base.colors,
+            [b"104", indexes @ ..] => {
+                for index in indexes.iter().filter_map(|i| ansi_index(i)) {
+                    self.palette.colors[index] = self.base.colors[index];
+                }
+            }
+            [b"110"] => self.palette.foreground = self.base.foreground,
+            [b"111"] => self.palette.background = self.base.background,
+            [b"112"] => self.palette.cursor = self.base.cursor,
+            _ => {}
+        }
+    }
+}
+
+impl Callbacks {
+    /// OSC 10, 11 or 12 (`which`) for one color.
+    fn dynamic_color(&mut self, which: u8, spec: &[u8]) {
+        let palette = &mut self.palette;
+        let mut cursor = palette.cursor.unwrap_or(palette.foreground);
+        let color = match which {
+            10 => &mut palette.foreground,
+            11 => &mut palette.background,
+            _ => &mut cursor,
+        };
+        if set_or_report(color, spec) {
+            if which == 12 {
+                palette.cursor = Some(cursor);
+            }
+        } else {
+            let reply = format!("\x1b]{which};{}\x1b\\", color.x11());
+            self.replies.extend_from_slice(reply.as_bytes());
+        }
+    }
+}
+
+/// `0` to `15`.
+fn ansi_index(text: &[u8]) -> Option<usize> {
+    std::str::from_utf8(text)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|&i| i < 16)
+}
+
+/// Set `color` from an OSC color `spec`. False if the spec is `?`, which
+/// asks for the color instead.
+fn set_or_report(color: &mut Rgb, spec: &[u8]) -> bool {
+    if spec == b"?" {
+        return false;
+    }
+    if let Some(rgb) = Rgb::parse(&String::from_utf8_lossy(spec)) {
+        *color = rgb;
+    }
+    true
+}
+
+/// `file://host/C:/dir%20name` → `C:/dir name`.
+fn path_from_file_url(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+
+    // `/C:/x` is a drive path; keep the leading slash otherwise.
+    match path.as_bytes() {
+        [b'/', _, b':', ..] => Some(path[1..].to_string()),
+        _ => Some(path),
+    }
+}
 
 /// A virtual terminal backed by vt100.
-/// - `rows` / `cols` are the *physical* console size.
-/// - We reserve the last physical row for the status bar.
-/// - The vt100 screen height is therefore `rows - 1`.
+/// - `cols` / `rows` are the size of the pane the child draws into; the
+///   status bar and pane borders are the renderer's business.
+/// - The newest `SCROLLBACK_LEN` rows of history live in the parser; older
+///   ones are spilled to `history` and scrolled through seamlessly.
 pub struct VirtualTerminal {
-    parser: Parser,
+    parser: Parser<Callbacks>,
     cols: u16,
-    rows: u16,      // physical rows (incl. status bar)
-    term_rows: u16, // rows dedicated to the child terminal (rows - 1)
+    rows: u16,
+    history: History,
+    /// Rows scrolled back from the live view, history file included.
+    scroll: usize,
+    /// History file rows at the top of the view when scrolled back past
+    /// what the parser holds.
+    disk_view: Vec<Vec<vt100::Cell>>,
 }
 
 impl VirtualTerminal {
-    pub fn new(cols: u16, rows: u16) -> Self {
-        // At least 1 row for the child.
-        let term_rows = rows.saturating_sub(1).max(1);
+    pub fn new(cols: u16, rows: u16, history: History) -> Self {
+        // At least 1 row/col for the child.
+        let cols = cols.max(1);
+        let rows = rows.max(1);
 
         // vt100 takes: height, width, scrollback_len.
-        let parser = Parser::new(term_rows as u16, cols as u16, SCROLLBACK_LEN);
+        let parser = new_parser(rows, cols, Callbacks::default());
 
         Self {
             parser,
             cols,
             rows,
-            term_rows,
+            history,
+            scroll: 0,
+            disk_view: Vec::new(),
         }
     }
 
@@ -36,23 +241,156 @@ impl VirtualTerminal {
         self.parser.screen().cursor_position()
     }
 
-    /// Physical console size (what the renderer cares about).
+    /// Draw the pane in `palette`, forgetting the child's own changes.
+    pub fn set_palette(&mut self, palette: Palette) {
+        let callbacks = self.parser.callbacks_mut();
+        callbacks.palette = palette.clone();
+        callbacks.base = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.parser.callbacks().palette
+    }
+
+    /// Answers to the queries in the output fed so far (device attributes,
+    /// status and cursor reports, XTVERSION, colors), for the child's input.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.parser.callbacks_mut().replies)
+    }
+
+    /// Whether the child hid the cursor (DECTCEM).
+    pub fn cursor_hidden(&self) -> bool {
+        self.parser.screen().hide_cursor()
+    }
+
+    /// Whether the child wants pastes bracketed (DECSET 2004).
+    pub fn bracketed_paste(&self) -> bool {
+        self.parser.screen().bracketed_paste()
+    }
+
+    /// Cursor shape and blink the child asked for.
+    pub fn cursor_style(&self) -> SetCursorStyle {
+        match self.parser.callbacks().cursor_style {
+            1 => SetCursorStyle::BlinkingBlock,
+            2 => SetCursorStyle::SteadyBlock,
+            3 => SetCursorStyle::BlinkingUnderScore,
+            4 => SetCursorStyle::SteadyUnderScore,
+            5 => SetCursorStyle::BlinkingBar,
+            6 => SetCursorStyle::SteadyBar,
+            _ => SetCursorStyle::DefaultUserShape,
+        }
+    }
+
+    /// Pane size (what the renderer cares about).
     pub fn size(&self) -> (u16, u16) {
         (self.cols, self.rows)
     }
 
-    /// Called when the host console is resized.
+    /// Called when the pane is resized.
+    ///
+    /// Soft-wrapped lines in the scrollback and on the primary screen are
+    /// re-wrapped at the new width. The alternate screen is only resized:
+    /// full-screen programs repaint it themselves, and the primary screen
+    /// behind it isn't reachable through vt100. Rows already in the history
+    /// file keep the width they were written at. Either way the view jumps
+    /// back to the bottom.
     pub fn resize(&mut self, cols: u16, rows: u16) {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+        if (cols, rows) == (self.cols, self.rows) {
+            return;
+        }
+
         self.cols = cols;
         self.rows = rows;
+        self.reset_scrollback();
 
-        let term_rows = rows.saturating_sub(1).max(1);
-        self.term_rows = term_rows;
+        // The replay needs room to scroll history off the screen.
+        if self.parser.screen().alternate_screen() || rows < 2 {
+            self.parser.screen_mut().set_size(rows, cols);
+            return;
+        }
+
+        self.rebuild(SCROLLBACK_LEN);
+    }
+
+    /// Re-create the screen at the current size from a snapshot of the old
+    /// one, first moving all but `keep` rows of history to the history file.
+    /// The parser itself stays, so a sequence or character cut in half by
+    /// the end of the last output still finishes on the new screen.
+    fn rebuild(&mut self, keep: usize) {
+        let mut snapshot = Snapshot::capture(self.parser.screen_mut());
+        let excess = snapshot.history_rows(self.cols).saturating_sub(keep);
+        if excess > 0 {
+            let spilled = snapshot.take_history_rows(excess, self.cols);
+            self.history.push(spilled, self.cols);
+        }
+
+        let mut fresh = new_parser(self.rows, self.cols, ());
+        snapshot.replay(&mut fresh);
+        std::mem::swap(self.parser.screen_mut(), fresh.screen_mut());
+    }
 
-        // Resize the vt100 screen.
-        self.parser
-            .screen_mut()
-            .set_size(term_rows as u16, cols as u16);
+    /// Make room in the parser's history before the next piece of output.
+    fn spill_if_full(&mut self) {
+        // The alternate screen has no history, and the replay needs at
+        // least two rows.
+        if self.parser.screen().alternate_screen() || self.rows < 2 {
+            return;
+        }
+        if self.memory_history() > SCROLLBACK_LEN {
+            self.rebuild(SCROLLBACK_LEN - SPILL_BATCH);
+        }
+    }
+
+    /// Whether the child rang the bell since the last call.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.parser.callbacks_mut().bell)
+    }
+
+    pub fn title(&self) -> &str {
+        &self.parser.callbacks().title
+    }
+
+    /// Whether the title changed since the last call.
+    pub fn take_title_change(&mut self) -> bool {
+        std::mem::take(&mut self.parser.callbacks_mut().title_changed)
+    }
+
+    /// Working directory reported by the child through OSC 7 or OSC 9;9.
+    pub fn cwd(&self) -> Option<&str> {
+        self.parser.callbacks().cwd.as_deref()
+    }
+
+    /// History file, scrollback and primary screen as text with SGR
+    /// attributes, one line per `\r\n`, for saving alongside a session.
+    pub fn history_formatted(&mut self) -> Vec<u8> {
+        if self.parser.screen().alternate_screen() {
+            return Vec::new();
+        }
+        let mut buf = Vec::new();
+        for idx in 0..self.history.len() {
+            if let Some((row, _)) = self.history.row(idx) {
+                buf.extend_from_slice(row);
+                buf.extend_from_slice(b"\x1b[m\r\n");
+            }
+        }
+        buf.extend(Snapshot::capture(self.parser.screen_mut()).contents_formatted());
+        self.reset_scrollback();
+        buf
+    }
+
+    /// Load saved history (see `history_formatted`) into the scrollback,
+    /// leaving a blank screen for the child to draw on.
+    pub fn replay_history(&mut self, history: &[u8]) {
+        if history.is_empty() {
+            return;
+        }
+        self.feed_bytes(history);
+        // `rows` newlines push everything that is on screen into the
+        // scrollback, wherever the history ended.
+        self.feed_bytes(&b"\r\n".repeat(self.rows as usize));
+        self.feed_bytes(b"\x1b[m\x1b[H");
     }
 
     /// Feed raw bytes from ConPTY into the VT parser.
@@ -61,51 +399,367 @@ impl VirtualTerminal {
             return;
         }
 
-        // If we're at the live view, we keep following the bottom.
-        if self.is_at_bottom() {
-            self.reset_scrollback();
+        let before = (self.scroll > 0).then(|| self.history_len());
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            self.spill_if_full();
+            let (piece, after) = rest.split_at(piece_len(rest, self.rows));
+            self.parser.process(piece);
+            rest = after;
         }
 
-        self.parser.process(bytes);
+        // Scrolled back: keep showing the same rows while history grows.
+        // At the live view we keep following the bottom.
+        if let Some(before) = before {
+            let grown = self.history_len().saturating_sub(before);
+            self.set_scroll(self.scroll + grown);
+        }
     }
 
     // ---------- Scrollback control ----------
 
-    /// Current scrollback offset (0 = bottom/live).
-    fn current_scrollback(&self) -> usize {
-        self.parser.screen().scrollback()
+    /// Rows of history held by the parser.
+    fn memory_history(&mut self) -> usize {
+        let screen = self.parser.screen_mut();
+        let current = screen.scrollback();
+        screen.set_scrollback(usize::MAX);
+        let len = screen.scrollback();
+        screen.set_scrollback(current);
+        len
+    }
+
+    /// Rows of history in the history file; none while the alternate
+    /// screen is up, since they belong to the primary screen.
+    fn disk_history(&self) -> usize {
+        if self.parser.screen().alternate_screen() {
+            0
+        } else {
+            self.history.len()
+        }
+    }
+
+    fn history_len(&mut self) -> usize {
+        self.memory_history() + self.disk_history()
+    }
+
+    /// Rows above the live view we are scrolled to.
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scroll to `offset` rows above the live view. Past what the parser
+    /// holds, the top of the view comes from the history file.
+    pub fn set_scroll(&mut self, offset: usize) {
+        let memory = self.memory_history();
+        let disk = self.disk_history();
+        let offset = offset.min(memory + disk);
+
+        self.scroll = offset;
+        self.parser.screen_mut().set_scrollback(offset.min(memory));
+        self.disk_view.clear();
+
+        if offset > memory {
+            let above = offset - memory;
+            let first = disk - above;
+            for idx in first..first + above.min(self.rows as usize) {
+                let row = self.history.row(idx).map(|(text, cols)| decode_row(text, cols));
+                self.disk_view.push(row.unwrap_or_default());
+            }
+        }
     }
 
     /// Scroll "up" into history by the given number of rows
     /// (toward older content).
     pub fn scroll_up(&mut self, lines: u16) {
-        let cur = self.current_scrollback();
-        let new = cur.saturating_add(lines as usize);
-        self.parser.screen_mut().set_scrollback(new);
+        self.set_scroll(self.scroll.saturating_add(lines as usize));
     }
 
     /// Scroll "down" toward the live view.
     pub fn scroll_down(&mut self, lines: u16) {
-        let cur = self.current_scrollback();
-        let new = cur.saturating_sub(lines as usize);
-        self.parser.screen_mut().set_scrollback(new);
+        self.set_scroll(self.scroll.saturating_sub(lines as usize));
+    }
+
+    pub fn set_history_limits(&mut self, limits: HistoryLimits) {
+        self.history.set_limits(limits);
     }
 
     /// Jump back to the live view (bottom).
     pub fn reset_scrollback(&mut self) {
-        self.parser.screen_mut().set_scrollback(0);
+        self.set_scroll(0);
+    }
+
+    /// Text of the live screen, one line per row.
+    pub fn screen_text(&self) -> String {
+        self.parser.screen().contents()
+    }
+
+    /// Cells of the live screen, whatever is being looked at.
+    pub fn live_cells(&mut self) -> Vec<Vec<vt100::Cell>> {
+        let screen = self.parser.screen_mut();
+        screen.set_scrollback(0);
+        let cells = (0..self.rows)
+            .map(|row| {
+                (0..self.cols)
+                    .filter_map(|col| screen.cell(row, col).cloned())
+                    .collect()
+            })
+            .collect();
+        self.set_scroll(self.scroll);
+        cells
+    }
+
+    /// Plain text of every history row, oldest first: the history file,
+    /// then the parser's scrollback.
+    pub fn history_text(&mut self) -> Vec<String> {
+        let memory = self.memory_history();
+        let disk = self.disk_history();
+        let mut lines = Vec::with_capacity(memory + disk);
+        for idx in 0..disk {
+            let text = self.history.row(idx).map(|(text, _)| strip_sgr(text));
+            lines.push(text.unwrap_or_default());
+        }
+        for above in (1..=memory).rev() {
+            let screen = self.parser.screen_mut();
+            screen.set_scrollback(above);
+            lines.push(screen.rows(0, self.cols).next().unwrap_or_default());
+        }
+        self.set_scroll(self.scroll);
+        lines
     }
 
     /// Are we currently looking at the live view?
     pub fn is_at_bottom(&self) -> bool {
-        self.current_scrollback() == 0
+        self.scroll == 0
+    }
+
+    /// Scroll so that the closest history row above the top of the view
+    /// that contains `needle` becomes the top row. Returns false (and leaves
+    /// the view alone) if there is none.
+    pub fn search_back(&mut self, needle: &str) -> bool {
+        if needle.is_empty() {
+            return false;
+        }
+        let memory = self.memory_history();
+        let disk = self.disk_history();
+        let total = memory + disk;
+
+        // Rows are numbered oldest first: the history file, then the
+        // parser's scrollback.
+        let mut found = None;
+        for idx in (0..total - self.scroll).rev() {
+            let text = if idx < disk {
+                self.history.row(idx).map(|(text, _)| strip_sgr(text))
+            } else {
+                let screen = self.parser.screen_mut();
+                screen.set_scrollback(total - idx);
+                screen.rows(0, self.cols).next()
+            };
+            if text.is_some_and(|t| t.contains(needle)) {
+                found = Some(idx);
+                break;
+            }
+        }
+
+        match found {
+            Some(idx) => self.set_scroll(total - idx),
+            None => self.set_scroll(self.scroll),
+        }
+        found.is_some()
     }
 
     // ---------- Rendering ----------
-    pub fn render_lines(&self) -> Vec<String> {
-        self.parser.screen().contents()
-            .split('\n')
-            .map(|s| s.to_string())
+
+    /// Cell at `(row, col)` of the visible view (scrollback offset applied).
+    /// Wide characters occupy their cell plus a continuation cell; combining
+    /// marks are already attached to the cell contents by vt100.
+    pub fn cell(&self, row: u16, col: u16) -> Option<&vt100::Cell> {
+        match self.disk_view.get(row as usize) {
+            Some(cells) => cells.get(col as usize),
+            None => self
+                .parser
+                .screen()
+                .cell(row - self.disk_view.len() as u16, col),
+        }
+    }
+}
+
+fn new_parser<CB: vt100::Callbacks>(rows: u16, cols: u16, callbacks: CB) -> Parser<CB> {
+    let room = FEED_ROOM.max(rows as usize);
+    Parser::new_with_callbacks(rows, cols, SCROLLBACK_LEN + room, callbacks)
+}
+
+/// How much of `bytes` to parse before looking at the history again: as
+/// much as can't scroll more than `FEED_ROOM` rows, but at least a byte. A
+/// byte scrolls at most one row, as a line feed or by wrapping; only an
+/// escape sequence (`CSI Ps S`) can scroll up to the whole screen.
+fn piece_len(bytes: &[u8], rows: u16) -> usize {
+    let mut room = FEED_ROOM;
+    for (i, &b) in bytes.iter().enumerate() {
+        let rows_scrolled = if b == 0x1b { rows as usize } else { 1 };
+        if rows_scrolled > room && i > 0 {
+            return i;
+        }
+        room = room.saturating_sub(rows_scrolled);
+    }
+    bytes.len()
+}
+
+/// Cells of a history file row written at width `cols`.
+fn decode_row(text: &[u8], cols: u16) -> Vec<vt100::Cell> {
+    let cols = cols.max(1);
+    let mut parser = Parser::new(2, cols, 0);
+    parser.process(text);
+    (0..cols)
+        .filter_map(|col| parser.screen().cell(0, col).cloned())
+        .collect()
+}
+
+/// Plain text of a history file row (which only contains SGR sequences).
+fn strip_sgr(text: &[u8]) -> String {
+    let mut out = Vec::with_capacity(text.len());
+    let mut iter = text.iter();
+    while let Some(&b) = iter.next() {
+        if b == 0x1b {
+            // ESC [ params final
+            iter.by_ref().skip(1).find(|&&c| (0x40..=0x7e).contains(&c));
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(name: &str, cols: u16, rows: u16) -> VirtualTerminal {
+        let limits = HistoryLimits {
+            max_lines: 1_000_000,
+            max_bytes: 64 << 20,
+        };
+        VirtualTerminal::new(cols, rows, History::new(name, limits))
+    }
+
+    fn lines(from: usize, to: usize) -> Vec<u8> {
+        (from..to)
+            .flat_map(|i| format!("line {i}\r\n").into_bytes())
             .collect()
     }
+
+    /// Physical rows of the screen.
+    fn rows(t: &VirtualTerminal) -> Vec<String> {
+        t.parser.screen().rows(0, t.cols).collect()
+    }
+
+    /// Enough lines and then `tail` in one feed, so the next feed starts
+    /// with a spill.
+    fn fill(t: &mut VirtualTerminal, tail: &[u8]) {
+        let mut bytes = lines(0, SCROLLBACK_LEN + 100);
+        bytes.extend_from_slice(tail);
+        t.feed_bytes(&bytes);
+        assert!(t.memory_history() > SCROLLBACK_LEN);
+    }
+
+    #[test]
+    fn history_moves_to_disk_in_order() {
+        let mut t = terminal("test-spill-order", 40, 10);
+        t.feed_bytes(&lines(0, 3 * SCROLLBACK_LEN));
+        assert!(t.history.len() >= SPILL_BATCH);
+        assert!(t.memory_history() <= SCROLLBACK_LEN + FEED_ROOM);
+
+        let history = t.history_text();
+        let expected: Vec<String> = (0..history.len()).map(|i| format!("line {i}")).collect();
+        assert_eq!(history, expected);
+        assert_eq!(
+            t.screen_text().lines().next(),
+            Some(&*format!("line {}", history.len()))
+        );
+
+        t.set_scroll(history.len());
+        let top = t.cell(0, 0).map(|c| c.contents().to_string());
+        assert_eq!(top.as_deref(), Some("l"));
+        t.reset_scrollback();
+    }
+
+    #[test]
+    fn sequences_and_characters_straddling_a_spill_survive() {
+        let mut t = terminal("test-spill-straddle", 40, 10);
+        fill(&mut t, b"\x1b[3");
+        t.feed_bytes(b"1mred\x1b[m");
+        let spilled = t.history.len();
+        assert!(spilled > 0);
+        let (row, _) = t.cursor_pos();
+        assert_eq!(t.cell(row, 0).unwrap().contents(), "r");
+        assert_eq!(t.cell(row, 0).unwrap().fgcolor(), vt100::Color::Idx(1));
+
+        fill(&mut t, b"\xc3");
+        t.feed_bytes(b"\xa9");
+        assert!(t.history.len() > spilled);
+        let (row, col) = t.cursor_pos();
+        assert_eq!(col, 1);
+        assert_eq!(t.cell(row, 0).unwrap().contents(), "é");
+    }
+
+    #[test]
+    fn scroll_region_origin_mode_and_saved_cursor_survive_a_spill() {
+        let mut t = terminal("test-spill-state", 40, 24);
+        // Saved at row 5, column 10; region rows 3 to 20; origin mode on,
+        // cursor at its row 2, column 4.
+        fill(
+            &mut t,
+            b"\x1b[5;10H\x1b[1m\x1b7\x1b[m\x1b[3;20r\x1b[?6h\x1b[2;4H\x1b[?25l",
+        );
+        let spilled = t.history.len();
+        t.feed_bytes(b"X");
+        assert!(t.history.len() > spilled);
+
+        assert_eq!(t.cursor_pos(), (3, 4));
+        assert_eq!(t.cell(3, 3).unwrap().contents(), "X");
+        assert!(t.cursor_hidden());
+        t.feed_bytes(b"\x1b[H");
+        assert_eq!(t.cursor_pos(), (2, 0));
+        t.feed_bytes(b"\x1b[99H");
+        assert_eq!(t.cursor_pos(), (19, 0));
+        t.feed_bytes(b"\x1b8Y");
+        assert_eq!(t.cursor_pos(), (4, 10));
+        assert!(t.cell(4, 9).unwrap().bold());
+        t.feed_bytes(b"\x1b[H");
+        assert_eq!(t.cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn scrolling_sequences_cannot_outrun_the_spill() {
+        let mut t = terminal("test-spill-su", 40, 50);
+        // Every `CSI 50 S` pushes the whole screen into the history.
+        let mut bytes = Vec::new();
+        for i in 0..200 {
+            bytes.extend_from_slice(format!("\x1b[Hpage {i}\x1b[50S").as_bytes());
+        }
+        t.feed_bytes(&bytes);
+        let history = t.history_text();
+        assert_eq!(history.len(), 200 * 50);
+        let pages: Vec<&String> = history.iter().filter(|l| !l.is_empty()).collect();
+        let expected: Vec<String> = (0..200).map(|i| format!("page {i}")).collect();
+        assert_eq!(pages, expected.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wrapped_lines_reflow_when_the_width_changes() {
+        let mut t = terminal("test-reflow", 80, 10);
+        let long = format!("{}{}", "a".repeat(60), "b".repeat(40));
+        t.feed_bytes(format!("{long}\r\nshort\r\n$ ").as_bytes());
+        assert_eq!(t.cursor_pos(), (3, 2));
+
+        t.resize(40, 10);
+        assert_eq!(
+            rows(&t)[..5],
+            [&long[..40], &long[40..80], &long[80..], "short", "$ "]
+        );
+        assert_eq!(t.cursor_pos(), (4, 2));
+
+        t.resize(120, 10);
+        assert_eq!(rows(&t)[..3], [long.as_str(), "short", "$ "]);
+        assert_eq!(t.cursor_pos(), (2, 2));
+    }
 }
@@ -0,0 +1,875 @@
+// src/testing.rs
+//
+// myux without a console or child processes, for tests. `FakeSpawner`
+// starts `FakePty`s that play a `Script` instead of running a program and
+// keep whatever is typed into them; `Host` stands in for the console, a
+// vt100 screen fed with what `Renderer` draws; `Harness` puts an `App`
+// between the two and drives it with crossterm events the way the main
+// loop does.
+
+use crate::app::{App, PaneEvent, SHELL};
+use crate::clients::{Access, LOCAL};
+use crate::layout::PaneId;
+use crate::palette::ColorDepth;
+use crate::pty::{Output, Pty, SpawnOptions, Spawner};
+use crate::renderer::Renderer;
+use crate::wake::{self, Wake};
+use crate::{execute, render, status_line};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, mpsc};
+
+enum Step {
+    Output(Vec<u8>),
+    /// Wait until this has been typed.
+    Expect(Vec<u8>),
+    Exit(u32),
+}
+
+/// What a fake child does, step by step.
+#[derive(Default)]
+pub struct Script {
+    steps: VecDeque<Step>,
+    /// Print whatever is typed, like a shell's line editor.
+    echo: bool,
+    /// Don't even start.
+    fail: bool,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Script::default()
+    }
+
+    pub fn output(mut self, text: &str) -> Self {
+        self.steps.push_back(Step::Output(text.as_bytes().to_vec()));
+        self
+    }
+
+    /// Go on once `text` has been typed (after what earlier steps
+    /// waited for).
+    pub fn expect(mut self, text: &str) -> Self {
+        self.steps.push_back(Step::Expect(text.as_bytes().to_vec()));
+        self
+    }
+
+    pub fn exit(mut self, status: u32) -> Self {
+        self.steps.push_back(Step::Exit(status));
+        self
+    }
+
+    pub fn echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    pub fn fail(mut self) -> Self {
+        self.fail = true;
+        self
+    }
+}
+
+struct Child {
+    options: SpawnOptions,
+    size: (u16, u16),
+    /// Everything typed, pipe-pane's included.
+    input: Arc<Mutex<Vec<u8>>>,
+    /// How much of `input` the script's `expect`s have used up.
+    matched: usize,
+    script: Script,
+    output: Output,
+    killed: bool,
+    exited: bool,
+}
+
+impl Child {
+    /// Play the script up to the first `expect` that isn't met yet.
+    fn run(&mut self) {
+        while let Some(step) = self.script.steps.pop_front() {
+            match step {
+                Step::Output(bytes) => {
+                    self.output.send(&bytes);
+                }
+                Step::Expect(text) => {
+                    let input = self.input.lock().unwrap();
+                    let found = input[self.matched..]
+                        .windows(text.len())
+                        .position(|w| w == text.as_slice());
+                    drop(input);
+                    let Some(at) = found else {
+                        self.script.steps.push_front(Step::Expect(text));
+                        return;
+                    };
+                    self.matched += at + text.len();
+                }
+                Step::Exit(status) => self.exit(status),
+            }
+        }
+    }
+
+    fn exit(&mut self, status: u32) {
+        if !self.exited {
+            self.exited = true;
+            self.script.steps.clear();
+            self.output.exited(status);
+        }
+    }
+}
+
+type Children = Rc<RefCell<Vec<Child>>>;
+
+/// Starts fake children: the script given for their command line, or one
+/// that echoes.
+#[derive(Clone, Default)]
+pub struct FakeSpawner {
+    children: Children,
+    scripts: Rc<RefCell<Vec<(String, Script)>>>,
+}
+
+impl FakeSpawner {
+    pub fn new() -> Self {
+        FakeSpawner::default()
+    }
+
+    /// The next child started with `cmdline` plays `script`.
+    pub fn script(&self, cmdline: &str, script: Script) {
+        self.scripts
+            .borrow_mut()
+            .push((cmdline.to_string(), script));
+    }
+
+    fn with_child<T>(&self, id: PaneId, f: impl FnOnce(&mut Child) -> T) -> T {
+        let mut children = self.children.borrow_mut();
+        let child = children
+            .iter_mut()
+            .find(|c| c.output.id == id)
+            .unwrap_or_else(|| panic!("no child for pane {id}"));
+        f(child)
+    }
+}
+
+impl Spawner for FakeSpawner {
+    fn spawn(
+        &self,
+        options: &SpawnOptions,
+        cols: u16,
+        rows: u16,
+        output: Output,
+    ) -> io::Result<Box<dyn Pty>> {
+        let cmdline = options.cmdline();
+        let mut scripts = self.scripts.borrow_mut();
+        let script = match scripts.iter().position(|(c, _)| *c == cmdline) {
+            Some(i) => scripts.remove(i).1,
+            None => Script::new().echo(),
+        };
+        if script.fail {
+            return Err(io::Error::new(io::ErrorKind::NotFound, cmdline));
+        }
+        let id = output.id;
+        let mut child = Child {
+            options: options.clone(),
+            size: (cols, rows),
+            input: Arc::default(),
+            matched: 0,
+            script,
+            output,
+            killed: false,
+            exited: false,
+        };
+        child.run();
+        self.children.borrow_mut().push(child);
+        Ok(Box::new(FakePty {
+            id,
+            spawner: self.clone(),
+        }))
+    }
+}
+
+pub struct FakePty {
+    id: PaneId,
+    spawner: FakeSpawner,
+}
+
+impl Pty for FakePty {
+    fn write(&self, bytes: &[u8]) {
+        self.spawner.with_child(self.id, |child| {
+            if child.exited {
+                return;
+            }
+            child.input.lock().unwrap().extend_from_slice(bytes);
+            if child.script.echo {
+                child.output.send(bytes);
+            }
+            child.run();
+        });
+    }
+
+    fn resize(&self, cols: u16, rows: u16) {
+        self.spawner
+            .with_child(self.id, |child| child.size = (cols, rows));
+    }
+
+    fn kill(&self) {
+        self.spawner.with_child(self.id, |child| {
+            child.killed = true;
+            child.exit(0);
+        });
+    }
+
+    fn input(&self) -> io::Result<Box<dyn Write + Send>> {
+        let input = self
+            .spawner
+            .with_child(self.id, |child| Arc::clone(&child.input));
+        Ok(Box::new(SharedInput(input)))
+    }
+}
+
+struct SharedInput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedInput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The console myux draws on: a vt100 screen in memory, so tests don't
+/// need a console of their own.
+pub struct Host {
+    parser: vt100::Parser,
+}
+
+impl Host {
+    fn new((cols, rows): (u16, u16)) -> Self {
+        Host {
+            parser: vt100::Parser::new(rows, cols, 0),
+        }
+    }
+
+    /// Row `row` as text, without trailing blanks.
+    pub fn row(&self, row: u16) -> String {
+        let screen = self.parser.screen();
+        let (_, cols) = screen.size();
+        let text = screen.rows(0, cols).nth(row as usize).unwrap_or_default();
+        text.trim_end().to_string()
+    }
+
+    pub fn rows(&self) -> Vec<String> {
+        let (rows, _) = self.parser.screen().size();
+        (0..rows).map(|r| self.row(r)).collect()
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        self.rows().iter().any(|r| r.contains(text))
+    }
+
+    pub fn cell(&self, row: u16, col: u16) -> Option<&vt100::Cell> {
+        self.parser.screen().cell(row, col)
+    }
+
+    /// (col, row), or None while it's hidden.
+    pub fn cursor(&self) -> Option<(u16, u16)> {
+        let screen = self.parser.screen();
+        let (row, col) = screen.cursor_position();
+        (!screen.hide_cursor()).then_some((col, row))
+    }
+}
+
+/// An `App` with fake children, drawing on a `Host`.
+pub struct Harness {
+    pub app: App,
+    pub host: Host,
+    pub spawner: FakeSpawner,
+    rx: mpsc::Receiver<Wake>,
+    renderer: Renderer,
+}
+
+impl Harness {
+    /// One tab running the shell, which echoes.
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Harness::with(FakeSpawner::new(), cols, rows)
+    }
+
+    /// Like `new`, with scripts already given to `spawner`.
+    pub fn with(spawner: FakeSpawner, cols: u16, rows: u16) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let tx = wake::Sender::new(&tx, Wake::Pane);
+        let mut app = App::new((cols, rows), Box::new(spawner.clone()), tx);
+        // The host takes any color.
+        app.color_depth = ColorDepth::TrueColor;
+        app.new_tab(SHELL, None);
+        crate::run_queued(&mut app).expect("fake children start unless told not to");
+        app.clients.attach(LOCAL, app.size, Access::Full, None);
+        let mut harness = Harness {
+            host: Host::new(app.size),
+            app,
+            spawner,
+            rx,
+            renderer: Renderer::new(),
+        };
+        harness.settle();
+        harness
+    }
+
+    /// What the main loop does between input and the next frame: hand the
+    /// children's output and exits to the app, run hooks, then draw.
+    pub fn settle(&mut self) {
+        // What a test did to the app itself.
+        if let Err(err) = crate::run_queued(&mut self.app) {
+            self.app.message = Some(err.to_string());
+        }
+        while let Ok(wake) = self.rx.try_recv() {
+            match wake {
+                Wake::Pane(PaneEvent::Output(id)) => {
+                    self.app.pane_output(id);
+                }
+                Wake::Pane(PaneEvent::Exited(id, status)) => self.app.pane_exited(id, status),
+                _ => {}
+            }
+        }
+        self.app.run_hooks();
+        if let Err(err) = crate::run_queued(&mut self.app) {
+            self.app.message = Some(err.to_string());
+        }
+        self.renderer.set_ambiguous_width(self.app.ambiguous);
+        self.renderer.set_color_depth(self.app.color_depth);
+        let frame = render(&self.app, &mut self.renderer, status_line(&self.app));
+        self.host.parser.process(&frame);
+    }
+
+    /// An event from the local console.
+    pub fn event(&mut self, event: Event) {
+        match event {
+            Event::Key(_) | Event::Mouse(_) | Event::Paste(_) => self.app.clients.touch(LOCAL),
+            Event::Resize(cols, rows) => self.host.parser.screen_mut().set_size(rows, cols),
+            _ => {}
+        }
+        // Quitting is left to the test.
+        let actions = self.app.handle_event(event);
+        let _ = execute(&mut self.app, LOCAL, actions);
+        self.settle();
+    }
+
+    pub fn key(&mut self, code: KeyCode) {
+        self.event(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+    }
+
+    pub fn ctrl(&mut self, c: char) {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        self.event(Event::Key(key));
+    }
+
+    /// Ctrl+B, then `code`.
+    pub fn prefix(&mut self, code: KeyCode) {
+        self.ctrl('b');
+        self.key(code);
+    }
+
+    /// Each character as a key press.
+    pub fn paste(&mut self, text: &str) {
+        self.event(Event::Paste(text.into()));
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            let code = match c {
+                '\r' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            };
+            self.key(code);
+        }
+    }
+
+    pub fn mouse(&mut self, kind: MouseEventKind, column: u16, row: u16) {
+        self.event(Event::Mouse(MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }));
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.event(Event::Resize(cols, rows));
+    }
+
+    /// Pane `id`'s child prints `text`, outside of its script.
+    pub fn print(&mut self, id: PaneId, text: &str) {
+        self.spawner
+            .with_child(id, |child| child.output.send(text.as_bytes()));
+        self.settle();
+    }
+
+    /// Pane `id`'s child exits.
+    pub fn exit(&mut self, id: PaneId, status: u32) {
+        self.spawner.with_child(id, |child| child.exit(status));
+        self.settle();
+    }
+
+    /// Everything typed into pane `id`, as text.
+    pub fn typed(&self, id: PaneId) -> String {
+        let input = self
+            .spawner
+            .with_child(id, |child| child.input.lock().unwrap().clone());
+        String::from_utf8_lossy(&input).into_owned()
+    }
+
+    /// The size pane `id`'s child was last given.
+    pub fn child_size(&self, id: PaneId) -> (u16, u16) {
+        self.spawner.with_child(id, |child| child.size)
+    }
+
+    pub fn killed(&self, id: PaneId) -> bool {
+        self.spawner.with_child(id, |child| child.killed)
+    }
+
+    /// Run a myux command, as from the command prompt.
+    pub fn run(&mut self, line: &str) -> Result<String, String> {
+        let result = crate::command::run_line(&mut self.app, line, None)
+            .and_then(|msg| crate::started(&mut self.app, msg));
+        self.settle();
+        result
+    }
+
+    pub fn command(&self, id: PaneId) -> String {
+        self.spawner.with_child(id, |child| child.options.cmdline())
+    }
+
+    /// What pane `id`'s child was given for `name` on top of myux's own
+    /// environment: None if nothing, Some(None) if it was removed.
+    pub fn child_env(&self, id: PaneId, name: &str) -> Option<Option<String>> {
+        self.spawner.with_child(id, |child| {
+            let env = &child.options.env;
+            env.iter()
+                .rev()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        })
+    }
+
+    pub fn focus(&self) -> PaneId {
+        self.app.active_tab().focus
+    }
+
+    /// The status bar.
+    pub fn status(&self) -> String {
+        self.host.row(self.app.size.1 - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pty::PENDING_LIMIT;
+    use crossterm::cursor::SetCursorStyle;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn typing_reaches_the_shell_and_its_echo_the_screen() {
+        let mut h = Harness::new(80, 24);
+        h.type_text("dir");
+        assert_eq!(h.host.row(0), "dir");
+        assert_eq!(h.host.cursor(), Some((3, 0)));
+        h.key(KeyCode::Enter);
+        assert_eq!(h.typed(0), "dir\r");
+    }
+
+    #[test]
+    fn scripted_output_is_drawn() {
+        let spawner = FakeSpawner::new();
+        spawner.script(
+            SHELL,
+            Script::new()
+                .output("C:\\> ")
+                .expect("ver\r")
+                .output("\r\nMicrosoft Windows\r\nC:\\> "),
+        );
+        let mut h = Harness::with(spawner, 80, 24);
+        assert_eq!(h.host.row(0), "C:\\>");
+
+        h.type_text("ver\r");
+        assert_eq!(h.host.row(1), "Microsoft Windows");
+        assert_eq!(h.host.cursor(), Some((5, 2)));
+    }
+
+    #[test]
+    fn status_bar_is_on_the_last_row() {
+        let h = Harness::new(80, 24);
+        assert!(h.status().starts_with("[myux] tab 1/1"), "{}", h.status());
+    }
+
+    #[test]
+    fn prefix_keys_are_not_typed() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('z'));
+        h.type_text("a");
+        assert_eq!(h.typed(0), "a");
+    }
+
+    #[test]
+    fn split_sizes_children_and_moves_input() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        assert_eq!(h.focus(), 1);
+        assert_eq!(h.command(1), SHELL);
+        assert_eq!(h.child_size(0), (39, 23));
+        assert_eq!(h.child_size(1), (40, 23));
+
+        h.type_text("x");
+        assert_eq!(h.typed(0), "");
+        assert_eq!(h.typed(1), "x");
+        assert_eq!(h.host.cell(0, 40).map(|c| c.contents()), Some("x"));
+    }
+
+    #[test]
+    fn wide_characters_dont_spill_out_of_a_split_pane() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.run("set ambiguous-width wide").unwrap();
+        h.print(0, &format!("{}\u{3b1}\u{3b1}", "a".repeat(37)));
+        assert_eq!(h.host.cell(0, 37).map(|c| c.contents()), Some("\u{3b1}"));
+        assert_eq!(h.host.cell(0, 38).map(|c| c.contents()), Some(" "));
+        assert_eq!(h.host.cell(0, 39).map(|c| c.contents()), Some("|"));
+    }
+
+    #[test]
+    fn synchronized_input_goes_to_every_pane() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.prefix(KeyCode::Char('s'));
+        h.type_text("ls\r");
+        assert_eq!(h.typed(0), "ls\r");
+        assert_eq!(h.typed(1), "ls\r");
+        assert!(h.status().contains("SYNC 2/2"), "{}", h.status());
+    }
+
+    #[test]
+    fn pastes_are_bracketed_for_panes_that_ask() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.prefix(KeyCode::Char('s'));
+        h.print(1, "\x1b[?2004h");
+        h.paste("echo hi\r");
+        assert_eq!(h.typed(0), "echo hi\r");
+        assert_eq!(h.typed(1), "\x1b[200~echo hi\r\x1b[201~");
+    }
+
+    #[test]
+    fn kill_pane_ends_its_child() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.prefix(KeyCode::Char('x'));
+        assert!(h.killed(1));
+        assert!(!h.killed(0));
+        assert_eq!(h.app.active_tab().panes.len(), 1);
+        assert_eq!(h.child_size(0), (80, 23));
+    }
+
+    #[test]
+    fn resize_reaches_children_and_host() {
+        let mut h = Harness::new(80, 24);
+        h.resize(100, 30);
+        assert_eq!(h.child_size(0), (100, 29));
+        assert!(h.host.row(29).starts_with("[myux]"), "{}", h.host.row(29));
+    }
+
+    #[test]
+    fn read_only_clients_dont_size_the_session() {
+        let mut h = Harness::new(80, 24);
+        h.run("set size-policy smallest").unwrap();
+        h.app.clients.attach(1, (40, 10), Access::ReadOnly, None);
+        h.app.clients.resize(1, (30, 8));
+        h.app.apply_size_policy();
+        assert_eq!(h.child_size(0), (80, 23));
+
+        h.run("client-access 1 full").unwrap();
+        assert_eq!(h.child_size(0), (30, 7));
+    }
+
+    #[test]
+    fn a_session_that_cant_start_leaves_nothing_running() {
+        let mut h = Harness::new(80, 24);
+        h.run("new-tab nope.exe").unwrap();
+        let session = h.app.session(false);
+        h.spawner.script("nope.exe", Script::new().fail());
+        h.app.open_session(&session);
+        assert!(crate::run_queued(&mut h.app).is_err());
+        assert_eq!(h.app.tabs.len(), 2);
+        assert!(h.killed(2));
+    }
+
+    #[test]
+    fn exit_keeps_the_last_output() {
+        let spawner = FakeSpawner::new();
+        spawner.script(SHELL, Script::new().output("bye").exit(3));
+        let h = Harness::with(spawner, 80, 24);
+        assert_eq!(h.host.row(0), "bye");
+        assert_eq!(h.app.tabs.len(), 1);
+    }
+
+    #[test]
+    fn scrollback_keeps_keys_from_the_child() {
+        let mut h = Harness::new(80, 24);
+        for i in 0..40 {
+            h.print(0, &format!("line {i}\r\n"));
+        }
+        h.key(KeyCode::PageUp);
+        h.type_text("q");
+        assert_eq!(h.typed(0), "");
+        assert!(h.status().contains("mode: scroll"), "{}", h.status());
+        assert!(!h.host.contains("line 39"));
+    }
+
+    #[test]
+    fn scrollback_counts_the_rows_kept_in_memory() {
+        let mut h = Harness::new(80, 24);
+        h.run("set scrollback 5000").unwrap();
+        assert_eq!(h.app.history_limits.max_lines, 3000);
+        assert!(h.run("set scrollback 100").is_err());
+        assert_eq!(h.app.history_limits.max_lines, 3000);
+    }
+
+    #[test]
+    fn wheel_scrolls_back_and_down_again() {
+        let mut h = Harness::new(80, 24);
+        for i in 0..40 {
+            h.print(0, &format!("line {i}\r\n"));
+        }
+        h.mouse(MouseEventKind::ScrollUp, 10, 10);
+        assert!(!h.host.contains("line 39"));
+        h.mouse(MouseEventKind::ScrollDown, 10, 10);
+        assert!(h.host.contains("line 39"));
+        assert!(h.status().contains("mode: normal"), "{}", h.status());
+    }
+
+    #[test]
+    fn popup_takes_the_keyboard_until_it_exits() {
+        let mut h = Harness::new(80, 24);
+        h.app.open_popup("cmd.exe /c dir", None, 80, 80, false);
+        h.settle();
+        h.type_text("y");
+        assert_eq!(h.typed(1), "y");
+        assert_eq!(h.typed(0), "");
+
+        h.exit(1, 0);
+        assert!(h.app.popup.is_none());
+        h.type_text("z");
+        assert_eq!(h.typed(0), "z");
+    }
+
+    #[test]
+    fn cursor_follows_the_child_hiding_it() {
+        let mut h = Harness::new(80, 24);
+        h.print(0, "ab\x1b[?25l");
+        assert_eq!(h.host.cursor(), None);
+        h.print(0, "\x1b[?25h");
+        assert_eq!(h.host.cursor(), Some((2, 0)));
+    }
+
+    #[test]
+    fn cursor_hides_while_scrolled_back() {
+        let mut h = Harness::new(80, 24);
+        for i in 0..40 {
+            h.print(0, &format!("line {i}\r\n"));
+        }
+        h.mouse(MouseEventKind::ScrollUp, 10, 10);
+        assert_eq!(h.host.cursor(), None);
+        h.mouse(MouseEventKind::ScrollDown, 10, 10);
+        assert_eq!(h.host.cursor(), Some((0, 22)));
+    }
+
+    #[test]
+    fn cursor_shape_is_kept_per_pane() {
+        let mut h = Harness::new(80, 24);
+        h.print(0, "\x1b[6 q");
+        h.prefix(KeyCode::Char('%'));
+        let style = |h: &Harness, id| h.app.pane(id).unwrap().term.cursor_style();
+        assert!(matches!(style(&h, 0), SetCursorStyle::SteadyBar));
+        assert!(matches!(style(&h, 1), SetCursorStyle::DefaultUserShape));
+        h.print(0, "\x1b[ q");
+        assert!(matches!(style(&h, 0), SetCursorStyle::DefaultUserShape));
+    }
+
+    #[test]
+    fn queries_are_answered_on_the_childs_input() {
+        let mut h = Harness::new(80, 24);
+        h.print(0, "\x1b[c");
+        assert_eq!(h.typed(0), "\x1b[?62;22c");
+        h.print(0, "ab\x1b[6n");
+        assert!(h.typed(0).ends_with("\x1b[1;3R"), "{:?}", h.typed(0));
+        h.print(0, "\x1b[5n\x1b[>q");
+        let version = format!("\x1b[0n\x1bP>|myux {}\x1b\\", env!("CARGO_PKG_VERSION"));
+        assert!(h.typed(0).ends_with(&version), "{:?}", h.typed(0));
+    }
+
+    #[test]
+    fn color_queries_get_the_palette() {
+        let mut h = Harness::new(80, 24);
+        h.print(0, "\x1b]11;?\x07");
+        assert_eq!(h.typed(0), "\x1b]11;rgb:0c0c/0c0c/0c0c\x1b\\");
+        h.print(0, "\x1b]10;?;?\x07");
+        assert!(
+            h.typed(0)
+                .ends_with("\x1b]10;rgb:cccc/cccc/cccc\x1b\\\x1b]11;rgb:0c0c/0c0c/0c0c\x1b\\"),
+            "{:?}",
+            h.typed(0)
+        );
+    }
+
+    fn fg(h: &Harness, row: u16, col: u16) -> vt100::Color {
+        h.host.cell(row, col).unwrap().fgcolor()
+    }
+
+    fn bg(h: &Harness, row: u16, col: u16) -> vt100::Color {
+        h.host.cell(row, col).unwrap().bgcolor()
+    }
+
+    #[test]
+    fn cells_are_drawn_through_the_palette() {
+        let mut h = Harness::new(80, 24);
+        h.print(0, "\x1b[31mr\x1b[m \x1b[38;5;200mx \x1b[2md");
+        assert_eq!(fg(&h, 0, 0), vt100::Color::Rgb(0xc5, 0x0f, 0x1f));
+        assert!(h.host.cell(0, 4).unwrap().dim());
+        assert_eq!(bg(&h, 0, 0), vt100::Color::Rgb(0x0c, 0x0c, 0x0c));
+        assert_eq!(fg(&h, 0, 2), vt100::Color::Idx(200));
+
+        h.print(0, "\x1b]4;1;#102030\x07\x1b]11;rgb:ff/ff/ff\x07");
+        assert_eq!(fg(&h, 0, 0), vt100::Color::Rgb(0x10, 0x20, 0x30));
+        assert_eq!(bg(&h, 5, 5), vt100::Color::Rgb(0xff, 0xff, 0xff));
+        h.print(0, "\x1b]104\x07\x1b]111\x07");
+        assert_eq!(fg(&h, 0, 0), vt100::Color::Rgb(0xc5, 0x0f, 0x1f));
+        assert_eq!(bg(&h, 5, 5), vt100::Color::Rgb(0x0c, 0x0c, 0x0c));
+    }
+
+    #[test]
+    fn themes_switch_live_for_the_session_or_a_pane() {
+        let mut h = Harness::new(80, 24);
+        h.prefix(KeyCode::Char('%'));
+        h.run("theme -p vintage").unwrap();
+        assert_eq!(bg(&h, 0, 50), vt100::Color::Rgb(0, 0, 0));
+        assert_eq!(bg(&h, 0, 5), vt100::Color::Rgb(0x0c, 0x0c, 0x0c));
+
+        h.run("theme solarized-light").unwrap();
+        assert_eq!(bg(&h, 0, 5), vt100::Color::Rgb(0xfd, 0xf6, 0xe3));
+        assert_eq!(bg(&h, 0, 50), vt100::Color::Rgb(0, 0, 0));
+        h.run("theme -u -t 1").unwrap();
+        assert_eq!(bg(&h, 0, 50), vt100::Color::Rgb(0xfd, 0xf6, 0xe3));
+
+        h.run("set-color background #123456").unwrap();
+        assert_eq!(bg(&h, 0, 50), vt100::Color::Rgb(0x12, 0x34, 0x56));
+        assert!(h.run("theme nope").is_err());
+    }
+
+    #[test]
+    fn colors_come_down_to_the_host_color_depth() {
+        let mut h = Harness::new(80, 24);
+        h.run("set color-depth 256").unwrap();
+        h.print(0, "\x1b[38;2;255;0;0mr\x1b[38;5;67mi\x1b[32mg");
+        assert_eq!(fg(&h, 0, 0), vt100::Color::Idx(196));
+        assert_eq!(fg(&h, 0, 1), vt100::Color::Idx(67));
+        // The host's own first 16 and defaults, not the theme's.
+        assert_eq!(fg(&h, 0, 2), vt100::Color::Idx(2));
+        assert_eq!(bg(&h, 0, 0), vt100::Color::Default);
+
+        h.run("set color-depth 16").unwrap();
+        assert_eq!(fg(&h, 0, 0), vt100::Color::Idx(9));
+        assert_eq!(fg(&h, 0, 1), vt100::Color::Idx(6));
+        assert_eq!(fg(&h, 0, 2), vt100::Color::Idx(2));
+        assert_eq!(bg(&h, 0, 0), vt100::Color::Default);
+    }
+
+    #[test]
+    fn scripts_run_commands_but_not_other_scripts() {
+        let mut h = Harness::new(80, 24);
+        let path = std::env::temp_dir().join(format!("myux-test-{}.rhai", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+                fn title(name) { rename_tab(name); tabs()[0].name }
+                fn again() { myux("call title x") }
+            "#,
+        )
+        .unwrap();
+        let loaded = crate::script::load_file(&mut h.app, &path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+
+        assert_eq!(h.run(r#"call title 'a"b'"#).unwrap(), r#"a"b"#);
+        let err = h.run("call again").unwrap_err();
+        assert!(err.contains("scripts can't be run from a script"), "{err}");
+        assert_eq!(h.run("call title y").unwrap(), "y");
+    }
+
+    #[test]
+    fn children_know_they_run_in_myux() {
+        let mut h = Harness::new(80, 24);
+        assert_eq!(h.child_env(0, "MYUX_PANE"), Some(Some("0".into())));
+        assert_eq!(h.child_env(0, "TERM"), Some(Some("xterm-256color".into())));
+        assert!(matches!(h.child_env(0, "MYUX"), Some(Some(v)) if v.contains(r"\\.\pipe\myux-")));
+
+        h.run("set-environment EDITOR vim").unwrap();
+        h.run("set-environment -u COLORTERM").unwrap();
+        h.run("set login-shell on").unwrap();
+        assert_eq!(h.run("set-environment").unwrap(), "EDITOR=vim\n-COLORTERM");
+        h.prefix(KeyCode::Char('%'));
+        assert_eq!(h.child_env(1, "EDITOR"), Some(Some("vim".into())));
+        assert_eq!(h.child_env(1, "COLORTERM"), Some(None));
+        assert_eq!(h.child_env(1, "MYUX_PANE"), Some(Some("1".into())));
+        assert_eq!(h.child_env(0, "EDITOR"), None);
+        assert!(h.run("set-environment A=B c").is_err());
+
+        assert_eq!(h.command(1), SHELL);
+        h.run("split bash.exe -i").unwrap();
+        assert_eq!(h.command(2), "bash.exe -l -i");
+    }
+
+    /// Output read faster than it is drawn: batches of up to PENDING_LIMIT
+    /// handed over by the reader and fed in one go, a frame after each.
+    /// Run with
+    /// `cargo test --release throughput -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn throughput() {
+        const TOTAL: usize = 64 << 20;
+        let line = b"\x1b[32mok\x1b[m 0123456789 abcdefghijklmnopqrstuvwxyz ABCDEFGHIJKLMNOPQRSTUVWXYZ\r\n";
+        let chunk: Vec<u8> = line.iter().copied().cycle().take(8192).collect();
+        let mut h = Harness::new(120, 40);
+        let (mut fed, mut drawn, mut frames) = (0, 0, 0);
+        let (mut feeding, mut drawing) = (Duration::ZERO, Duration::ZERO);
+
+        while fed < TOTAL {
+            let start = Instant::now();
+            for _ in 0..PENDING_LIMIT / chunk.len() {
+                h.spawner.with_child(0, |child| child.output.send(&chunk));
+            }
+            fed += h.app.pane_output(0).len();
+            feeding += start.elapsed();
+
+            let start = Instant::now();
+            let frame = render(&h.app, &mut h.renderer, status_line(&h.app));
+            drawing += start.elapsed();
+            drawn += frame.len();
+            frames += 1;
+        }
+
+        let mb =
+            |bytes: usize, time: Duration| bytes as f64 / (1 << 20) as f64 / time.as_secs_f64();
+        println!("feed:   {:.1} MB/s", mb(fed, feeding));
+        println!(
+            "render: {:.1} MB/s of output, {:.0} frames/s ({:.1} MB/s of frames)",
+            mb(fed, drawing),
+            frames as f64 / drawing.as_secs_f64(),
+            mb(drawn, drawing)
+        );
+        h.settle();
+        assert!(h.host.contains("ok 0123456789"));
+    }
+}
@@ -0,0 +1,214 @@
+// src/viewer.rs
+//
+// What read-only clients can do. They look back through the focused pane's
+// history on their own (PageUp/PageDown or the mouse wheel, `/` to search,
+// `n` to search again, Esc to return, or the copy-mode binding) and run
+// the commands that only tell them something; anything else is turned away
+// with a message in their own status bar.
+
+use crate::clients::{ClientId, Viewport};
+use crate::command::{self, Key};
+use crate::layout::PaneId;
+use crate::app::{App, SCROLL_STEP};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind};
+use unicode_width::UnicodeWidthStr;
+
+const NO_INPUT: &str = "read-only: input is disabled (PageUp: look back, F10: detach)";
+
+pub fn key(app: &mut App, id: ClientId, key: KeyEvent) {
+    if key.kind == KeyEventKind::Release {
+        return;
+    }
+    let Some(client) = app.clients.get_mut(id) else {
+        return;
+    };
+    client.message = None;
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+    if std::mem::take(&mut client.prefix) {
+        let binding = Key {
+            code: key.code,
+            ctrl,
+        };
+        if let Some(words) = app.bindings.get(&binding).cloned() {
+            run_command(app, id, &words);
+        }
+        return;
+    }
+
+    if let Some(view) = client.view.as_mut()
+        && let Some(query) = view.search.as_mut()
+    {
+        match key.code {
+            KeyCode::Enter => {
+                let query = std::mem::take(query);
+                view.search = None;
+                search(app, id, query);
+            }
+            KeyCode::Esc => view.search = None,
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) => query.push(c),
+            _ => {}
+        }
+        return;
+    }
+
+    let Some(view) = client.view.as_mut() else {
+        match key.code {
+            KeyCode::Char('b') if ctrl => client.prefix = true,
+            KeyCode::PageUp => scroll(app, id, 5),
+            _ => client.message = Some(NO_INPUT.into()),
+        }
+        return;
+    };
+    // Like scrollback mode, other keys do nothing.
+    match key.code {
+        KeyCode::Char('b') if ctrl => client.prefix = true,
+        KeyCode::PageUp => scroll(app, id, 5),
+        KeyCode::PageDown => scroll(app, id, -5),
+        KeyCode::Esc => client.view = None,
+        KeyCode::Char('/') => view.search = Some(String::new()),
+        KeyCode::Char('n') => {
+            if let Some(query) = client.last_search.clone() {
+                search(app, id, query);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn mouse(app: &mut App, id: ClientId, mouse: MouseEvent) {
+    match mouse.kind {
+        MouseEventKind::ScrollUp => scroll(app, id, SCROLL_STEP as isize),
+        MouseEventKind::ScrollDown => scroll(app, id, -(SCROLL_STEP as isize)),
+        _ => {}
+    }
+}
+
+/// Status bar of a client with something of its own to show.
+pub fn status(client: &crate::clients::Client) -> Option<(String, Option<u16>)> {
+    if let Some(query) = client.view.as_ref().and_then(|v| v.search.as_ref()) {
+        let line = format!("search /{query}");
+        let cursor = line.width() as u16;
+        return Some((line, Some(cursor)));
+    }
+    if let Some(message) = &client.message {
+        return Some((message.clone(), None));
+    }
+    let view = client.view.as_ref()?;
+    Some((
+        format!(
+            "[myux] read-only | {} row(s) back | PgUp/PgDn, /: search, n: again, Esc: back",
+            view.offset
+        ),
+        None,
+    ))
+}
+
+/// Run `draw` with the pane client `id` is looking back through scrolled to
+/// where the client has it. A viewport whose pane is gone is dropped.
+pub fn render(app: &mut App, id: ClientId, draw: impl FnOnce(&App) -> Vec<u8>) -> Vec<u8> {
+    let view = app.clients.get(id).and_then(|c| c.view.clone());
+    let Some(view) = view.filter(|v| app.pane(v.pane).is_some()) else {
+        if let Some(client) = app.clients.get_mut(id) {
+            client.view = None;
+        }
+        return draw(app);
+    };
+    at_offset(app, view.pane, view.offset, |app| draw(app)).unwrap_or_default()
+}
+
+/// Run `f` with pane `pane` scrolled to `offset`, then put it back where
+/// it was. None if the pane is gone.
+fn at_offset<T>(
+    app: &mut App,
+    pane: PaneId,
+    offset: usize,
+    f: impl FnOnce(&mut App) -> T,
+) -> Option<T> {
+    let term = &mut app.pane_mut(pane)?.term;
+    let saved = term.scroll_offset();
+    term.set_scroll(offset);
+    let result = f(app);
+    if let Some(p) = app.pane_mut(pane) {
+        p.term.set_scroll(saved);
+    }
+    Some(result)
+}
+
+/// Move client `id`'s viewport `lines` rows back (or forward, when
+/// negative), starting one on the focused pane if there is none. Coming
+/// forward to the live view ends it.
+fn scroll(app: &mut App, id: ClientId, lines: isize) {
+    let focus = app.active_tab().focus;
+    let Some(client) = app.clients.get_mut(id) else {
+        return;
+    };
+    if client.view.is_none() && lines <= 0 {
+        return;
+    }
+    let mut view = client.view.take().unwrap_or(Viewport {
+        pane: focus,
+        offset: 0,
+        search: None,
+    });
+    let wanted = view.offset.saturating_add_signed(lines);
+    let Some(offset) = at_offset(app, view.pane, wanted, |app| {
+        app.pane(view.pane).map_or(0, |p| p.term.scroll_offset())
+    }) else {
+        return;
+    };
+    if offset > 0 || lines > 0 {
+        view.offset = offset;
+        if let Some(client) = app.clients.get_mut(id) {
+            client.view = Some(view);
+        }
+    }
+}
+
+fn search(app: &mut App, id: ClientId, query: String) {
+    let Some(client) = app.clients.get_mut(id) else {
+        return;
+    };
+    client.last_search = Some(query.clone());
+    let Some(view) = client.view.clone() else {
+        return;
+    };
+    let found = at_offset(app, view.pane, view.offset, |app| {
+        let term = &mut app.pane_mut(view.pane)?.term;
+        term.search_back(&query).then(|| term.scroll_offset())
+    });
+    let Some(client) = app.clients.get_mut(id) else {
+        return;
+    };
+    match (found.flatten(), client.view.as_mut()) {
+        (Some(offset), Some(view)) => view.offset = offset,
+        _ => client.message = Some(format!("not found: {query}")),
+    }
+}
+
+/// A command from the client's prefix key: its own scrollback for
+/// copy-mode, commands that only report something, nothing else.
+fn run_command(app: &mut App, id: ClientId, words: &[String]) {
+    let name = words.first().map_or("", String::as_str);
+    let message = if name == "copy-mode" {
+        let focus = app.active_tab().focus;
+        if let Some(client) = app.clients.get_mut(id) {
+            client.view.get_or_insert(Viewport {
+                pane: focus,
+                offset: 0,
+                search: None,
+            });
+        }
+        None
+    } else if command::READ_ONLY_COMMANDS.contains(&name) {
+        Some(command::run(app, words, None).unwrap_or_else(|e| e))
+    } else {
+        Some(format!("read-only: {name} is not allowed"))
+    };
+    if let Some(client) = app.clients.get_mut(id) {
+        client.message = message.filter(|m| !m.is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+// src/wake.rs
+//
+// Everything the main loop waits for comes in on one channel: pane output
+// and exits, console input, requests from `myux <command>`, JSON-RPC calls,
+// attached clients and control-mode lines. The loop sleeps in `recv` until
+// one of them (or a timer of its own) is due, so nothing waits for a poll.
+
+use crate::app::PaneEvent;
+use crate::{attach, ipc, rpc};
+use crossterm::event;
+use std::sync::mpsc;
+use std::thread;
+
+pub enum Wake {
+    Pane(PaneEvent),
+    Input(event::Event),
+    Request(ipc::Request),
+    Rpc(rpc::Message),
+    Attach(attach::Message),
+    /// A line from the control-mode front-end; None once it is gone.
+    Line(Option<String>),
+}
+
+/// Sends one kind of message into the loop's channel, so the code that
+/// produces it needn't know about the others.
+pub struct Sender<T> {
+    tx: mpsc::Sender<Wake>,
+    wrap: fn(T) -> Wake,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            tx: self.tx.clone(),
+            wrap: self.wrap,
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn new(tx: &mpsc::Sender<Wake>, wrap: fn(T) -> Wake) -> Self {
+        Sender {
+            tx: tx.clone(),
+            wrap,
+        }
+    }
+
+    /// Fails once the loop is gone.
+    pub fn send(&self, message: T) -> Result<(), mpsc::SendError<Wake>> {
+        self.tx.send((self.wrap)(message))
+    }
+}
+
+/// Read console input on a thread of its own.
+pub fn read_input(tx: Sender<event::Event>) {
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    });
+}